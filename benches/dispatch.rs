@@ -0,0 +1,64 @@
+// Compares the bytecode dispatch with the decoded one on a few guest
+// programs. Run with `cargo bench --bench dispatch`.
+
+use std::time::{Duration, Instant};
+
+use rsvm::decode::Dispatch;
+
+mod workloads;
+
+fn run(bytecode: &[u8], dispatch: Dispatch) -> Duration {
+    let mut vm = workloads::load(bytecode);
+    vm.set_dispatch(dispatch);
+
+    let start = Instant::now();
+    let status = vm.run_program();
+    let elapsed = start.elapsed();
+
+    if let Err(fault) = status {
+        panic!("benchmark faulted: {}", fault);
+    }
+
+    elapsed
+}
+
+/// The best of a few runs, as instructions per second.
+fn measure(bytecode: &[u8], dispatch: Dispatch) -> f64 {
+    let instructions = workloads::instructions(bytecode) as f64;
+
+    (0..5)
+        .map(|_| instructions / run(bytecode, dispatch).as_secs_f64())
+        .fold(0.0, f64::max)
+}
+
+fn main() {
+    let benches = workloads::all();
+    let dispatches = [
+        Dispatch::Bytecode,
+        Dispatch::Decoded,
+        #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+        Dispatch::Compiled,
+    ];
+
+    println!(
+        "{:<20} {:<10} {:>16} {:>8}",
+        "", "dispatch", "instrs/s", "speedup"
+    );
+    for (name, bytecode) in &benches {
+        // Bytecode dispatch comes first, and everything is compared to it.
+        let mut baseline = None;
+
+        for &dispatch in &dispatches {
+            let ips = measure(bytecode, dispatch);
+            let baseline = *baseline.get_or_insert(ips);
+
+            println!(
+                "{:<20} {:<10} {:>16.0} {:>7.2}x",
+                name,
+                format!("{:?}", dispatch),
+                ips,
+                ips / baseline
+            );
+        }
+    }
+}
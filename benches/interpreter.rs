@@ -0,0 +1,62 @@
+// Times `run_program` on guest workloads meant to stand for what programs
+// spend their time on, in instructions per second. Run with
+// `cargo bench --bench interpreter`, followed by part of a workload's name to
+// only run the ones that match.
+
+use std::env;
+use std::time::{Duration, Instant};
+
+mod workloads;
+
+/// How many timed runs each workload gets, after one to warm up.
+const RUNS: usize = 10;
+
+fn run(bytecode: &[u8]) -> Duration {
+    let mut vm = workloads::load(bytecode);
+
+    let start = Instant::now();
+    let status = vm.run_program();
+    let elapsed = start.elapsed();
+
+    if let Err(fault) = status {
+        panic!("benchmark faulted: {}", fault);
+    }
+
+    elapsed
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` along, which is no filter.
+    let filters = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect::<Vec<_>>();
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>16} {:>16}",
+        "", "instrs", "median", "median instrs/s", "best instrs/s"
+    );
+    for (name, bytecode) in workloads::all() {
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+
+        let instructions = workloads::instructions(&bytecode) as f64;
+
+        run(&bytecode);
+        let mut times = (0..RUNS).map(|_| run(&bytecode)).collect::<Vec<_>>();
+        times.sort();
+
+        let median = times[RUNS / 2];
+        let best = times[0];
+
+        println!(
+            "{:<20} {:>12} {:>10.2}ms {:>16.0} {:>16.0}",
+            name,
+            instructions,
+            median.as_secs_f64() * 1000.0,
+            instructions / median.as_secs_f64(),
+            instructions / best.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+// The guest programs the benchmarks run, shared between them.
+
+use std::io;
+
+use rsvm::VM;
+
+const A: u8 = 0;
+const B: u8 = 1;
+const C: u8 = 2;
+const D: u8 = 3;
+
+/// The words the heap copying starts from, taken from the header.
+const BLOCK_LEN: u32 = 1024;
+
+const WRITE_STDOUT: u32 = 0x00;
+const PRINT_NUMBER: u32 = 0x03;
+
+const MESSAGE: &[u8] = b"Hello, world!\n";
+
+/// Just enough of an assembler to write the programs below.
+#[derive(Default)]
+struct Asm {
+    data: Vec<u8>,
+    code: Vec<u8>,
+}
+
+impl Asm {
+    fn data(&mut self, data: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(data);
+        self
+    }
+
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    fn op(&mut self, opcode: u8, operands: &[u8]) -> &mut Self {
+        self.code.push(opcode);
+        self.code.extend_from_slice(operands);
+        self
+    }
+
+    fn lit(&mut self, lit: u32) -> &mut Self {
+        self.code.extend_from_slice(&lit.to_be_bytes());
+        self
+    }
+
+    fn op_lit(&mut self, opcode: u8, regs: &[u8], lit: u32) -> &mut Self {
+        self.op(opcode, regs).lit(lit)
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let mut bytecode = self.data.clone();
+        bytecode.extend_from_slice(&[0x1d; 4]);
+        bytecode.extend_from_slice(&self.code);
+        bytecode
+    }
+}
+
+/// Counts A up to `n`.
+fn counting_loop(n: u32) -> Vec<u8> {
+    let mut asm = Asm::default();
+
+    asm.op_lit(0x06, &[A], 0);
+    let top = asm.here();
+    asm.op(0x50, &[A])
+        .op_lit(0x31, &[A], n)
+        .op_lit(0x34, &[], top)
+        .op(0x00, &[]);
+
+    asm.finish()
+}
+
+/// Sums 1 to `n` on the stack.
+fn stack_arithmetic(n: u32) -> Vec<u8> {
+    let mut asm = Asm::default();
+
+    asm.op_lit(0x06, &[B], n).op_lit(0x01, &[], 0);
+    let top = asm.here();
+    asm.op(0x02, &[B])
+        .op(0x11, &[])
+        .op(0x51, &[B])
+        .op_lit(0x31, &[B], 0)
+        .op_lit(0x34, &[], top)
+        .op(0x03, &[A])
+        .op(0x00, &[]);
+
+    asm.finish()
+}
+
+/// Copies the block of words the header puts at the start of the heap to
+/// right after it through pointers, `rounds` times.
+fn heap_copying(rounds: u32) -> Vec<u8> {
+    let block = (0..BLOCK_LEN).map(|i| i as u8).collect::<Vec<_>>();
+    let mut asm = Asm::default();
+
+    asm.data(&block).op_lit(0x06, &[D], 0);
+    let outer = asm.here();
+    asm.op_lit(0x06, &[A], 0).op_lit(0x06, &[B], BLOCK_LEN);
+    let inner = asm.here();
+    asm.op(0x0D, &[C, A])
+        .op(0x0E, &[B, C])
+        .op(0x50, &[A])
+        .op(0x50, &[B])
+        .op_lit(0x31, &[A], BLOCK_LEN)
+        .op_lit(0x34, &[], inner)
+        .op(0x50, &[D])
+        .op_lit(0x31, &[D], rounds)
+        .op_lit(0x34, &[], outer)
+        .op(0x00, &[]);
+
+    asm.finish()
+}
+
+/// Writes a line from the heap and prints a number, `n` times.
+fn syscall_output(n: u32) -> Vec<u8> {
+    let mut asm = Asm::default();
+
+    asm.data(MESSAGE).op_lit(0x06, &[D], 0);
+    let top = asm.here();
+    asm.op_lit(0x06, &[A], WRITE_STDOUT)
+        .op_lit(0x06, &[B], 0)
+        .op_lit(0x06, &[C], MESSAGE.len() as u32)
+        .op(0xFF, &[])
+        .op_lit(0x06, &[A], PRINT_NUMBER)
+        .op(0x0A, &[B, D])
+        .op(0xFF, &[])
+        .op(0x50, &[D])
+        .op_lit(0x31, &[D], n)
+        .op_lit(0x34, &[], top)
+        .op(0x00, &[]);
+
+    asm.finish()
+}
+
+/// Every workload, by name, sized to run for a fraction of a second.
+pub fn all() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("counting loop", counting_loop(1_000_000)),
+        ("stack arithmetic", stack_arithmetic(500_000)),
+        ("heap copying", heap_copying(500)),
+        ("syscall output", syscall_output(200_000)),
+    ]
+}
+
+/// A `VM` with the program loaded, writing whatever it prints nowhere.
+pub fn load(bytecode: &[u8]) -> VM {
+    let mut vm = VM::new();
+    vm.load_program(bytecode.to_vec());
+    vm.set_output(io::sink());
+    vm
+}
+
+/// How many instructions the program runs, counted by stepping through it.
+pub fn instructions(bytecode: &[u8]) -> u64 {
+    let mut vm = load(bytecode);
+
+    let mut steps = 0;
+    while !vm.is_stopped() {
+        vm.step();
+        steps += 1;
+    }
+
+    steps
+}
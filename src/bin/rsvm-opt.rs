@@ -0,0 +1,134 @@
+use std::{env, fmt, fs, io, process};
+
+use colored::Colorize;
+use rsvm::opt::{Mismatch, Unsupported};
+
+enum CliError {
+    NoFileProvided,
+    FailedToOpenFile,
+    MissingOptionValue(&'static str),
+    FailedToWriteOutput,
+    Mismatch(Mismatch),
+}
+
+impl fmt::Debug for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::NoFileProvided => {
+                write!(
+                    f,
+                    "{}{} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Please provide a valid file.".cyan()
+                )
+            }
+            CliError::FailedToOpenFile => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to open file!".cyan(),
+                    "Please make sure the file exists and can be read.".white()
+                )
+            }
+            CliError::MissingOptionValue(option) => {
+                write!(
+                    f,
+                    "{}{} {} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Missing value for option".cyan(),
+                    option.white()
+                )
+            }
+            CliError::FailedToWriteOutput => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to write the optimized program!".cyan(),
+                    "Please make sure the file can be created.".white()
+                )
+            }
+            CliError::Mismatch(mismatch) => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "The optimized program behaves differently!".cyan(),
+                    format!("{:?}", mismatch).white()
+                )
+            }
+        }
+    }
+}
+
+fn warn(reason: Unsupported) {
+    let reason = match reason {
+        Unsupported::Undecodable => "Some of the code does not decode.",
+        Unsupported::Interrupts => "The program takes interrupts.",
+    };
+
+    eprintln!(
+        "{}{} {}\n    {}",
+        "[WARNING]".bright_yellow(),
+        ":".bright_white(),
+        "Left the program as it is.".cyan(),
+        reason.white()
+    );
+}
+
+fn try_main() -> Result<(), CliError> {
+    let mut args = env::args().skip(1);
+    let mut output = None;
+    let mut filename = None;
+    let mut verify = false;
+    let mut guest_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or(CliError::MissingOptionValue("-o"))?),
+            "--verify" => verify = true,
+            "--" => guest_args.extend(args.by_ref()),
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = filename.ok_or(CliError::NoFileProvided)?;
+    let input = fs::read(&filename).map_err(|_| CliError::FailedToOpenFile)?;
+
+    // The guest sees the same arguments it would under `rsvm`.
+    guest_args.insert(0, filename);
+
+    let optimized = match rsvm::opt::optimize(input.clone()) {
+        Ok(optimized) => {
+            if verify {
+                rsvm::opt::verify(&input, &optimized, &guest_args).map_err(CliError::Mismatch)?;
+            }
+
+            optimized
+        }
+        Err(reason) => {
+            warn(reason);
+            input
+        }
+    };
+
+    let written = match output {
+        Some(path) => fs::write(path, &optimized),
+        None => io::Write::write_all(&mut io::stdout().lock(), &optimized),
+    };
+
+    written.map_err(|_| CliError::FailedToWriteOutput)
+}
+
+fn main() {
+    if let Err(error) = try_main() {
+        eprintln!("{:?}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{env, fmt, fs, io, process};
+
+use colored::Colorize;
+
+enum CliError {
+    NoFileProvided,
+    FailedToOpenFile,
+    MissingOptionValue(&'static str),
+    NotBytecode,
+    FailedToWriteOutput,
+}
+
+impl fmt::Debug for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::NoFileProvided => {
+                write!(
+                    f,
+                    "{}{} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Please provide a valid file.".cyan()
+                )
+            }
+            CliError::FailedToOpenFile => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to open file!".cyan(),
+                    "Please make sure the file exists and can be read.".white()
+                )
+            }
+            CliError::MissingOptionValue(option) => {
+                write!(
+                    f,
+                    "{}{} {} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Missing value for option".cyan(),
+                    option.white()
+                )
+            }
+            CliError::NotBytecode => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "The file is not rsvm bytecode!".cyan(),
+                    "Please make sure its header ends in 1d1d1d1d.".white()
+                )
+            }
+            CliError::FailedToWriteOutput => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to write the translation!".cyan(),
+                    "Please make sure the file can be created.".white()
+                )
+            }
+        }
+    }
+}
+
+fn try_main() -> Result<(), CliError> {
+    let mut args = env::args().skip(1);
+    let mut output = None;
+    let mut filename = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or(CliError::MissingOptionValue("-o"))?),
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = filename.ok_or(CliError::NoFileProvided)?;
+    let input = fs::read(filename).map_err(|_| CliError::FailedToOpenFile)?;
+
+    let written = match output {
+        Some(path) => {
+            let file = fs::File::create(path).map_err(|_| CliError::FailedToWriteOutput)?;
+            rsvm::to_c::translate(input, io::BufWriter::new(file))
+        }
+        None => rsvm::to_c::translate(input, io::stdout().lock()),
+    };
+
+    written.map_err(|error| match error.kind() {
+        io::ErrorKind::InvalidData => CliError::NotBytecode,
+        _ => CliError::FailedToWriteOutput,
+    })
+}
+
+fn main() {
+    if let Err(error) = try_main() {
+        eprintln!("{:?}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Word(u32),
+    Buffer(Vec<u32>),
+}
+
+impl Message {
+    pub fn len(&self) -> usize {
+        match self {
+            Message::Word(_) => 1,
+            Message::Buffer(buf) => buf.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChannelError {
+    Full(Message),
+    Closed(Message),
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    queue: VecDeque<Message>,
+    capacity: Option<usize>,
+    closed: bool,
+}
+
+/// A queue of messages shared between the host and any number of `VM`s.
+/// Cloning a channel yields another handle to the same queue.
+#[derive(Clone, Debug)]
+pub struct Channel(Arc<Mutex<ChannelState>>);
+
+impl Channel {
+    fn with_capacity(capacity: Option<usize>) -> Channel {
+        Channel(Arc::new(Mutex::new(ChannelState {
+            queue: VecDeque::new(),
+            capacity,
+            closed: false,
+        })))
+    }
+
+    pub fn new(capacity: usize) -> Channel {
+        Channel::with_capacity(Some(capacity.max(1)))
+    }
+
+    pub fn unbounded() -> Channel {
+        Channel::with_capacity(None)
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), ChannelError> {
+        let mut state = self.0.lock().unwrap();
+
+        if state.closed {
+            return Err(ChannelError::Closed(message));
+        }
+        if state.capacity.is_some_and(|cap| state.queue.len() >= cap) {
+            return Err(ChannelError::Full(message));
+        }
+
+        state.queue.push_back(message);
+
+        Ok(())
+    }
+
+    pub fn recv(&self) -> Option<Message> {
+        self.0.lock().unwrap().queue.pop_front()
+    }
+
+    pub(crate) fn recv_if<F>(&self, accept: F) -> Result<Message, Option<Message>>
+    where
+        F: FnOnce(&Message) -> bool,
+    {
+        let mut state = self.0.lock().unwrap();
+
+        match state.queue.front().map(accept) {
+            Some(true) => Ok(state.queue.pop_front().unwrap()),
+            _ => Err(state.queue.front().cloned()),
+        }
+    }
+
+    pub fn close(&self) {
+        self.0.lock().unwrap().closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.lock().unwrap().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of time for the clock syscalls.
+pub trait Clock: Send + fmt::Debug {
+    /// Time elapsed since some fixed point, never going backwards.
+    fn monotonic(&self) -> Duration;
+
+    /// Time elapsed since the Unix epoch.
+    fn wall(&self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration);
+
+    /// Called by the `VM` once for every executed instruction.
+    fn tick(&mut self) {}
+
+    /// Called by the `VM` instead of `tick` after it ran several
+    /// instructions in one go.
+    fn tick_n(&mut self, instructions: u32) {
+        for _ in 0..instructions {
+            self.tick();
+        }
+    }
+
+    /// Where a clock that keeps its own time is at, for the debugger to put
+    /// back when it steps back over an instruction. Clocks that follow the
+    /// host have nothing to put back.
+    fn position(&self) -> Option<Duration> {
+        None
+    }
+
+    fn set_position(&mut self, _position: Duration) {}
+}
+
+impl Default for Box<dyn Clock> {
+    fn default() -> Box<dyn Clock> {
+        Box::new(SystemClock::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn monotonic(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn wall(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when the guest does: every executed instruction
+/// advances it by a fixed step and sleeping advances it instantly, so the
+/// same program always observes the same times.
+#[derive(Debug)]
+pub struct VirtualClock {
+    now: Duration,
+    epoch: Duration,
+    step: Duration,
+}
+
+impl VirtualClock {
+    pub fn new(step: Duration) -> VirtualClock {
+        VirtualClock {
+            now: Duration::ZERO,
+            epoch: Duration::ZERO,
+            step,
+        }
+    }
+
+    pub fn set_epoch(&mut self, epoch: Duration) {
+        self.epoch = epoch;
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> VirtualClock {
+        VirtualClock::new(Duration::from_nanos(1))
+    }
+}
+
+impl Clock for VirtualClock {
+    fn monotonic(&self) -> Duration {
+        self.now
+    }
+
+    fn wall(&self) -> Duration {
+        self.epoch + self.now
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+
+    fn tick(&mut self) {
+        self.now += self.step;
+    }
+
+    fn tick_n(&mut self, instructions: u32) {
+        self.now += self.step * instructions;
+    }
+
+    fn position(&self) -> Option<Duration> {
+        Some(self.now)
+    }
+
+    fn set_position(&mut self, position: Duration) {
+        self.now = position;
+    }
+}
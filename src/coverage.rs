@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::debuginfo::DebugInfo;
+use crate::VM;
+
+/// `jump_equal` up to `jump_overflow`.
+const CONDITIONAL_JUMPS: std::ops::RangeInclusive<u8> = 0x33..=0x37;
+
+/// How often a conditional jump went either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    pub fn is_reached(&self) -> bool {
+        self.taken + self.not_taken > 0
+    }
+
+    /// How many of the two ways were gone at least once.
+    pub fn ways_hit(&self) -> usize {
+        (self.taken > 0) as usize + (self.not_taken > 0) as usize
+    }
+}
+
+/// The instructions a `VM` executed while collecting coverage, and the
+/// outcomes of its conditional jumps.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    instructions: BTreeMap<usize, u64>,
+    branches: BTreeMap<usize, Branch>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Default::default()
+    }
+
+    pub fn instruction_count(&self, prgrm_cntr: usize) -> u64 {
+        self.instructions.get(&prgrm_cntr).copied().unwrap_or(0)
+    }
+
+    /// The offsets of the conditional jumps known to the coverage, including
+    /// those that were never reached.
+    pub fn branches(&self) -> impl Iterator<Item = (usize, Branch)> + '_ {
+        self.branches
+            .iter()
+            .map(|(&offset, &branch)| (offset, branch))
+    }
+
+    fn record(&mut self, prgrm_cntr: usize) {
+        *self.instructions.entry(prgrm_cntr).or_insert(0) += 1;
+    }
+
+    pub(crate) fn record_branch(&mut self, prgrm_cntr: usize, taken: bool) {
+        let branch = self.branches.entry(prgrm_cntr).or_default();
+
+        if taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+
+    /// Line hits per file, counting a line as often as its most executed
+    /// instruction. Every line the debug info knows of is included.
+    fn line_hits(&self, debug_info: &DebugInfo) -> BTreeMap<String, BTreeMap<u32, u64>> {
+        let mut files = BTreeMap::<String, BTreeMap<u32, u64>>::new();
+
+        for (_, file, line) in debug_info.lines() {
+            files
+                .entry(file.to_string())
+                .or_default()
+                .entry(line)
+                .or_insert(0);
+        }
+
+        for (&offset, &count) in &self.instructions {
+            if let Some(source) = debug_info.source_location(offset) {
+                let hits = files
+                    .entry(source.file)
+                    .or_default()
+                    .entry(source.line)
+                    .or_insert(0);
+                *hits = (*hits).max(count);
+            }
+        }
+
+        files
+    }
+
+    /// Writes the coverage as an lcov tracefile, with one record per source
+    /// file of the debug info. Each conditional jump is a block of two
+    /// branches, taken and not taken, numbered by the offset of the jump.
+    /// Without debug info there is no source to attribute anything to.
+    pub fn write_lcov<W: Write>(&self, mut w: W, debug_info: Option<&DebugInfo>) -> io::Result<()> {
+        let debug_info = match debug_info {
+            Some(debug_info) => debug_info,
+            None => return w.flush(),
+        };
+
+        let mut branches = BTreeMap::<String, Vec<(u32, usize, Branch)>>::new();
+        for (&offset, &branch) in &self.branches {
+            if let Some(source) = debug_info.source_location(offset) {
+                branches
+                    .entry(source.file)
+                    .or_default()
+                    .push((source.line, offset, branch));
+            }
+        }
+
+        for (file, lines) in self.line_hits(debug_info) {
+            writeln!(w, "TN:")?;
+            writeln!(w, "SF:{}", file)?;
+
+            let branches = branches.remove(&file).unwrap_or_default();
+            for (line, offset, branch) in &branches {
+                for (i, count) in [branch.taken, branch.not_taken].iter().enumerate() {
+                    if branch.is_reached() {
+                        writeln!(w, "BRDA:{},{},{},{}", line, offset, i, count)?;
+                    } else {
+                        writeln!(w, "BRDA:{},{},{},-", line, offset, i)?;
+                    }
+                }
+            }
+            let hit = branches
+                .iter()
+                .map(|(_, _, branch)| branch.ways_hit())
+                .sum::<usize>();
+            writeln!(w, "BRF:{}", 2 * branches.len())?;
+            writeln!(w, "BRH:{}", hit)?;
+
+            for (line, count) in &lines {
+                writeln!(w, "DA:{},{}", line, count)?;
+            }
+            writeln!(w, "LF:{}", lines.len())?;
+            writeln!(
+                w,
+                "LH:{}",
+                lines.values().filter(|&&count| count > 0).count()
+            )?;
+
+            writeln!(w, "end_of_record")?;
+        }
+
+        w.flush()
+    }
+
+    /// Writes how many of the known lines and branches were hit.
+    pub fn write_summary<W: Write>(
+        &self,
+        mut w: W,
+        debug_info: Option<&DebugInfo>,
+    ) -> io::Result<()> {
+        let (mut found, mut hit) = (0, 0);
+        if let Some(debug_info) = debug_info {
+            for lines in self.line_hits(debug_info).values() {
+                found += lines.len();
+                hit += lines.values().filter(|&&count| count > 0).count();
+            }
+        }
+
+        let branches_hit = self.branches.values().map(Branch::ways_hit).sum::<usize>();
+
+        writeln!(w, "{} instructions hit", self.instructions.len())?;
+        writeln!(w, "{} of {} lines hit", hit, found)?;
+        writeln!(
+            w,
+            "{} of {} branches hit",
+            branches_hit,
+            2 * self.branches.len()
+        )?;
+
+        w.flush()
+    }
+}
+
+impl VM {
+    /// Makes the `VM` record which instructions it executes from now on, and
+    /// which way its conditional jumps go. Jumps that have debug info are
+    /// known up front, so that those never reached show up as well, which
+    /// means the program should be loaded first.
+    pub fn enable_coverage(&mut self) {
+        let mut coverage = Coverage::new();
+
+        if let Some(debug_info) = &self.debug_info {
+            for (offset, _, _) in debug_info.lines() {
+                let opcode = self.bytecode.get(offset + self.hdr_size);
+
+                if opcode.is_some_and(|opcode| CONDITIONAL_JUMPS.contains(opcode)) {
+                    coverage.branches.entry(offset).or_default();
+                }
+            }
+        }
+
+        self.coverage = Some(coverage);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub(crate) fn cover(&mut self) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(self.instr_start);
+        }
+    }
+}
@@ -0,0 +1,716 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::debugger::{Caller, Debugger, StopReason};
+use crate::debuginfo::DebugInfo;
+use crate::json::{object, Json};
+use crate::scheduler::SharedBuffer;
+use crate::{Frame, VM};
+
+/// Instructions executed between two looks at the incoming requests while
+/// the program is running.
+const POLL_INTERVAL: usize = 0x1000;
+
+/// The only thread the adapter reports; green threads of the guest are
+/// scheduled inside of it.
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REF: u64 = 1;
+const FLAGS_REF: u64 = 2;
+const STACK_REF: u64 = 3;
+const HEAP_REF: u64 = 4;
+
+const REGISTER_NAMES: [&str; 4] = ["A", "B", "C", "D"];
+const FLAG_NAMES: [&str; 7] = [
+    "Equal",
+    "NotEqual",
+    "Greater",
+    "Smaller",
+    "Overflow",
+    "Stop",
+    "Interrupt",
+];
+
+/// Reads one message framed by a `Content-Length` header, or `None` once
+/// the client hangs up.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut len = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse().ok();
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing length"))?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    let message = String::from_utf8(body)
+        .ok()
+        .and_then(|body| Json::parse(&body))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed message"))?;
+
+    Ok(Some(message))
+}
+
+/// Finds the file of the debug info that `path` refers to. Code generators
+/// may record paths relative to the program, editors send absolute ones.
+fn debug_file<'a>(info: &'a DebugInfo, path: &str) -> Option<&'a str> {
+    info.files()
+        .iter()
+        .find(|file| Path::new(path).ends_with(file.as_str()))
+        .map(String::as_str)
+}
+
+fn parse_addr(reference: &str) -> Option<usize> {
+    match reference.strip_prefix("0x") {
+        Some(digits) => usize::from_str_radix(digits, 16).ok(),
+        None => reference.parse().ok(),
+    }
+}
+
+/// Why a request could not be answered.
+type Failure = String;
+
+/// Serves the debug adapter protocol to an editor. Requests are read on a
+/// thread of their own, so that a running program can still be paused.
+///
+/// Breakpoints can be placed on instructions, or on source lines if the
+/// program carries debug info. Guest output is forwarded as `output` events;
+/// since stdin carries the protocol, guests read it as empty.
+pub struct DapServer<W: Write> {
+    requests: Receiver<io::Result<Option<Json>>>,
+    out: W,
+    seq: u64,
+    debugger: Option<Debugger>,
+    output: SharedBuffer,
+    program_dir: PathBuf,
+    instruction_breakpoints: Vec<usize>,
+    source_breakpoints: BTreeMap<String, Vec<usize>>,
+    stop_on_entry: bool,
+    running: bool,
+    /// Set while running only until a function returns to this caller,
+    /// when stepping out of it or over a call.
+    stepping_out: Option<Caller>,
+    done: bool,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new<R: BufRead + Send + 'static>(mut input: R, out: W) -> DapServer<W> {
+        let (sender, requests) = mpsc::channel();
+
+        thread::spawn(move || loop {
+            let message = read_message(&mut input);
+            let last = !matches!(message, Ok(Some(_)));
+
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        });
+
+        DapServer {
+            requests,
+            out,
+            seq: 0,
+            debugger: None,
+            output: Default::default(),
+            program_dir: PathBuf::new(),
+            instruction_breakpoints: Vec::new(),
+            source_breakpoints: BTreeMap::new(),
+            stop_on_entry: false,
+            running: false,
+            stepping_out: None,
+            done: false,
+        }
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+
+        if let Json::Object(members) = &mut message {
+            members.insert(0, ("seq".to_string(), Json::from(self.seq)));
+        }
+
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(object! {
+            "type" => "event",
+            "event" => event,
+            "body" => body,
+        })
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, Failure>) -> io::Result<()> {
+        let seq = request.get("seq").as_u64().unwrap_or(0);
+        let command = request.get("command").as_str().unwrap_or("");
+
+        let mut response = object! {
+            "type" => "response",
+            "request_seq" => seq,
+            "success" => result.is_ok(),
+            "command" => command,
+        };
+
+        if let Json::Object(members) = &mut response {
+            match result {
+                Ok(body) => members.push(("body".to_string(), body)),
+                Err(message) => members.push(("message".to_string(), Json::from(message))),
+            }
+        }
+
+        self.send(response)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let output = self.output.take();
+
+        if output.is_empty() {
+            return Ok(());
+        }
+
+        self.event(
+            "output",
+            object! {
+                "category" => "stdout",
+                "output" => String::from_utf8_lossy(&output).into_owned(),
+            },
+        )
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        let mut body = object! {
+            "reason" => reason,
+            "threadId" => THREAD_ID,
+            "allThreadsStopped" => true,
+        };
+
+        if let (Json::Object(members), Some(description)) = (&mut body, description) {
+            members.push(("description".to_string(), Json::from(description.clone())));
+            members.push(("text".to_string(), Json::from(description)));
+        }
+
+        self.event("stopped", body)
+    }
+
+    fn report(&mut self, reason: StopReason) -> io::Result<()> {
+        self.running = false;
+        self.stepping_out = None;
+        self.flush_output()?;
+
+        match reason {
+            StopReason::Step => self.stopped("step", None),
+            StopReason::Breakpoint(addr) => {
+                let on_source = self
+                    .source_breakpoints
+                    .values()
+                    .flatten()
+                    .any(|&bp| bp == addr);
+                let reason = if on_source {
+                    "breakpoint"
+                } else {
+                    "instruction breakpoint"
+                };
+
+                self.stopped(reason, None)
+            }
+            StopReason::Watchpoint { addr, old, new } => {
+                let description = format!("Heap word {:#x} changed from {} to {}", addr, old, new);
+                self.stopped("data breakpoint", Some(description))
+            }
+            StopReason::StartOfHistory => {
+                let description = "Reached the start of the recorded history".to_string();
+                self.stopped("step", Some(description))
+            }
+            StopReason::Faulted(fault) => self.stopped("exception", Some(fault.to_string())),
+            StopReason::Exited(status) => {
+                self.event("exited", object! { "exitCode" => status.code() })?;
+                self.event("terminated", object! {})
+            }
+        }
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, Failure> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    fn apply_breakpoints(&mut self) {
+        if let Some(debugger) = &mut self.debugger {
+            let old = debugger.breakpoints().collect::<Vec<_>>();
+
+            for addr in old {
+                debugger.remove_breakpoint(addr);
+            }
+            let source = self.source_breakpoints.values().flatten();
+            for &addr in self.instruction_breakpoints.iter().chain(source) {
+                debugger.set_breakpoint(addr);
+            }
+        }
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, Failure> {
+        let program = args
+            .get("program")
+            .as_str()
+            .ok_or_else(|| "Missing `program` to launch".to_string())?;
+        let bytecode = fs::read(program).map_err(|error| format!("{}: {}", program, error))?;
+
+        let guest_args = std::iter::once(program)
+            .chain(args.get("args").as_array().iter().filter_map(Json::as_str))
+            .collect::<Vec<_>>();
+
+        let mut vm = VM::new();
+        vm.load_program(bytecode);
+        vm.set_args(&guest_args, &[]);
+        vm.set_input(io::empty());
+        vm.set_output(self.output.clone());
+
+        self.debugger = Some(Debugger::new(vm));
+        self.program_dir = Path::new(program)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.apply_breakpoints();
+
+        Ok(Json::Null)
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Json {
+        let breakpoints = args.get("breakpoints").as_array();
+
+        self.instruction_breakpoints = breakpoints
+            .iter()
+            .filter_map(|breakpoint| {
+                let addr = parse_addr(breakpoint.get("instructionReference").as_str()?)?;
+                let offset = breakpoint.get("offset").as_i64().unwrap_or(0);
+
+                Some((addr as i64 + offset) as usize)
+            })
+            .collect();
+        self.apply_breakpoints();
+
+        let verified = breakpoints
+            .iter()
+            .map(|breakpoint| {
+                object! {
+                    "verified" => breakpoint.get("instructionReference").as_str().and_then(parse_addr).is_some(),
+                    "instructionReference" => breakpoint.get("instructionReference").clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        object! { "breakpoints" => verified }
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Json) -> Json {
+        let path = args.get("source").get("path").as_str().unwrap_or("");
+        let info = self
+            .debugger
+            .as_ref()
+            .and_then(|debugger| debugger.vm().debug_info());
+        let file = info.and_then(|info| debug_file(info, path));
+
+        let mut addrs = Vec::new();
+        let breakpoints = args
+            .get("breakpoints")
+            .as_array()
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint.get("line").as_u64().unwrap_or(0) as u32;
+                let addr = info
+                    .zip(file)
+                    .and_then(|(info, file)| info.line_offset(file, line));
+
+                match addr {
+                    Some(addr) => {
+                        addrs.push(addr);
+
+                        object! {
+                            "verified" => true,
+                            "line" => line,
+                            "instructionReference" => format!("{:#x}", addr),
+                        }
+                    }
+                    None => object! {
+                        "verified" => false,
+                        "line" => line,
+                        "message" => match file {
+                            Some(_) => "No instruction was generated for this line",
+                            None => "The program has no debug info for this file",
+                        },
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.source_breakpoints.insert(path.to_string(), addrs);
+        self.apply_breakpoints();
+
+        object! { "breakpoints" => breakpoints }
+    }
+
+    fn frame(&self, id: usize, frame: Frame) -> Json {
+        let pc = frame.prgrm_cntr;
+
+        let name = match frame
+            .location
+            .as_ref()
+            .and_then(|location| location.symbol.as_ref())
+        {
+            Some((name, 0)) => name.clone(),
+            Some((name, offset)) => format!("{}+{:#x}", name, offset),
+            None => format!("{:#x}", pc),
+        };
+
+        let (source, line, column) = match frame.location.and_then(|location| location.source) {
+            Some(source) => {
+                let path = self.program_dir.join(&source.file);
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+
+                let json = object! {
+                    "name" => name.unwrap_or_default(),
+                    "path" => path.to_string_lossy().into_owned(),
+                };
+
+                (Some(json), source.line, source.column)
+            }
+            None => (None, 0, 0),
+        };
+
+        let mut frame = object! {
+            "id" => id,
+            "name" => name,
+            "line" => line,
+            "column" => column,
+            "instructionPointerReference" => format!("{:#x}", pc),
+        };
+
+        if let (Json::Object(members), Some(source)) = (&mut frame, source) {
+            members.push(("source".to_string(), source));
+        }
+
+        frame
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, Failure> {
+        let vm = self.debugger()?.vm();
+        let backtrace = vm.backtrace(vm.prgrm_cntr);
+
+        let frames = backtrace
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| self.frame(id, frame))
+            .collect::<Vec<_>>();
+
+        Ok(object! {
+            "totalFrames" => frames.len(),
+            "stackFrames" => frames,
+        })
+    }
+
+    fn scopes(&mut self) -> Result<Json, Failure> {
+        let heap_words = self.debugger()?.vm().heap.capacity();
+
+        let scope = |name: &str, reference: u64, expensive: bool| {
+            object! {
+                "name" => name,
+                "variablesReference" => reference,
+                "expensive" => expensive,
+            }
+        };
+
+        let mut heap = scope("Heap", HEAP_REF, true);
+        if let Json::Object(members) = &mut heap {
+            members.push(("indexedVariables".to_string(), Json::from(heap_words)));
+        }
+
+        Ok(object! {
+            "scopes" => vec![
+                scope("Registers", REGISTERS_REF, false),
+                scope("Flags", FLAGS_REF, false),
+                scope("Stack", STACK_REF, false),
+                heap,
+            ],
+        })
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, Failure> {
+        let vm = self.debugger()?.vm();
+
+        let variable = |name: String, value: String| {
+            object! {
+                "name" => name,
+                "value" => value,
+                "variablesReference" => 0u64,
+            }
+        };
+
+        let variables = match args.get("variablesReference").as_u64() {
+            Some(REGISTERS_REF) => REGISTER_NAMES
+                .iter()
+                .zip(&vm.regs)
+                .map(|(name, value)| variable(name.to_string(), value.to_string()))
+                .chain(std::iter::once(variable(
+                    "pc".to_string(),
+                    format!("{:#x}", vm.prgrm_cntr),
+                )))
+                .collect(),
+            Some(FLAGS_REF) => FLAG_NAMES
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let set = vm.flags.bits() & (1 << i) != 0;
+                    variable(name.to_string(), set.to_string())
+                })
+                .collect(),
+            Some(STACK_REF) => vm
+                .stack()
+                .as_slice()
+                .iter()
+                .enumerate()
+                .map(|(i, value)| variable(format!("[{}]", i), value.to_string()))
+                .collect(),
+            Some(HEAP_REF) => {
+                let start = args.get("start").as_u64().unwrap_or(0) as usize;
+                let count = args
+                    .get("count")
+                    .as_u64()
+                    .map_or(usize::MAX, |count| count as usize);
+                let end = start.saturating_add(count).min(vm.heap.capacity());
+
+                (start..end)
+                    .map(|addr| variable(format!("[{:#x}]", addr), vm.heap.read(addr).to_string()))
+                    .collect()
+            }
+            _ => return Err("Unknown variables reference".to_string()),
+        };
+
+        Ok(object! { "variables" => Json::Array(variables) })
+    }
+
+    /// Runs a request that stops the program again right away.
+    fn stop_after<F>(&mut self, request: &Json, action: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Debugger) -> StopReason,
+    {
+        let reason = self.debugger().map(action);
+
+        match reason {
+            Ok(reason) => {
+                self.respond(request, Ok(Json::Null))?;
+                self.report(reason)
+            }
+            Err(message) => self.respond(request, Err(message)),
+        }
+    }
+
+    /// Runs until a function returns to `caller`, or steps once without
+    /// one. Returning can take arbitrarily long, so it runs the way
+    /// `continue` does and can be paused.
+    fn step_out(
+        &mut self,
+        request: &Json,
+        caller: Result<Option<Caller>, Failure>,
+    ) -> io::Result<()> {
+        match caller {
+            Ok(Some(caller)) => {
+                self.running = true;
+                self.stepping_out = Some(caller);
+                self.respond(request, Ok(Json::Null))
+            }
+            Ok(None) => self.stop_after(request, Debugger::step),
+            Err(message) => self.respond(request, Err(message)),
+        }
+    }
+
+    fn dispatch(&mut self, request: &Json) -> io::Result<()> {
+        let args = request.get("arguments");
+
+        match request.get("command").as_str().unwrap_or("") {
+            "initialize" => {
+                let capabilities = object! {
+                    "supportsConfigurationDoneRequest" => true,
+                    "supportsInstructionBreakpoints" => true,
+                    "supportsStepBack" => true,
+                    "supportsTerminateRequest" => true,
+                };
+
+                self.respond(request, Ok(capabilities))
+            }
+            // Breakpoints on source lines can only be resolved once the debug
+            // info is loaded, so the client is told to send them afterwards.
+            "launch" => {
+                let result = self.launch(args);
+                let launched = result.is_ok();
+                self.respond(request, result)?;
+
+                if launched {
+                    self.event("initialized", object! {})?;
+                }
+
+                Ok(())
+            }
+            "setBreakpoints" => {
+                let body = self.set_source_breakpoints(args);
+                self.respond(request, Ok(body))
+            }
+            "setInstructionBreakpoints" => {
+                let body = self.set_instruction_breakpoints(args);
+                self.respond(request, Ok(body))
+            }
+            "setExceptionBreakpoints" => {
+                self.respond(request, Ok(object! { "breakpoints" => Vec::new() }))
+            }
+            "configurationDone" => {
+                let launched = self.debugger().map(|_| Json::Null);
+                self.respond(request, launched)?;
+
+                if self.debugger.is_some() {
+                    if self.stop_on_entry {
+                        self.stopped("entry", None)?;
+                    } else {
+                        self.running = true;
+                    }
+                }
+
+                Ok(())
+            }
+            "threads" => {
+                let threads = vec![object! { "id" => THREAD_ID, "name" => "main" }];
+                self.respond(request, Ok(object! { "threads" => threads }))
+            }
+            "stackTrace" => {
+                let result = self.stack_trace();
+                self.respond(request, result)
+            }
+            "scopes" => {
+                let result = self.scopes();
+                self.respond(request, result)
+            }
+            "variables" => {
+                let result = self.variables(args);
+                self.respond(request, result)
+            }
+            "continue" => {
+                let launched = self
+                    .debugger()
+                    .map(|_| object! { "allThreadsContinued" => true });
+                self.running = launched.is_ok();
+                self.stepping_out = None;
+                self.respond(request, launched)
+            }
+            "pause" => {
+                self.respond(request, Ok(Json::Null))?;
+
+                if self.running {
+                    self.running = false;
+                    self.stepping_out = None;
+                    self.flush_output()?;
+                    self.stopped("pause", None)?;
+                }
+
+                Ok(())
+            }
+            "next" => {
+                let call_site = self.debugger().map(|debugger| debugger.call_site());
+                self.step_out(request, call_site)
+            }
+            "stepIn" => self.stop_after(request, Debugger::step),
+            "stepOut" => {
+                let caller = self.debugger().map(|debugger| debugger.caller());
+                self.step_out(request, caller)
+            }
+            "stepBack" => self.stop_after(request, Debugger::step_back),
+            "reverseContinue" => self.stop_after(request, Debugger::reverse_continue),
+            "terminate" => {
+                self.respond(request, Ok(Json::Null))?;
+                self.event("terminated", object! {})
+            }
+            "disconnect" => {
+                self.done = true;
+                self.respond(request, Ok(Json::Null))
+            }
+            command => {
+                let message = format!("Unsupported request `{}`", command);
+                self.respond(request, Err(message))
+            }
+        }
+    }
+
+    /// Serves requests until the client disconnects.
+    pub fn serve(&mut self) -> io::Result<()> {
+        while !self.done {
+            let message = if self.running {
+                match self.requests.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match message {
+                Some(Ok(Some(request))) => self.dispatch(&request)?,
+                Some(Ok(None)) => return Ok(()),
+                Some(Err(error)) => return Err(error),
+                None => {}
+            }
+
+            if self.running {
+                // `None` while the program keeps running.
+                let stepping_out = self.stepping_out;
+                let reason = self
+                    .debugger()
+                    .ok()
+                    .and_then(|debugger| match stepping_out {
+                        Some(caller) => debugger.step_out_for(caller, POLL_INTERVAL),
+                        None => match debugger.resume_for(POLL_INTERVAL) {
+                            StopReason::Step => None,
+                            reason => Some(reason),
+                        },
+                    });
+
+                match reason {
+                    Some(reason) => self.report(reason)?,
+                    None => self.flush_output()?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serves the debug adapter protocol over the standard streams.
+pub fn serve_stdio() -> io::Result<()> {
+    let input = io::BufReader::new(io::stdin());
+
+    DapServer::new(input, io::stdout()).serve()
+}
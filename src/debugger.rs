@@ -0,0 +1,425 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::decode::Instr;
+use crate::replay::Event;
+use crate::thread::ThreadState;
+use crate::{ExitStatus, Fault, StackChange, VM};
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 4096;
+
+/// Why the `Debugger` handed control back.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(usize),
+    Watchpoint {
+        addr: usize,
+        old: u32,
+        new: u32,
+    },
+    Exited(ExitStatus),
+    Faulted(Fault),
+    /// Stepping back ran out of recorded history.
+    StartOfHistory,
+}
+
+/// Where the function running now returns to: the thread running it, and
+/// how deep the stack of that thread is once it has returned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Caller {
+    thread: usize,
+    depth: usize,
+}
+
+/// Everything needed to take back one executed instruction.
+#[derive(Debug)]
+struct Undo {
+    regs: [usize; 4],
+    flags: u32,
+    prgrm_cntr: usize,
+    base_ptr: u32,
+    instr_start: usize,
+    ivt_base: usize,
+    timer_period: u32,
+    timer_count: u32,
+    time_slice: u32,
+    slice_count: u32,
+    yield_requested: bool,
+    blocked: bool,
+    exit_code: u32,
+    fault: Option<Fault>,
+    clock: Option<Duration>,
+    /// Interrupts the instruction serviced, which have to be raised again.
+    serviced: u32,
+    /// Interrupts raised while it ran, by the timer say, which have to go.
+    raised: u32,
+    thread_states: Vec<ThreadState>,
+    heap: Vec<(usize, u32)>,
+    /// How many words the heap had, which it shrinks back to.
+    heap_len: usize,
+    stack: Vec<StackChange>,
+    /// The inputs the instruction took from the host, to hand out again.
+    inputs: Vec<Event>,
+}
+
+impl Undo {
+    fn watch_hit(&self, vm: &VM, watchpoints: &BTreeSet<usize>) -> Option<StopReason> {
+        self.heap
+            .iter()
+            .find(|(addr, old)| watchpoints.contains(addr) && vm.heap.read(*addr) != *old)
+            .map(|&(addr, old)| StopReason::Watchpoint {
+                addr,
+                old,
+                new: vm.heap.read(addr),
+            })
+    }
+}
+
+/// Drives a `VM` one instruction at a time, stopping at breakpoints on
+/// instructions and watchpoints on heap words. Every executed instruction is
+/// journaled in a bounded ring buffer so execution can also be rewound.
+///
+/// Only the state of the `VM` itself is rewound: output, files and channels
+/// keep whatever the guest did to them. The inputs it took from stdin,
+/// channels, files, clocks, the random number generator or a replay are
+/// handed back to it when it runs forward again, so it gets the same ones
+/// as the first time.
+/// History does not reach past thread spawns and context switches.
+#[derive(Debug)]
+pub struct Debugger {
+    vm: VM,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    history: VecDeque<Undo>,
+    history_limit: usize,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: VecDeque::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Changing the state of the `VM` behind the back of the journal would
+    /// make the recorded history meaningless, so it is dropped, along with
+    /// the inputs stepped back over.
+    pub fn vm_mut(&mut self) -> &mut VM {
+        self.history.clear();
+        self.vm.rewound_inputs.clear();
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> VM {
+        self.vm
+    }
+
+    pub fn set_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn set_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.insert(addr)
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// Number of executed instructions that can still be stepped back over.
+    /// Zero disables the journal altogether.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    fn stopped(&self) -> Option<StopReason> {
+        match self.vm.exit_status()? {
+            Ok(status) => Some(StopReason::Exited(status)),
+            Err(fault) => Some(StopReason::Faulted(fault)),
+        }
+    }
+
+    fn record(&mut self) -> Option<StopReason> {
+        let vm = &mut self.vm;
+        let thread_layout = (vm.threads.len(), vm.current_thread);
+
+        let mut undo = Undo {
+            regs: vm.regs,
+            flags: vm.flags.bits(),
+            prgrm_cntr: vm.prgrm_cntr,
+            base_ptr: vm.base_ptr,
+            instr_start: vm.instr_start,
+            ivt_base: vm.ivt_base,
+            timer_period: vm.timer_period,
+            timer_count: vm.timer_count,
+            time_slice: vm.time_slice,
+            slice_count: vm.slice_count,
+            yield_requested: vm.yield_requested,
+            blocked: vm.blocked,
+            exit_code: vm.exit_code,
+            fault: vm.fault.clone(),
+            clock: vm.clock.position(),
+            serviced: 0,
+            raised: 0,
+            thread_states: vm
+                .threads
+                .iter()
+                .map(|thread| thread.state.clone())
+                .collect(),
+            heap: Vec::new(),
+            heap_len: vm.heap.capacity(),
+            stack: Vec::new(),
+            inputs: Vec::new(),
+        };
+
+        let pending = vm.interrupts.0.load(Ordering::SeqCst);
+        vm.heap.journal = Some(Vec::new());
+        vm.stack.journal = Some(Vec::new());
+        vm.input_journal = Some(Vec::new());
+
+        vm.step();
+
+        undo.heap = vm.heap.journal.take().unwrap_or_default();
+        undo.stack = vm.stack.journal.take().unwrap_or_default();
+        undo.inputs = vm.input_journal.take().unwrap_or_default();
+
+        let now_pending = vm.interrupts.0.load(Ordering::SeqCst);
+        undo.serviced = pending & !now_pending;
+        undo.raised = now_pending & !pending;
+
+        let hit = undo.watch_hit(vm, &self.watchpoints);
+
+        if thread_layout != (vm.threads.len(), vm.current_thread) {
+            // The journal of the stack left with the thread that was switched
+            // out, and the undo log can't bring the old layout back anyway.
+            for thread in &mut vm.threads {
+                thread.stack_mut().journal = None;
+            }
+            self.history.clear();
+        } else if self.history_limit > 0 {
+            if self.history.len() == self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(undo);
+        }
+
+        hit
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        let vm = &mut self.vm;
+
+        for change in undo.stack.iter().rev() {
+            match change {
+                StackChange::Push => {
+                    vm.stack.pop();
+                }
+                StackChange::Pop(elem) => vm.stack.push(*elem),
+            }
+        }
+
+        // The words were there to be written, so they can be written back.
+        for &(addr, old) in undo.heap.iter().rev() {
+            let _ = vm.heap.write(addr, old);
+        }
+        vm.heap.truncate(undo.heap_len);
+        vm.rewound_inputs.extend(undo.inputs.into_iter().rev());
+
+        vm.regs = undo.regs;
+        vm.flags.set_bits(undo.flags);
+        vm.prgrm_cntr = undo.prgrm_cntr;
+        vm.base_ptr = undo.base_ptr;
+        vm.instr_start = undo.instr_start;
+        vm.ivt_base = undo.ivt_base;
+        vm.timer_period = undo.timer_period;
+        vm.timer_count = undo.timer_count;
+        vm.time_slice = undo.time_slice;
+        vm.slice_count = undo.slice_count;
+        vm.yield_requested = undo.yield_requested;
+        vm.blocked = undo.blocked;
+        vm.exit_code = undo.exit_code;
+        vm.fault = undo.fault;
+        vm.interrupts.0.fetch_and(!undo.raised, Ordering::SeqCst);
+        vm.interrupts.0.fetch_or(undo.serviced, Ordering::SeqCst);
+
+        if let Some(position) = undo.clock {
+            vm.clock.set_position(position);
+        }
+
+        for (thread, state) in vm.threads.iter_mut().zip(undo.thread_states) {
+            thread.state = state;
+        }
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> StopReason {
+        if let Some(reason) = self.stopped() {
+            return reason;
+        }
+
+        let hit = self.record();
+
+        hit.or_else(|| self.stopped()).unwrap_or(StopReason::Step)
+    }
+
+    /// Executes instructions until a breakpoint or watchpoint is hit or the
+    /// program stops. The instruction under the program counter is always
+    /// executed, so resuming from a breakpoint doesn't stop right away.
+    pub fn resume(&mut self) -> StopReason {
+        loop {
+            match self.resume_for(usize::MAX) {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+        }
+    }
+
+    /// Like `resume`, but hands control back with `StopReason::Step` after
+    /// `max_steps` instructions, so that a front end can poll for a request
+    /// to interrupt the program in between.
+    pub fn resume_for(&mut self, max_steps: usize) -> StopReason {
+        for _ in 0..max_steps {
+            match self.step() {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+
+            if self.breakpoints.contains(&self.vm.prgrm_cntr) {
+                return StopReason::Breakpoint(self.vm.prgrm_cntr);
+            }
+        }
+
+        StopReason::Step
+    }
+
+    /// The caller of the function running now, found through the frame
+    /// `base_ptr` points at. Outside of any function there's none.
+    pub fn caller(&self) -> Option<Caller> {
+        let frame = self.vm.base_ptr as usize;
+
+        frame.checked_sub(2).map(|depth| Caller {
+            thread: self.vm.current_thread,
+            depth,
+        })
+    }
+
+    /// Where the function the instruction under the program counter calls
+    /// returns to, which is back here with the stack as deep as it is now.
+    /// Other instructions call nothing.
+    pub fn call_site(&self) -> Option<Caller> {
+        match self.vm.decoded.get(self.vm.prgrm_cntr) {
+            Some((Instr::Call(_), _)) => Some(Caller {
+                thread: self.vm.current_thread,
+                depth: self.vm.stack.len(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn step_out_to(&mut self, caller: Caller) -> StopReason {
+        loop {
+            if let Some(reason) = self.step_out_for(caller, usize::MAX) {
+                return reason;
+            }
+        }
+    }
+
+    /// Executes instructions until the function running now returns, or
+    /// just the one outside of any function. Breakpoints, watchpoints and
+    /// the program stopping end it early, the same as for `resume`.
+    pub fn step_out(&mut self) -> StopReason {
+        match self.caller() {
+            Some(caller) => self.step_out_to(caller),
+            None => self.step(),
+        }
+    }
+
+    /// Executes a single instruction, and when it is a call, the function
+    /// it calls up to where that returns. Breakpoints, watchpoints and the
+    /// program stopping end it early, the same as for `step_out`.
+    pub fn step_over(&mut self) -> StopReason {
+        match self.call_site() {
+            Some(caller) => self.step_out_to(caller),
+            None => self.step(),
+        }
+    }
+
+    /// Like `step_out`, but gives up with `None` after `max_steps`
+    /// instructions, so that a front end can poll for a request to interrupt
+    /// the program in between.
+    pub fn step_out_for(&mut self, caller: Caller, max_steps: usize) -> Option<StopReason> {
+        for _ in 0..max_steps {
+            match self.step() {
+                StopReason::Step => {}
+                reason => return Some(reason),
+            }
+
+            if self.vm.current_thread == caller.thread && self.vm.stack.len() <= caller.depth {
+                return Some(StopReason::Step);
+            }
+            if self.breakpoints.contains(&self.vm.prgrm_cntr) {
+                return Some(StopReason::Breakpoint(self.vm.prgrm_cntr));
+            }
+        }
+
+        None
+    }
+
+    /// Takes back the last executed instruction.
+    pub fn step_back(&mut self) -> StopReason {
+        let undo = match self.history.pop_back() {
+            Some(undo) => undo,
+            None => return StopReason::StartOfHistory,
+        };
+
+        let hit = undo.watch_hit(&self.vm, &self.watchpoints);
+        self.undo(undo);
+
+        hit.unwrap_or(StopReason::Step)
+    }
+
+    /// Rewinds until the program counter lands on a breakpoint, a watched
+    /// heap word goes back to an earlier value or the history runs out.
+    pub fn reverse_continue(&mut self) -> StopReason {
+        loop {
+            match self.step_back() {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+
+            if self.breakpoints.contains(&self.vm.prgrm_cntr) {
+                return StopReason::Breakpoint(self.vm.prgrm_cntr);
+            }
+        }
+    }
+}
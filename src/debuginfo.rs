@@ -0,0 +1,304 @@
+use std::convert::TryInto;
+use std::fmt;
+
+/// Marks the end of bytecode that carries debug info. The section itself
+/// comes right before its length, which comes right before the magic.
+const MAGIC: &[u8; 8] = b"RSVMDBG1";
+const TRAILER_LEN: usize = MAGIC.len() + 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LineEntry {
+    offset: usize,
+    file: usize,
+    line: u32,
+    column: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// What the debug info knows about an instruction offset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// The closest label at or before the offset, and how far past it the
+    /// offset is.
+    pub symbol: Option<(String, usize)>,
+    pub source: Option<SourceLocation>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some((name, 0)) => write!(f, "{}", name)?,
+            Some((name, offset)) => write!(f, "{}+{:#x}", name, offset)?,
+            None => {}
+        }
+
+        match (&self.symbol, &self.source) {
+            (Some(_), Some(source)) => write!(f, " at {}", source),
+            (None, Some(source)) => write!(f, "{}", source),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Maps instruction offsets, as found in `prgrm_cntr`, to source lines and
+/// label names. Code generators build it with a `DebugInfoBuilder` and
+/// append it to the bytecode they emit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    lines: Vec<LineEntry>,
+    labels: Vec<(usize, String)>,
+}
+
+impl DebugInfo {
+    /// Splits debug info off the end of `bytecode`, if it carries any.
+    pub fn split_off(bytecode: &mut Vec<u8>) -> Option<DebugInfo> {
+        if !bytecode.ends_with(MAGIC) || bytecode.len() < TRAILER_LEN {
+            return None;
+        }
+
+        let len_at = bytecode.len() - TRAILER_LEN;
+        let len = u32::from_be_bytes(bytecode[len_at..len_at + 4].try_into().unwrap()) as usize;
+        let start = len_at.checked_sub(len)?;
+
+        let info = DebugInfo::decode(&bytecode[start..len_at])?;
+        bytecode.truncate(start);
+
+        Some(info)
+    }
+
+    /// Appends the debug info to the end of `bytecode`.
+    pub fn append_to(&self, bytecode: &mut Vec<u8>) {
+        let section = self.encode();
+
+        bytecode.extend_from_slice(&section);
+        bytecode.extend_from_slice(&(section.len() as u32).to_be_bytes());
+        bytecode.extend_from_slice(MAGIC);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut put = |value: usize| buf.extend_from_slice(&(value as u32).to_be_bytes());
+
+        put(self.files.len());
+        for file in &self.files {
+            put(file.len());
+        }
+        put(self.lines.len());
+        put(self.labels.len());
+
+        for entry in &self.lines {
+            put(entry.offset);
+            put(entry.file);
+            put(entry.line as usize);
+            put(entry.column as usize);
+        }
+        for (offset, name) in &self.labels {
+            put(*offset);
+            put(name.len());
+        }
+
+        // Strings go last, so that every number above sits on a known offset.
+        for file in &self.files {
+            buf.extend_from_slice(file.as_bytes());
+        }
+        for (_, name) in &self.labels {
+            buf.extend_from_slice(name.as_bytes());
+        }
+
+        buf
+    }
+
+    fn decode(section: &[u8]) -> Option<DebugInfo> {
+        let mut words = section.chunks_exact(4);
+        let mut next = || -> Option<usize> {
+            Some(u32::from_be_bytes(words.next()?.try_into().unwrap()) as usize)
+        };
+
+        let file_lens = (0..next()?).map(|_| next()).collect::<Option<Vec<_>>>()?;
+        let (no_of_lines, no_of_labels) = (next()?, next()?);
+
+        let lines = (0..no_of_lines)
+            .map(|_| {
+                Some(LineEntry {
+                    offset: next()?,
+                    file: next()?,
+                    line: next()? as u32,
+                    column: next()? as u32,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let labels = (0..no_of_labels)
+            .map(|_| Some((next()?, next()?)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let numbers = 4 * (3 + file_lens.len() + 4 * lines.len() + 2 * labels.len());
+        let mut strings = section.get(numbers..)?;
+        let mut string = |len: usize| -> Option<String> {
+            let bytes = strings.get(..len)?;
+            strings = &strings[len..];
+
+            String::from_utf8(bytes.to_vec()).ok()
+        };
+
+        let files = file_lens
+            .into_iter()
+            .map(&mut string)
+            .collect::<Option<Vec<_>>>()?;
+        let labels = labels
+            .into_iter()
+            .map(|(offset, len)| Some((offset, string(len)?)))
+            .collect::<Option<Vec<_>>>()?;
+
+        if lines.iter().any(|entry| entry.file >= files.len()) {
+            return None;
+        }
+
+        let mut info = DebugInfo {
+            files,
+            lines,
+            labels,
+        };
+        info.sort();
+
+        Some(info)
+    }
+
+    /// Moves every line and label to the offset `relocate` gives for its
+    /// own, as after code before it grew or shrank.
+    pub fn relocate(&mut self, relocate: impl Fn(usize) -> usize) {
+        for entry in &mut self.lines {
+            entry.offset = relocate(entry.offset);
+        }
+        for (offset, _) in &mut self.labels {
+            *offset = relocate(*offset);
+        }
+
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.lines.sort_by_key(|entry| entry.offset);
+        self.labels.sort_by_key(|(offset, _)| *offset);
+    }
+
+    pub fn source_location(&self, prgrm_cntr: usize) -> Option<SourceLocation> {
+        let end = self
+            .lines
+            .partition_point(|entry| entry.offset <= prgrm_cntr);
+        let entry = self.lines.get(end.checked_sub(1)?)?;
+
+        Some(SourceLocation {
+            file: self.files[entry.file].clone(),
+            line: entry.line,
+            column: entry.column,
+        })
+    }
+
+    pub fn symbol(&self, prgrm_cntr: usize) -> Option<(&str, usize)> {
+        let end = self
+            .labels
+            .partition_point(|(offset, _)| *offset <= prgrm_cntr);
+        let (offset, name) = self.labels.get(end.checked_sub(1)?)?;
+
+        Some((name.as_str(), prgrm_cntr - offset))
+    }
+
+    pub fn locate(&self, prgrm_cntr: usize) -> Option<Location> {
+        let symbol = self
+            .symbol(prgrm_cntr)
+            .map(|(name, offset)| (name.to_string(), offset));
+        let source = self.source_location(prgrm_cntr);
+
+        if symbol.is_none() && source.is_none() {
+            return None;
+        }
+
+        Some(Location { symbol, source })
+    }
+
+    pub fn label_offset(&self, name: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(_, label)| label == name)
+            .map(|(offset, _)| *offset)
+    }
+
+    /// Offset of the first instruction generated for `line` of `file`.
+    pub fn line_offset(&self, file: &str, line: u32) -> Option<usize> {
+        let file = self.files.iter().position(|path| path == file)?;
+
+        self.lines
+            .iter()
+            .find(|entry| entry.file == file && entry.line == line)
+            .map(|entry| entry.offset)
+    }
+
+    /// Every offset a line was recorded for, along with the file and line.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str, u32)> + '_ {
+        self.lines
+            .iter()
+            .map(move |entry| (entry.offset, self.files[entry.file].as_str(), entry.line))
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+}
+
+/// Collects debug info while a code generator emits instructions. Entries
+/// can be added in any order.
+#[derive(Debug, Default)]
+pub struct DebugInfoBuilder {
+    info: DebugInfo,
+}
+
+impl DebugInfoBuilder {
+    pub fn new() -> DebugInfoBuilder {
+        Default::default()
+    }
+
+    /// Records that the instruction at `offset` was generated from the given
+    /// position in `file`.
+    pub fn line(&mut self, offset: usize, file: &str, line: u32, column: u32) -> &mut Self {
+        let file = match self.info.files.iter().position(|path| path == file) {
+            Some(file) => file,
+            None => {
+                self.info.files.push(file.to_string());
+                self.info.files.len() - 1
+            }
+        };
+
+        self.info.lines.push(LineEntry {
+            offset,
+            file,
+            line,
+            column,
+        });
+        self
+    }
+
+    pub fn label(&mut self, offset: usize, name: &str) -> &mut Self {
+        self.info.labels.push((offset, name.to_string()));
+        self
+    }
+
+    pub fn build(&self) -> DebugInfo {
+        let mut info = self.info.clone();
+        info.sort();
+
+        info
+    }
+}
@@ -0,0 +1,700 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use crate::syscall::syscall;
+use crate::{FaultKind, Flag, VM};
+
+/// How the `VM` gets from the bytecode to the operation it runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dispatch {
+    /// Reads every operand from the bytecode as the instruction runs, and
+    /// calls the operation through a table indexed by opcode.
+    Bytecode,
+    /// Runs instructions decoded once when the program is loaded.
+    #[default]
+    Decoded,
+    /// Decodes like `Decoded`, and compiles blocks of instructions that run
+    /// often to native code.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    Compiled,
+}
+
+/// An instruction with its operands read out of the bytecode. Registers are
+/// known to be valid, and literals, jump targets included, are already put
+/// together from their bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instr {
+    Nop,
+    Exit,
+    ExitReg(u8),
+    PushLit(u32),
+    PushReg(u8),
+    PopReg(u8),
+    PopHeap(u32),
+    StackDupe,
+    MovLitReg(u8, u32),
+    MovLitHeap(u32, u32),
+    MovHeapReg(u8, u32),
+    MovRegHeap(u32, u8),
+    MovRegReg(u8, u8),
+    MovHeapHeap(u32, u32),
+    PushHeap(u32),
+    MovPtrReg(u8, u8),
+    MovRegPtr(u8, u8),
+    AddReg(u8, u8),
+    AddStack,
+    SubReg(u8, u8),
+    SubStack,
+    MulReg(u8, u8),
+    MulStack,
+    DivReg(u8, u8),
+    DivStack,
+    NotReg(u8),
+    NotStack,
+    AndReg(u8, u8),
+    AndStack,
+    OrReg(u8, u8),
+    OrStack,
+    XorReg(u8, u8),
+    XorStack,
+    Jump(u32),
+    Call(u32),
+    Ret,
+    CompareRegReg(u8, u8),
+    CompareRegLit(u8, u32),
+    CompareStackLit(u32),
+    JumpEqual(u32),
+    JumpNotEqual(u32),
+    JumpGreater(u32),
+    JumpSmaller(u32),
+    JumpOverflow(u32),
+    FlagReset,
+    IncReg(u8),
+    DecReg(u8),
+    IncStack,
+    DecStack,
+    AddRegNum(u8, u32),
+    AddStackNum(u8),
+    SubRegNum(u8, u32),
+    SubStackNum(u8),
+    MulRegNum(u8, u32),
+    MulStackNum(u8),
+    DivRegNum(u8, u32),
+    DivStackNum(u8),
+    AndRegNum(u8, u32),
+    AndStackNum(u8),
+    OrRegNum(u8, u32),
+    OrStackNum(u8),
+    XorRegNum(u8, u32),
+    XorStackNum(u8),
+    IntEnable,
+    IntDisable,
+    IntReturn,
+    IntTable(u32),
+    TimerSet(u32),
+    ThreadSpawn(u8, u32),
+    ThreadYield,
+    ThreadJoin(u8),
+    ThreadSelf(u8),
+    Syscall,
+}
+
+/// Reads the operands of an instruction, in the order the operations
+/// themselves fetch them.
+struct Operands<'a> {
+    bytes: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Operands<'a> {
+    fn byte(&mut self) -> Result<u8, FaultKind> {
+        let byte = *self
+            .bytes
+            .get(self.len)
+            .ok_or(FaultKind::ProgramCounterOutOfBounds)?;
+        self.len += 1;
+
+        Ok(byte)
+    }
+
+    fn reg(&mut self) -> Result<u8, FaultKind> {
+        match self.byte()? {
+            reg if reg < 4 => Ok(reg),
+            reg => Err(FaultKind::InvalidRegister(reg)),
+        }
+    }
+
+    fn lit(&mut self) -> Result<u32, FaultKind> {
+        let bytes = self
+            .bytes
+            .get(self.len..self.len + 4)
+            .ok_or(FaultKind::ProgramCounterOutOfBounds)?;
+        self.len += 4;
+
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+impl Instr {
+    /// Decodes the instruction at the start of `code`, along with its length
+    /// in bytes. Instructions that would fault while fetching their operands
+    /// are left to the bytecode dispatch, so that they fault the same way.
+    pub fn decode(code: &[u8]) -> Option<(Instr, usize)> {
+        Instr::try_decode(code).ok()
+    }
+
+    /// Decodes like `decode`, but tells the fault fetching the operands
+    /// raises when the instruction does not decode.
+    pub fn try_decode(code: &[u8]) -> Result<(Instr, usize), FaultKind> {
+        let mut ops = Operands {
+            bytes: code,
+            len: 1,
+        };
+
+        let opcode = *code.first().ok_or(FaultKind::ProgramCounterOutOfBounds)?;
+        let instr = match opcode {
+            0x00 => Instr::Exit,
+            0x01 => Instr::PushLit(ops.lit()?),
+            0x02 => Instr::PushReg(ops.reg()?),
+            0x03 => Instr::PopReg(ops.reg()?),
+            0x04 => Instr::PopHeap(ops.lit()?),
+            0x05 => Instr::StackDupe,
+            0x06 => Instr::MovLitReg(ops.reg()?, ops.lit()?),
+            0x07 => Instr::MovLitHeap(ops.lit()?, ops.lit()?),
+            0x08 => Instr::MovHeapReg(ops.reg()?, ops.lit()?),
+            0x09 => Instr::MovRegHeap(ops.lit()?, ops.reg()?),
+            0x0A => Instr::MovRegReg(ops.reg()?, ops.reg()?),
+            0x0B => Instr::MovHeapHeap(ops.lit()?, ops.lit()?),
+            0x0C => Instr::PushHeap(ops.lit()?),
+            0x0D => Instr::MovPtrReg(ops.reg()?, ops.reg()?),
+            0x0E => Instr::MovRegPtr(ops.reg()?, ops.reg()?),
+            0x0F => Instr::ExitReg(ops.reg()?),
+            0x10 => Instr::AddReg(ops.reg()?, ops.reg()?),
+            0x11 => Instr::AddStack,
+            0x12 => Instr::SubReg(ops.reg()?, ops.reg()?),
+            0x13 => Instr::SubStack,
+            0x14 => Instr::MulReg(ops.reg()?, ops.reg()?),
+            0x15 => Instr::MulStack,
+            0x16 => Instr::DivReg(ops.reg()?, ops.reg()?),
+            0x17 => Instr::DivStack,
+            0x18 => Instr::NotReg(ops.reg()?),
+            0x19 => Instr::NotStack,
+            0x1A => Instr::AndReg(ops.reg()?, ops.reg()?),
+            0x1B => Instr::AndStack,
+            0x1C => Instr::OrReg(ops.reg()?, ops.reg()?),
+            0x1D => Instr::OrStack,
+            0x1E => Instr::XorReg(ops.reg()?, ops.reg()?),
+            0x1F => Instr::XorStack,
+            0x20 => Instr::Jump(ops.lit()?),
+            0x21 => Instr::Call(ops.lit()?),
+            0x22 => Instr::Ret,
+            0x30 => Instr::CompareRegReg(ops.reg()?, ops.reg()?),
+            0x31 => Instr::CompareRegLit(ops.reg()?, ops.lit()?),
+            0x32 => Instr::CompareStackLit(ops.lit()?),
+            0x33 => Instr::JumpEqual(ops.lit()?),
+            0x34 => Instr::JumpNotEqual(ops.lit()?),
+            0x35 => Instr::JumpGreater(ops.lit()?),
+            0x36 => Instr::JumpSmaller(ops.lit()?),
+            0x37 => Instr::JumpOverflow(ops.lit()?),
+            0x40 => Instr::FlagReset,
+            0x50 => Instr::IncReg(ops.reg()?),
+            0x51 => Instr::DecReg(ops.reg()?),
+            0x52 => Instr::IncStack,
+            0x53 => Instr::DecStack,
+            0x70 => Instr::AddRegNum(ops.reg()?, ops.lit()?),
+            0x71 => Instr::AddStackNum(ops.byte()?),
+            0x72 => Instr::SubRegNum(ops.reg()?, ops.lit()?),
+            0x73 => Instr::SubStackNum(ops.byte()?),
+            0x74 => Instr::MulRegNum(ops.reg()?, ops.lit()?),
+            0x75 => Instr::MulStackNum(ops.byte()?),
+            0x76 => Instr::DivRegNum(ops.reg()?, ops.lit()?),
+            0x77 => Instr::DivStackNum(ops.byte()?),
+            0x78 => Instr::AndRegNum(ops.reg()?, ops.lit()?),
+            0x79 => Instr::AndStackNum(ops.byte()?),
+            0x7A => Instr::OrRegNum(ops.reg()?, ops.lit()?),
+            0x7B => Instr::OrStackNum(ops.byte()?),
+            0x7C => Instr::XorRegNum(ops.reg()?, ops.lit()?),
+            0x7D => Instr::XorStackNum(ops.byte()?),
+            0x80 => Instr::IntEnable,
+            0x81 => Instr::IntDisable,
+            0x82 => Instr::IntReturn,
+            0x83 => Instr::IntTable(ops.lit()?),
+            0x84 => Instr::TimerSet(ops.lit()?),
+            0x90 => Instr::ThreadSpawn(ops.reg()?, ops.lit()?),
+            0x91 => Instr::ThreadYield,
+            0x92 => Instr::ThreadJoin(ops.reg()?),
+            0x93 => Instr::ThreadSelf(ops.reg()?),
+            0xFF => Instr::Syscall,
+            _ => Instr::Nop,
+        };
+
+        Ok((instr, ops.len))
+    }
+
+    /// Appends the instruction to `code` the way `decode` reads it back.
+    /// Opcodes without an operation of their own all decode as `Nop`, which
+    /// takes 0x2F.
+    pub fn encode(self, code: &mut Vec<u8>) {
+        enum Operand {
+            Byte(u8),
+            Lit(u32),
+        }
+        use Operand::{Byte, Lit};
+
+        let (opcode, operands) = match self {
+            Instr::Nop => (0x2F, vec![]),
+            Instr::Exit => (0x00, vec![]),
+            Instr::PushLit(lit) => (0x01, vec![Lit(lit)]),
+            Instr::PushReg(reg) => (0x02, vec![Byte(reg)]),
+            Instr::PopReg(reg) => (0x03, vec![Byte(reg)]),
+            Instr::PopHeap(addr) => (0x04, vec![Lit(addr)]),
+            Instr::StackDupe => (0x05, vec![]),
+            Instr::MovLitReg(reg, lit) => (0x06, vec![Byte(reg), Lit(lit)]),
+            Instr::MovLitHeap(addr, lit) => (0x07, vec![Lit(addr), Lit(lit)]),
+            Instr::MovHeapReg(reg, addr) => (0x08, vec![Byte(reg), Lit(addr)]),
+            Instr::MovRegHeap(addr, reg) => (0x09, vec![Lit(addr), Byte(reg)]),
+            Instr::MovRegReg(dst, src) => (0x0A, vec![Byte(dst), Byte(src)]),
+            Instr::MovHeapHeap(src, dst) => (0x0B, vec![Lit(src), Lit(dst)]),
+            Instr::PushHeap(addr) => (0x0C, vec![Lit(addr)]),
+            Instr::MovPtrReg(dst, ptr) => (0x0D, vec![Byte(dst), Byte(ptr)]),
+            Instr::MovRegPtr(ptr, src) => (0x0E, vec![Byte(ptr), Byte(src)]),
+            Instr::ExitReg(reg) => (0x0F, vec![Byte(reg)]),
+            Instr::AddReg(a, b) => (0x10, vec![Byte(a), Byte(b)]),
+            Instr::AddStack => (0x11, vec![]),
+            Instr::SubReg(a, b) => (0x12, vec![Byte(a), Byte(b)]),
+            Instr::SubStack => (0x13, vec![]),
+            Instr::MulReg(a, b) => (0x14, vec![Byte(a), Byte(b)]),
+            Instr::MulStack => (0x15, vec![]),
+            Instr::DivReg(a, b) => (0x16, vec![Byte(a), Byte(b)]),
+            Instr::DivStack => (0x17, vec![]),
+            Instr::NotReg(reg) => (0x18, vec![Byte(reg)]),
+            Instr::NotStack => (0x19, vec![]),
+            Instr::AndReg(a, b) => (0x1A, vec![Byte(a), Byte(b)]),
+            Instr::AndStack => (0x1B, vec![]),
+            Instr::OrReg(a, b) => (0x1C, vec![Byte(a), Byte(b)]),
+            Instr::OrStack => (0x1D, vec![]),
+            Instr::XorReg(a, b) => (0x1E, vec![Byte(a), Byte(b)]),
+            Instr::XorStack => (0x1F, vec![]),
+            Instr::Jump(addr) => (0x20, vec![Lit(addr)]),
+            Instr::Call(addr) => (0x21, vec![Lit(addr)]),
+            Instr::Ret => (0x22, vec![]),
+            Instr::CompareRegReg(a, b) => (0x30, vec![Byte(a), Byte(b)]),
+            Instr::CompareRegLit(reg, lit) => (0x31, vec![Byte(reg), Lit(lit)]),
+            Instr::CompareStackLit(lit) => (0x32, vec![Lit(lit)]),
+            Instr::JumpEqual(addr) => (0x33, vec![Lit(addr)]),
+            Instr::JumpNotEqual(addr) => (0x34, vec![Lit(addr)]),
+            Instr::JumpGreater(addr) => (0x35, vec![Lit(addr)]),
+            Instr::JumpSmaller(addr) => (0x36, vec![Lit(addr)]),
+            Instr::JumpOverflow(addr) => (0x37, vec![Lit(addr)]),
+            Instr::FlagReset => (0x40, vec![]),
+            Instr::IncReg(reg) => (0x50, vec![Byte(reg)]),
+            Instr::DecReg(reg) => (0x51, vec![Byte(reg)]),
+            Instr::IncStack => (0x52, vec![]),
+            Instr::DecStack => (0x53, vec![]),
+            Instr::AddRegNum(reg, lit) => (0x70, vec![Byte(reg), Lit(lit)]),
+            Instr::AddStackNum(num) => (0x71, vec![Byte(num)]),
+            Instr::SubRegNum(reg, lit) => (0x72, vec![Byte(reg), Lit(lit)]),
+            Instr::SubStackNum(num) => (0x73, vec![Byte(num)]),
+            Instr::MulRegNum(reg, lit) => (0x74, vec![Byte(reg), Lit(lit)]),
+            Instr::MulStackNum(num) => (0x75, vec![Byte(num)]),
+            Instr::DivRegNum(reg, lit) => (0x76, vec![Byte(reg), Lit(lit)]),
+            Instr::DivStackNum(num) => (0x77, vec![Byte(num)]),
+            Instr::AndRegNum(reg, lit) => (0x78, vec![Byte(reg), Lit(lit)]),
+            Instr::AndStackNum(num) => (0x79, vec![Byte(num)]),
+            Instr::OrRegNum(reg, lit) => (0x7A, vec![Byte(reg), Lit(lit)]),
+            Instr::OrStackNum(num) => (0x7B, vec![Byte(num)]),
+            Instr::XorRegNum(reg, lit) => (0x7C, vec![Byte(reg), Lit(lit)]),
+            Instr::XorStackNum(num) => (0x7D, vec![Byte(num)]),
+            Instr::IntEnable => (0x80, vec![]),
+            Instr::IntDisable => (0x81, vec![]),
+            Instr::IntReturn => (0x82, vec![]),
+            Instr::IntTable(addr) => (0x83, vec![Lit(addr)]),
+            Instr::TimerSet(period) => (0x84, vec![Lit(period)]),
+            Instr::ThreadSpawn(reg, addr) => (0x90, vec![Byte(reg), Lit(addr)]),
+            Instr::ThreadYield => (0x91, vec![]),
+            Instr::ThreadJoin(reg) => (0x92, vec![Byte(reg)]),
+            Instr::ThreadSelf(reg) => (0x93, vec![Byte(reg)]),
+            Instr::Syscall => (0xFF, vec![]),
+        };
+
+        code.push(opcode);
+        for operand in operands {
+            match operand {
+                Byte(byte) => code.push(byte),
+                Lit(lit) => code.extend_from_slice(&lit.to_be_bytes()),
+            }
+        }
+    }
+
+    /// The number of bytes the instruction takes up.
+    pub fn encoded_len(self) -> usize {
+        let mut code = Vec::new();
+        self.encode(&mut code);
+
+        code.len()
+    }
+
+    /// The instruction with the target `target` gives for its own.
+    pub fn retarget(self, target: impl FnOnce(u32) -> u32) -> Instr {
+        match self {
+            Instr::Jump(addr) => Instr::Jump(target(addr)),
+            Instr::Call(addr) => Instr::Call(target(addr)),
+            Instr::JumpEqual(addr) => Instr::JumpEqual(target(addr)),
+            Instr::JumpNotEqual(addr) => Instr::JumpNotEqual(target(addr)),
+            Instr::JumpGreater(addr) => Instr::JumpGreater(target(addr)),
+            Instr::JumpSmaller(addr) => Instr::JumpSmaller(target(addr)),
+            Instr::JumpOverflow(addr) => Instr::JumpOverflow(target(addr)),
+            Instr::ThreadSpawn(reg, addr) => Instr::ThreadSpawn(reg, target(addr)),
+            instr => instr,
+        }
+    }
+
+    /// Where the instruction sends the program other than to the next one,
+    /// as far as that is known without running it.
+    pub fn target(self) -> Option<usize> {
+        match self {
+            Instr::Jump(addr)
+            | Instr::Call(addr)
+            | Instr::JumpEqual(addr)
+            | Instr::JumpNotEqual(addr)
+            | Instr::JumpGreater(addr)
+            | Instr::JumpSmaller(addr)
+            | Instr::JumpOverflow(addr)
+            | Instr::ThreadSpawn(_, addr) => Some(addr as usize),
+            _ => None,
+        }
+    }
+}
+
+/// The instruction starting at every offset a translation covers, or the
+/// fault fetching its operands raises.
+pub type Reachable = BTreeMap<usize, Result<(Instr, usize), FaultKind>>;
+
+const NO_INSTR: u32 = u32::MAX;
+
+/// The code of a program decoded in a single sweep from its first byte.
+/// Jumps into the middle of an instruction, or to one that did not decode,
+/// find nothing and fall back to the bytecode dispatch.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    instrs: Vec<(Instr, usize)>,
+    /// The index of the instruction starting at each offset of the code.
+    starts: Vec<u32>,
+}
+
+impl Program {
+    pub fn decode(code: &[u8]) -> Program {
+        let mut program = Program {
+            instrs: Vec::new(),
+            starts: vec![NO_INSTR; code.len()],
+        };
+
+        let mut offset = 0;
+        while offset < code.len() {
+            match Instr::decode(&code[offset..]) {
+                Some((instr, len)) => {
+                    program.starts[offset] = program.instrs.len() as u32;
+                    program.instrs.push((instr, len));
+                    offset += len;
+                }
+                None => offset += 1,
+            }
+        }
+
+        program
+    }
+
+    /// The instruction starting at `prgrm_cntr` and its length.
+    pub fn get(&self, prgrm_cntr: usize) -> Option<(Instr, usize)> {
+        match self.starts.get(prgrm_cntr) {
+            Some(&index) if index != NO_INSTR => Some(self.instrs[index as usize]),
+            _ => None,
+        }
+    }
+
+    /// The length of the code in bytes.
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    pub fn instrs(&self) -> impl Iterator<Item = Instr> + '_ {
+        self.instrs.iter().map(|&(instr, _)| instr)
+    }
+
+    /// The instructions along with their offsets and lengths, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Instr, usize)> + '_ {
+        self.starts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &index)| index != NO_INSTR)
+            .map(move |(offset, &index)| {
+                let (instr, len) = self.instrs[index as usize];
+                (offset, instr, len)
+            })
+    }
+
+    /// Every instruction of the program, along with those that static jumps
+    /// into the middle of one lead to. Whatever is only ever reached through
+    /// a return address or an interrupt handler in memory has to be in there
+    /// already.
+    pub fn reachable(&self, code: &[u8]) -> Reachable {
+        let mut instrs = Reachable::new();
+
+        let mut pending = self.iter().map(|(offset, _, _)| offset).collect::<Vec<_>>();
+        pending.push(0);
+
+        while let Some(offset) = pending.pop() {
+            if offset >= code.len() || instrs.contains_key(&offset) {
+                continue;
+            }
+
+            let decoded = Instr::try_decode(&code[offset..]);
+            if let Ok((instr, len)) = decoded {
+                pending.push(offset + len);
+                pending.extend(instr.target());
+            }
+
+            instrs.insert(offset, decoded);
+        }
+
+        instrs
+    }
+}
+
+impl VM {
+    pub fn set_dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatch = dispatch;
+    }
+
+    fn set_overflow(&mut self, overflow: bool) {
+        if overflow {
+            self.flags.set(Flag::Overflow, true);
+        }
+    }
+
+    /// Pops two words, `a` from the top, and pushes `op(a, b)`.
+    fn stack_op(&mut self, op: impl FnOnce(&mut VM, u32, u32) -> Option<u32>) {
+        match (self.stack.pop(), self.stack.pop()) {
+            (Some(a), Some(b)) => {
+                if let Some(value) = op(self, a, b) {
+                    self.stack.push(value);
+                }
+            }
+            _ => self.raise_fault(FaultKind::StackUnderflow),
+        }
+    }
+
+    /// Pops a word and pushes `op(value)`.
+    fn stack_op_num(&mut self, op: impl FnOnce(&mut VM, u32) -> Option<u32>) {
+        match self.stack.pop() {
+            Some(value) => {
+                if let Some(value) = op(self, value) {
+                    self.stack.push(value);
+                }
+            }
+            None => self.raise_fault(FaultKind::StackUnderflow),
+        }
+    }
+
+    fn reg_op_num(&mut self, reg: u8, op: impl FnOnce(u32) -> (u32, bool)) {
+        let (value, overflow) = op(self.regs[reg as usize] as u32);
+
+        self.set_overflow(overflow);
+        self.regs[reg as usize] = value as usize;
+    }
+
+    /// Runs a decoded instruction, leaving `prgrm_cntr` on its last byte the
+    /// way fetching its operands would have.
+    pub(crate) fn execute(&mut self, instr: Instr, len: usize) {
+        self.prgrm_cntr += len - 1;
+
+        let r = |reg: u8| reg as usize;
+
+        match instr {
+            Instr::Nop => {}
+            Instr::Exit => self.exit_thread(),
+            Instr::ExitReg(reg) => {
+                if self.current_thread == 0 {
+                    self.exit_code = self.regs[r(reg)] as u32;
+                }
+                self.exit_thread();
+            }
+            Instr::PushLit(lit) => self.stack.push(lit),
+            Instr::PushReg(reg) => self.stack.push(self.regs[r(reg)] as u32),
+            Instr::PopReg(reg) => match self.stack.pop() {
+                Some(value) => self.regs[r(reg)] = value as usize,
+                None => self.raise_fault(FaultKind::StackUnderflow),
+            },
+            Instr::PopHeap(addr) => match self.stack.pop() {
+                Some(value) => self.write_heap(addr as usize, value),
+                None => self.raise_fault(FaultKind::StackUnderflow),
+            },
+            Instr::StackDupe => self.stack.push(self.stack.peek()),
+            Instr::MovLitReg(reg, lit) => self.regs[r(reg)] = lit as usize,
+            Instr::MovLitHeap(addr, lit) => self.write_heap(addr as usize, lit),
+            Instr::MovHeapReg(reg, addr) => {
+                self.regs[r(reg)] = self.heap.read(addr as usize) as usize
+            }
+            Instr::MovRegHeap(addr, reg) => {
+                self.write_heap(addr as usize, self.regs[r(reg)] as u32)
+            }
+            Instr::MovRegReg(dst, src) => self.regs[r(dst)] = self.regs[r(src)],
+            Instr::MovHeapHeap(src, dst) => {
+                let value = self.heap.read(src as usize);
+                self.write_heap(dst as usize, value);
+            }
+            Instr::PushHeap(addr) => self.stack.push(self.heap.read(addr as usize)),
+            Instr::MovPtrReg(dst, ptr) => {
+                self.regs[r(dst)] = self.heap.read(self.regs[r(ptr)]) as usize
+            }
+            Instr::MovRegPtr(ptr, src) => {
+                self.write_heap(self.regs[r(ptr)], self.regs[r(src)] as u32)
+            }
+            Instr::AddReg(a, b) => {
+                let (value, overflow) = self.regs[r(a)].overflowing_add(self.regs[r(b)]);
+                self.set_overflow(overflow);
+                self.regs[r(a)] = value;
+            }
+            Instr::SubReg(a, b) => {
+                let (value, overflow) = self.regs[r(a)].overflowing_sub(self.regs[r(b)]);
+                self.set_overflow(overflow);
+                self.regs[r(a)] = value;
+            }
+            Instr::MulReg(a, b) => {
+                let (value, overflow) = self.regs[r(a)].overflowing_mul(self.regs[r(b)]);
+                self.set_overflow(overflow);
+                self.regs[r(a)] = value;
+            }
+            Instr::DivReg(a, b) => match self.regs[r(a)].checked_div(self.regs[r(b)]) {
+                Some(value) => self.regs[r(a)] = value,
+                None => self.raise_fault(FaultKind::DivisionByZero),
+            },
+            Instr::AddStack => self.stack_op(|vm, a, b| {
+                let (value, overflow) = a.overflowing_add(b);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::SubStack => self.stack_op(|vm, a, b| {
+                let (value, overflow) = a.overflowing_sub(b);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::MulStack => self.stack_op(|vm, a, b| {
+                let (value, overflow) = a.overflowing_mul(b);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::DivStack => self.stack_op(|vm, a, b| {
+                let value = a.checked_div(b);
+                if value.is_none() {
+                    vm.raise_fault(FaultKind::DivisionByZero);
+                }
+                value
+            }),
+            Instr::NotReg(reg) => self.regs[r(reg)] = !self.regs[r(reg)],
+            Instr::NotStack => self.stack_op_num(|_, value| Some(!value)),
+            Instr::AndReg(a, b) => self.regs[r(a)] &= self.regs[r(b)],
+            Instr::AndStack => self.stack_op(|_, a, b| Some(a & b)),
+            Instr::OrReg(a, b) => self.regs[r(a)] |= self.regs[r(b)],
+            Instr::OrStack => self.stack_op(|_, a, b| Some(a | b)),
+            Instr::XorReg(a, b) => self.regs[r(a)] ^= self.regs[r(b)],
+            Instr::XorStack => self.stack_op(|_, a, b| Some(a ^ b)),
+            Instr::Jump(addr) => self.jump(addr as usize),
+            Instr::Call(addr) => {
+                self.stack.push(self.prgrm_cntr.wrapping_add(1) as u32);
+                self.stack.push(self.base_ptr);
+                self.base_ptr = self.stack.len() as u32;
+
+                self.jump(addr as usize);
+            }
+            Instr::Ret => crate::ret(self),
+            Instr::CompareRegReg(a, b) => {
+                self.compare_numbers(self.regs[r(a)] as u32, self.regs[r(b)] as u32)
+            }
+            Instr::CompareRegLit(reg, lit) => self.compare_numbers(self.regs[r(reg)] as u32, lit),
+            Instr::CompareStackLit(lit) => self.compare_numbers(self.stack.peek(), lit),
+            Instr::JumpEqual(addr) => self.jump_if(self.flags.get(Flag::Equal), addr as usize),
+            Instr::JumpNotEqual(addr) => {
+                self.jump_if(self.flags.get(Flag::NotEqual), addr as usize)
+            }
+            Instr::JumpGreater(addr) => self.jump_if(self.flags.get(Flag::Greater), addr as usize),
+            Instr::JumpSmaller(addr) => self.jump_if(self.flags.get(Flag::Smaller), addr as usize),
+            Instr::JumpOverflow(addr) => {
+                self.jump_if(self.flags.get(Flag::Overflow), addr as usize)
+            }
+            Instr::FlagReset => crate::flag_reset(self),
+            Instr::IncReg(reg) => {
+                let (value, overflow) = self.regs[r(reg)].overflowing_add(1);
+                self.set_overflow(overflow);
+                self.regs[r(reg)] = value;
+            }
+            Instr::DecReg(reg) => {
+                let (value, overflow) = self.regs[r(reg)].overflowing_sub(1);
+                self.set_overflow(overflow);
+                self.regs[r(reg)] = value;
+            }
+            Instr::IncStack => self.stack_op_num(|vm, value| {
+                let (value, overflow) = value.overflowing_add(1);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::DecStack => self.stack_op_num(|vm, value| {
+                let (value, overflow) = value.overflowing_sub(1);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::AddRegNum(reg, lit) => self.reg_op_num(reg, |value| value.overflowing_add(lit)),
+            Instr::SubRegNum(reg, lit) => self.reg_op_num(reg, |value| value.overflowing_sub(lit)),
+            Instr::MulRegNum(reg, lit) => self.reg_op_num(reg, |value| value.overflowing_mul(lit)),
+            Instr::DivRegNum(reg, lit) => match self.regs[r(reg)].checked_div(lit as usize) {
+                Some(value) => self.regs[r(reg)] = value,
+                None => self.raise_fault(FaultKind::DivisionByZero),
+            },
+            Instr::AndRegNum(reg, lit) => self.regs[r(reg)] &= lit as usize,
+            Instr::OrRegNum(reg, lit) => self.regs[r(reg)] |= lit as usize,
+            Instr::XorRegNum(reg, lit) => self.regs[r(reg)] ^= lit as usize,
+            Instr::AddStackNum(num) => self.stack_op_num(|vm, value| {
+                let (value, overflow) = (num as u32).overflowing_add(value);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::SubStackNum(num) => self.stack_op_num(|vm, value| {
+                let (value, overflow) = (num as u32).overflowing_sub(value);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::MulStackNum(num) => self.stack_op_num(|vm, value| {
+                let (value, overflow) = (num as u32).overflowing_mul(value);
+                vm.set_overflow(overflow);
+                Some(value)
+            }),
+            Instr::DivStackNum(num) => self.stack_op_num(|vm, value| {
+                let value = (num as u32).checked_div(value);
+                if value.is_none() {
+                    vm.raise_fault(FaultKind::DivisionByZero);
+                }
+                value
+            }),
+            Instr::AndStackNum(num) => self.stack_op_num(|_, value| Some(num as u32 & value)),
+            Instr::OrStackNum(num) => self.stack_op_num(|_, value| Some(num as u32 | value)),
+            Instr::XorStackNum(num) => self.stack_op_num(|_, value| Some(num as u32 ^ value)),
+            Instr::IntEnable => self.flags.set(Flag::Interrupt, true),
+            Instr::IntDisable => self.flags.set(Flag::Interrupt, false),
+            Instr::IntReturn => crate::int_return(self),
+            Instr::IntTable(addr) => self.ivt_base = addr as usize,
+            Instr::TimerSet(period) => {
+                self.timer_period = period;
+                self.timer_count = 0;
+            }
+            Instr::ThreadSpawn(reg, addr) => self.regs[r(reg)] = self.spawn_thread(addr as usize),
+            Instr::ThreadYield => self.yield_requested = true,
+            Instr::ThreadJoin(reg) => self.join_thread(self.regs[r(reg)]),
+            Instr::ThreadSelf(reg) => self.regs[r(reg)] = self.current_thread,
+            Instr::Syscall => syscall(self),
+        }
+    }
+}
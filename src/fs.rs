@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const OPEN_READ: u32 = 0x01;
+pub const OPEN_WRITE: u32 = 0x02;
+pub const OPEN_CREATE: u32 = 0x04;
+pub const OPEN_TRUNCATE: u32 = 0x08;
+pub const OPEN_APPEND: u32 = 0x10;
+
+fn escape_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "path escapes the sandbox root",
+    )
+}
+
+/// Interprets a guest path relative to the root of a filesystem, absolute or
+/// not, refusing to go above the root.
+fn normalize(path: &str) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return Err(escape_error());
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+        }
+    }
+
+    Ok(normalized)
+}
+
+pub trait FileHandle: Read + Write + Seek + Send + fmt::Debug {}
+
+impl<T: Read + Write + Seek + Send + fmt::Debug> FileHandle for T {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stat {
+    pub len: u64,
+    pub is_dir: bool,
+}
+
+/// Backend of the file I/O syscalls.
+pub trait FileSystem: Send + fmt::Debug {
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn FileHandle>>;
+
+    fn stat(&self, path: &str) -> io::Result<Stat>;
+}
+
+/// Confines guest paths to a directory on the host.
+#[derive(Debug)]
+pub struct DiskFs {
+    root: PathBuf,
+}
+
+impl DiskFs {
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<DiskFs> {
+        let root = root.as_ref().canonicalize()?;
+
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sandbox root is not a directory",
+            ));
+        }
+
+        Ok(DiskFs { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let resolved = self.root.join(normalize(path)?);
+
+        // Symlinks may still point outside of the root, so the deepest entry
+        // that already exists has to resolve to somewhere inside of it.
+        let mut existing = resolved.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            existing = match existing.parent() {
+                Some(parent) => parent,
+                None => return Err(escape_error()),
+            };
+        }
+
+        if !existing.canonicalize()?.starts_with(&self.root) {
+            return Err(escape_error());
+        }
+
+        Ok(resolved)
+    }
+}
+
+impl FileSystem for DiskFs {
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn FileHandle>> {
+        let path = self.resolve(path)?;
+
+        let file = OpenOptions::new()
+            .read(flags & OPEN_READ != 0)
+            .write(flags & OPEN_WRITE != 0)
+            .create(flags & OPEN_CREATE != 0)
+            .truncate(flags & OPEN_TRUNCATE != 0)
+            .append(flags & OPEN_APPEND != 0)
+            .open(path)?;
+
+        Ok(Box::new(file))
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Stat> {
+        let metadata = fs::metadata(self.resolve(path)?)?;
+
+        Ok(Stat {
+            len: metadata.len(),
+            is_dir: metadata.is_dir(),
+        })
+    }
+}
+
+/// How large a file in memory may grow, so that seeking far past the end
+/// before writing can't have it take up gigabytes.
+const MEM_FILE_MAX_LEN: u64 = 1 << 24;
+
+type MemFiles = Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>;
+
+/// Keeps every file in memory. Clones share the same files, so a test can
+/// keep one around to populate the filesystem before running a guest and to
+/// inspect it afterwards. Directories exist implicitly as soon as they
+/// contain a file.
+#[derive(Clone, Debug, Default)]
+pub struct MemFs {
+    files: MemFiles,
+}
+
+impl MemFs {
+    pub fn new() -> MemFs {
+        Default::default()
+    }
+
+    pub fn insert<C: Into<Vec<u8>>>(&self, path: &str, contents: C) -> io::Result<()> {
+        let path = normalize(path)?;
+        self.files.lock().unwrap().insert(path, contents.into());
+
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        let path = normalize(path).ok()?;
+        self.files.lock().unwrap().get(&path).cloned()
+    }
+
+    pub fn remove(&self, path: &str) -> Option<Vec<u8>> {
+        let path = normalize(path).ok()?;
+        self.files.lock().unwrap().remove(&path)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MemFs {
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn FileHandle>> {
+        if flags & (OPEN_READ | OPEN_WRITE | OPEN_APPEND) == 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let path = normalize(path)?;
+        let mut files = self.files.lock().unwrap();
+
+        match files.get_mut(&path) {
+            Some(contents) if flags & OPEN_TRUNCATE != 0 => contents.clear(),
+            Some(_) => {}
+            None if flags & OPEN_CREATE != 0 => {
+                files.insert(path.clone(), Vec::new());
+            }
+            None => return Err(io::ErrorKind::NotFound.into()),
+        }
+
+        Ok(Box::new(MemFile {
+            files: self.files.clone(),
+            path,
+            pos: 0,
+            flags,
+        }))
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Stat> {
+        let path = normalize(path)?;
+        let files = self.files.lock().unwrap();
+
+        if let Some(contents) = files.get(&path) {
+            return Ok(Stat {
+                len: contents.len() as u64,
+                is_dir: false,
+            });
+        }
+
+        // The root is there even before anything is put in it.
+        if path.as_os_str().is_empty() || files.keys().any(|file| file.starts_with(&path)) {
+            return Ok(Stat {
+                len: 0,
+                is_dir: true,
+            });
+        }
+
+        Err(io::ErrorKind::NotFound.into())
+    }
+}
+
+#[derive(Debug)]
+struct MemFile {
+    files: MemFiles,
+    path: PathBuf,
+    pos: u64,
+    flags: u32,
+}
+
+impl MemFile {
+    fn with_contents<T, F>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut Vec<u8>) -> T,
+    {
+        let mut files = self.files.lock().unwrap();
+
+        match files.get_mut(&self.path) {
+            Some(contents) => Ok(f(contents)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.flags & OPEN_READ == 0 {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let pos = self.pos as usize;
+        let read = self.with_contents(|contents| {
+            let available = contents.get(pos..).unwrap_or_default();
+            let read = available.len().min(buf.len());
+
+            buf[..read].copy_from_slice(&available[..read]);
+            read
+        })?;
+
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.flags & (OPEN_WRITE | OPEN_APPEND) == 0 {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let (append, pos) = (self.flags & OPEN_APPEND != 0, self.pos);
+        let end = self.with_contents(|contents| {
+            let start = if append { contents.len() as u64 } else { pos };
+            let end = start + buf.len() as u64;
+
+            if end > MEM_FILE_MAX_LEN {
+                return None;
+            }
+
+            let (start, end) = (start as usize, end as usize);
+            if contents.len() < end {
+                contents.resize(end, 0);
+            }
+            contents[start..end].copy_from_slice(buf);
+
+            Some(end)
+        })?;
+        let end = end.ok_or(io::ErrorKind::InvalidInput)?;
+
+        self.pos = end as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.with_contents(|contents| contents.len() as i64)?;
+
+        let pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => (self.pos as i64).saturating_add(offset),
+            SeekFrom::End(offset) => len.saturating_add(offset),
+        };
+
+        if pos < 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        self.pos = pos as u64;
+
+        Ok(self.pos)
+    }
+}
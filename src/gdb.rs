@@ -0,0 +1,475 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::debugger::{Debugger, StopReason};
+use crate::FaultKind;
+
+const PACKET_SIZE: usize = 0x4000;
+
+/// Instructions executed between two polls for an interrupt request while
+/// the program is running.
+const POLL_INTERVAL: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// Registers in the order of the target description: A-D, pc and flags.
+const NO_OF_REGS: usize = 6;
+const PC_REG: usize = 4;
+const FLAGS_REG: usize = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>rsvm</architecture>
+  <feature name="org.rsvm.core">
+    <flags id="rsvm_flags" size="4">
+      <field name="EQ" start="0" end="0"/>
+      <field name="NE" start="1" end="1"/>
+      <field name="GT" start="2" end="2"/>
+      <field name="LT" start="3" end="3"/>
+      <field name="OF" start="4" end="4"/>
+      <field name="STOP" start="5" end="5"/>
+      <field name="IE" start="6" end="6"/>
+    </flags>
+    <reg name="a" bitsize="32" type="uint32" regnum="0"/>
+    <reg name="b" bitsize="32" type="uint32"/>
+    <reg name="c" bitsize="32" type="uint32"/>
+    <reg name="d" bitsize="32" type="uint32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="flags" bitsize="32" type="rsvm_flags"/>
+  </feature>
+</target>
+"#;
+
+/// How a debugging session came to an end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ending {
+    /// The program ran to completion or faulted for good.
+    Finished,
+    /// The debugger let go of the program, which should keep running.
+    Detached,
+    /// The debugger asked for the program to be killed.
+    Killed,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(digits: &str) -> Option<usize> {
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn parse_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses `addr,len` as sent along with memory and breakpoint packets,
+/// refusing ranges that run past the end of the address space.
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+
+    addr.checked_add(len)?;
+    Some((addr, len))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+/// Speaks the GDB remote serial protocol over a single connection.
+///
+/// The heap is exposed as memory, byte `n` being byte `n % 4` of word
+/// `n / 4` in big-endian order, the same packing the syscalls use for
+/// strings. Breakpoints are placed on program counter values, so code and
+/// memory live in separate address spaces.
+pub struct GdbServer<'a> {
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    no_ack: bool,
+}
+
+impl<'a> GdbServer<'a> {
+    pub fn new(debugger: &'a mut Debugger, stream: TcpStream) -> GdbServer<'a> {
+        GdbServer {
+            debugger,
+            stream,
+            no_ack: false,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+
+        Ok(byte[0])
+    }
+
+    /// Waits for the next packet, or `None` for an interrupt request.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => break,
+                0x03 => return Ok(None),
+                _ => {}
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+
+        let sum = [self.read_byte()?, self.read_byte()?];
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(parse_hex)
+            .is_some_and(|sum| sum == checksum(&data) as usize);
+
+        if !self.no_ack {
+            self.stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+
+        if !valid {
+            return self.read_packet();
+        }
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+
+        if !self.no_ack {
+            // The only answer worth acting on is a request to resend.
+            while self.read_byte()? == b'-' {
+                self.stream.write_all(packet.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks for an interrupt request without waiting for one.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn stop_reply(&self, reason: &StopReason) -> String {
+        match reason {
+            StopReason::Step => format!("S{:02x}", SIGTRAP),
+            StopReason::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+            StopReason::Watchpoint { addr, .. } => {
+                format!("T{:02x}watch:{:x};", SIGTRAP, addr * 4)
+            }
+            StopReason::StartOfHistory => format!("T{:02x}replaylog:begin;", SIGTRAP),
+            StopReason::Exited(status) => format!("W{:02x}", status.process_code()),
+            // A faulted program is kept around so its state can be inspected.
+            StopReason::Faulted(fault) => {
+                let signal = match fault.kind {
+                    FaultKind::DivisionByZero => SIGFPE,
+                    FaultKind::InvalidRegister(_) => SIGILL,
+                    _ => SIGSEGV,
+                };
+
+                format!("S{:02x}", signal)
+            }
+        }
+    }
+
+    /// Runs the program until it stops, or `None` if it was interrupted.
+    fn resume(&mut self) -> io::Result<Option<StopReason>> {
+        loop {
+            match self.debugger.resume_for(POLL_INTERVAL) {
+                StopReason::Step => {}
+                reason => return Ok(Some(reason)),
+            }
+
+            if self.interrupted()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn read_reg(&self, n: usize) -> Option<u32> {
+        let vm = self.debugger.vm();
+
+        match n {
+            0..=3 => Some(vm.regs[n] as u32),
+            PC_REG => Some(vm.prgrm_cntr as u32),
+            FLAGS_REG => Some(vm.flags.bits()),
+            _ => None,
+        }
+    }
+
+    fn write_reg(&mut self, n: usize, value: u32) -> bool {
+        if n >= NO_OF_REGS {
+            return false;
+        }
+
+        let vm = self.debugger.vm_mut();
+
+        match n {
+            0..=3 => vm.regs[n] = value as usize,
+            PC_REG => vm.prgrm_cntr = value as usize,
+            FLAGS_REG => vm.flags.set_bits(value),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Vec<u8> {
+        let heap = &self.debugger.vm().heap;
+
+        (addr..addr + len.min(PACKET_SIZE / 2))
+            .map(|byte| heap.read(byte / 4).to_be_bytes()[byte % 4])
+            .collect()
+    }
+
+    /// Fails when the heap can't grow to hold all of `bytes`.
+    fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Option<()> {
+        let heap = &mut self.debugger.vm_mut().heap;
+
+        for (byte, value) in (addr..).zip(bytes) {
+            let mut word = heap.read(byte / 4).to_be_bytes();
+            word[byte % 4] = *value;
+
+            heap.write(byte / 4, u32::from_be_bytes(word)).ok()?;
+        }
+
+        Some(())
+    }
+
+    fn features(&self, args: &str) -> Option<String> {
+        let (offset, len) = parse_range(args.strip_prefix("target.xml:")?)?;
+        let xml = TARGET_XML.as_bytes();
+
+        let start = offset.min(xml.len());
+        let end = (start + len).min(xml.len());
+        let prefix = if end == xml.len() { 'l' } else { 'm' };
+
+        Some(format!(
+            "{}{}",
+            prefix,
+            String::from_utf8_lossy(&xml[start..end])
+        ))
+    }
+
+    /// Toggles a breakpoint (`Z0`) or a write watchpoint (`Z2`) on every heap
+    /// word overlapping the given byte range.
+    fn toggle_point(&mut self, args: &str, insert: bool) -> Option<()> {
+        let (kind, range) = args.split_once(',')?;
+        let (addr, len) = parse_range(range)?;
+
+        match kind {
+            "0" if insert => {
+                self.debugger.set_breakpoint(addr);
+            }
+            "0" => {
+                self.debugger.remove_breakpoint(addr);
+            }
+            "2" => {
+                for word in addr / 4..=(addr + len.max(1) - 1) / 4 {
+                    if insert {
+                        self.debugger.set_watchpoint(word);
+                    } else {
+                        self.debugger.remove_watchpoint(word);
+                    }
+                }
+            }
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Answers a packet, or returns the reason the session ended.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<Ending>> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => self.stop_reply(&StopReason::Step),
+            "g" => (0..NO_OF_REGS)
+                .filter_map(|n| self.read_reg(n))
+                .map(|value| hex(&value.to_be_bytes()))
+                .collect(),
+            "G" => match parse_bytes(args) {
+                Some(bytes) if bytes.len() == NO_OF_REGS * 4 => {
+                    for (n, value) in bytes.chunks(4).enumerate() {
+                        let value = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+                        self.write_reg(n, value);
+                    }
+
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => match parse_hex(args).and_then(|n| self.read_reg(n)) {
+                Some(value) => hex(&value.to_be_bytes()),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let value = parse_bytes(value).filter(|bytes| bytes.len() == 4)?;
+
+                    Some((parse_hex(n)?, [value[0], value[1], value[2], value[3]]))
+                });
+
+                match parsed {
+                    Some((n, value)) if self.write_reg(n, u32::from_be_bytes(value)) => {
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => hex(&self.read_memory(addr, len)),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = parse_bytes(data).filter(|bytes| bytes.len() == len)?;
+
+                    Some((addr, bytes))
+                });
+
+                match parsed.and_then(|(addr, bytes)| self.write_memory(addr, &bytes)) {
+                    Some(()) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => match self.toggle_point(args, command == "Z") {
+                Some(()) => "OK".to_string(),
+                None => String::new(),
+            },
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.debugger.vm_mut().prgrm_cntr = addr;
+                }
+
+                let reason = if command == "s" {
+                    Some(self.debugger.step())
+                } else {
+                    self.resume()?
+                };
+
+                return self.report(reason);
+            }
+            "b" => {
+                let reason = match args {
+                    "s" => self.debugger.step_back(),
+                    "c" => self.debugger.reverse_continue(),
+                    _ => return self.send("").map(|_| None),
+                };
+
+                return self.report(Some(reason));
+            }
+            "H" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(Some(Ending::Detached));
+            }
+            "k" => return Ok(Some(Ending::Killed)),
+            _ => self.query(packet),
+        };
+
+        self.send(&reply)?;
+
+        Ok(None)
+    }
+
+    fn report(&mut self, reason: Option<StopReason>) -> io::Result<Option<Ending>> {
+        match reason {
+            Some(reason) => {
+                self.send(&self.stop_reply(&reason))?;
+
+                match reason {
+                    StopReason::Exited(_) => Ok(Some(Ending::Finished)),
+                    _ => Ok(None),
+                }
+            }
+            None => {
+                self.send(&format!("S{:02x}", SIGINT))?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;\
+                 ReverseStep+;ReverseContinue+",
+                PACKET_SIZE
+            );
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:") {
+            return self.features(args).unwrap_or_else(|| "E00".to_string());
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Serves requests until the debugger detaches, kills the program or
+    /// hangs up.
+    pub fn serve(&mut self) -> io::Result<Ending> {
+        loop {
+            let packet = match self.read_packet() {
+                Ok(Some(packet)) => packet,
+                // The program is already stopped between packets.
+                Ok(None) => {
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(Ending::Detached);
+                }
+                Err(error) => return Err(error),
+            };
+
+            if let Some(ending) = self.handle(&packet)? {
+                return Ok(ending);
+            }
+        }
+    }
+}
@@ -0,0 +1,825 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::ptr;
+
+use crate::decode::{Instr, Program};
+use crate::syscall::syscall;
+use crate::VM;
+
+/// How many times an offset has to be reached before the block starting
+/// there gets compiled.
+const HOT_THRESHOLD: u32 = 64;
+/// Blocks stop after this many instructions, and ones that loop back to
+/// their own start return to the `VM` once they ran this many, so that it
+/// gets to look at interrupts now and then.
+const MAX_BLOCK_LEN: usize = 256;
+const LOOP_BUDGET: u32 = 1 << 16;
+
+const COMPILED: u32 = u32::MAX;
+const UNCOMPILABLE: u32 = u32::MAX - 1;
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// The state compiled code works on, copied out of the `VM` before a block
+/// runs and back into it afterwards.
+#[repr(C)]
+struct Context {
+    regs: [u64; 4],
+    heap_ptr: *mut u32,
+    heap_cap: u64,
+    vm: *mut VM,
+    exit_pc: u64,
+    executed: u64,
+    /// Equal, not equal, greater, smaller and overflow.
+    flags: [u8; 8],
+}
+
+const REGS: i32 = 0;
+const HEAP_PTR: i32 = 32;
+const HEAP_CAP: i32 = 40;
+const EXIT_PC: i32 = 56;
+const EXECUTED: i32 = 64;
+const FLAGS: i32 = 72;
+
+/// Equal up to overflow come first in a `FlagSet`, the rest is left alone.
+const SYNCED_FLAGS: usize = 5;
+
+impl Context {
+    fn new(vm: &mut VM) -> Context {
+        let mut ctx = Context {
+            regs: [0; 4],
+            heap_ptr: ptr::null_mut(),
+            heap_cap: 0,
+            vm: ptr::null_mut(),
+            exit_pc: 0,
+            executed: 0,
+            flags: [0; 8],
+        };
+        ctx.load_from(vm);
+        ctx.vm = vm;
+
+        ctx
+    }
+
+    fn load_from(&mut self, vm: &mut VM) {
+        for (reg, &value) in self.regs.iter_mut().zip(vm.regs.iter()) {
+            *reg = value as u64;
+        }
+        for (byte, &flag) in self.flags.iter_mut().zip(&vm.flags.0[..SYNCED_FLAGS]) {
+            *byte = flag as u8;
+        }
+
+        self.heap_ptr = vm.heap.as_mut_ptr();
+        self.heap_cap = vm.heap.capacity() as u64;
+    }
+
+    fn store_into(&self, vm: &mut VM) {
+        for (reg, &value) in vm.regs.iter_mut().zip(self.regs.iter()) {
+            *reg = value as usize;
+        }
+        for (flag, &byte) in vm.flags.0[..SYNCED_FLAGS].iter_mut().zip(&self.flags) {
+            *flag = byte != 0;
+        }
+    }
+}
+
+extern "sysv64" fn heap_read(ctx: &mut Context, addr: u64) -> u32 {
+    let vm = unsafe { &mut *ctx.vm };
+
+    vm.heap.read(addr as usize)
+}
+
+/// Tells whether the heap could grow to `addr`. A heap that can't is left
+/// to the interpreter to fault on.
+extern "sysv64" fn heap_write(ctx: &mut Context, addr: u64, value: u32) -> u32 {
+    let vm = unsafe { &mut *ctx.vm };
+    let written = vm.heap.write(addr as usize, value).is_ok();
+
+    // Growing the heap may have moved it.
+    ctx.heap_ptr = vm.heap.as_mut_ptr();
+    ctx.heap_cap = vm.heap.capacity() as u64;
+
+    written as u32
+}
+
+extern "sysv64" fn syscall_hook(ctx: &mut Context, offset: u64) {
+    let vm = unsafe { &mut *ctx.vm };
+
+    ctx.store_into(vm);
+    vm.prgrm_cntr = offset as usize;
+    vm.instr_start = offset as usize;
+
+    syscall(vm);
+
+    ctx.load_from(vm);
+}
+
+/// Machine code mapped into memory that can be executed but not written.
+#[derive(Debug)]
+struct Code {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Code {
+    fn new(bytes: &[u8]) -> Option<Code> {
+        let len = bytes.len();
+
+        unsafe {
+            let ptr = mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == MAP_FAILED {
+                return None;
+            }
+
+            let code = Code { ptr, len };
+            ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, len);
+
+            if mprotect(ptr, len, PROT_READ | PROT_EXEC) != 0 {
+                return None;
+            }
+
+            Some(code)
+        }
+    }
+
+    fn entry(&self) -> extern "sysv64" fn(&mut Context) {
+        unsafe { std::mem::transmute(self.ptr) }
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// The code is never written to once mapped.
+unsafe impl Send for Code {}
+
+#[derive(Debug)]
+struct Block {
+    code: Code,
+    /// Offset of the last instruction, where a blocked syscall has to be
+    /// retried from.
+    last: usize,
+}
+
+/// Compiled blocks, keyed by the offset they start at, along with how often
+/// each offset was reached while interpreting.
+#[derive(Debug, Default)]
+pub(crate) struct Jit {
+    counts: Vec<u32>,
+    blocks: HashMap<usize, Block>,
+}
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBP: u8 = 5;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+const R15: u8 = 15;
+
+/// Host registers holding A to D. All of them are callee-saved, so they
+/// survive calls back into Rust.
+const GUEST_REGS: [u8; 4] = [3, 12, 13, 14];
+
+fn host(reg: u8) -> u8 {
+    GUEST_REGS[reg as usize]
+}
+
+#[derive(Clone, Copy)]
+struct Label(usize);
+
+/// Just enough of an x86-64 assembler for the code the compiler emits.
+/// Instructions go through the encodings of the AMD64 manual, ModRM bytes
+/// and all.
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | rm >> 3;
+
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    /// `op rm, reg` between two registers.
+    fn op_rr(&mut self, wide: bool, opcode: u8, reg: u8, rm: u8) {
+        self.rex(wide, reg, rm);
+        self.emit(&[opcode, 0xC0 | (reg & 7) << 3 | (rm & 7)]);
+    }
+
+    /// `op [base + disp], reg` or the other way around, depending on the
+    /// opcode.
+    fn op_mem(&mut self, wide: bool, opcode: &[u8], reg: u8, base: u8, disp: i32) {
+        self.rex(wide, reg, base);
+        self.emit(opcode);
+        self.code.push(0x80 | (reg & 7) << 3 | (base & 7));
+        if base & 7 == 4 {
+            self.code.push(0x24);
+        }
+        self.emit(&disp.to_le_bytes());
+    }
+
+    /// `op rm` with the opcode extension in the reg field of the ModRM byte.
+    fn op_ext(&mut self, wide: bool, opcode: u8, ext: u8, rm: u8) {
+        self.op_rr(wide, opcode, ext, rm);
+    }
+
+    /// Zero-extends `imm` into the whole register.
+    fn mov_imm32(&mut self, dst: u8, imm: u32) {
+        self.rex(false, 0, dst);
+        self.code.push(0xB8 + (dst & 7));
+        self.emit(&imm.to_le_bytes());
+    }
+
+    fn mov_imm64(&mut self, dst: u8, imm: u64) {
+        self.rex(true, 0, dst);
+        self.code.push(0xB8 + (dst & 7));
+        self.emit(&imm.to_le_bytes());
+    }
+
+    fn mov(&mut self, dst: u8, src: u8) {
+        self.op_rr(true, 0x89, src, dst);
+    }
+
+    fn call(&mut self, addr: usize) {
+        self.mov_imm64(RAX, addr as u64);
+        self.emit(&[0xFF, 0xD0]);
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    fn jmp(&mut self, label: Label) {
+        self.code.push(0xE9);
+        self.rel32(label);
+    }
+
+    /// A conditional jump, `cc` being the low nibble of the `jcc` opcode.
+    fn jcc(&mut self, cc: u8, label: Label) {
+        self.emit(&[0x0F, 0x80 | cc]);
+        self.rel32(label);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (at, label) in self.fixups {
+            let target = self.labels[label.0].expect("unbound label");
+            let rel = target as i64 - (at as i64 + 4);
+
+            self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+
+        self.code
+    }
+}
+
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_A: u8 = 0x7;
+
+/// Compiles the straight line of instructions starting at `start`. Anything
+/// not supported ends the block right before it, jumps and syscalls end it
+/// right after them.
+struct Compiler<'a> {
+    asm: Asm,
+    program: &'a Program,
+    start: usize,
+    epilogue: Label,
+    loop_start: Label,
+}
+
+impl<'a> Compiler<'a> {
+    fn supports(instr: Instr) -> bool {
+        match instr {
+            Instr::Nop
+            | Instr::MovLitReg(..)
+            | Instr::MovRegReg(..)
+            | Instr::MovLitHeap(..)
+            | Instr::MovHeapReg(..)
+            | Instr::MovRegHeap(..)
+            | Instr::MovHeapHeap(..)
+            | Instr::MovPtrReg(..)
+            | Instr::MovRegPtr(..)
+            | Instr::AddReg(..)
+            | Instr::SubReg(..)
+            | Instr::MulReg(..)
+            | Instr::DivReg(..)
+            | Instr::NotReg(_)
+            | Instr::AndReg(..)
+            | Instr::OrReg(..)
+            | Instr::XorReg(..)
+            | Instr::IncReg(_)
+            | Instr::DecReg(_)
+            | Instr::AddRegNum(..)
+            | Instr::SubRegNum(..)
+            | Instr::MulRegNum(..)
+            | Instr::AndRegNum(..)
+            | Instr::OrRegNum(..)
+            | Instr::XorRegNum(..)
+            | Instr::CompareRegReg(..)
+            | Instr::CompareRegLit(..)
+            | Instr::Jump(_)
+            | Instr::JumpEqual(_)
+            | Instr::JumpNotEqual(_)
+            | Instr::JumpGreater(_)
+            | Instr::JumpSmaller(_)
+            | Instr::JumpOverflow(_)
+            | Instr::Syscall => true,
+            // Dividing by a zero literal is left to the interpreter to fault.
+            Instr::DivRegNum(_, lit) => lit != 0,
+            _ => false,
+        }
+    }
+
+    fn ends_block(instr: Instr) -> bool {
+        matches!(
+            instr,
+            Instr::Jump(_)
+                | Instr::JumpEqual(_)
+                | Instr::JumpNotEqual(_)
+                | Instr::JumpGreater(_)
+                | Instr::JumpSmaller(_)
+                | Instr::JumpOverflow(_)
+                | Instr::Syscall
+        )
+    }
+
+    /// Leaves the block for `prgrm_cntr`, with `executed` more instructions
+    /// run since the last time around the loop.
+    fn exit(&mut self, prgrm_cntr: usize, executed: usize) {
+        let asm = &mut self.asm;
+
+        // lea rax, [rbp + executed]
+        asm.op_mem(true, &[0x8D], RAX, RBP, executed as i32);
+        asm.op_mem(true, &[0x89], RAX, R15, EXECUTED);
+        asm.mov_imm64(RAX, prgrm_cntr as u64);
+        asm.op_mem(true, &[0x89], RAX, R15, EXIT_PC);
+        asm.jmp(self.epilogue);
+    }
+
+    /// Carries on at `target`, looping back without leaving the block when
+    /// that is where it started.
+    fn branch(&mut self, target: usize, executed: usize) {
+        if target != self.start {
+            return self.exit(target, executed);
+        }
+
+        // add rbp, executed; cmp rbp, LOOP_BUDGET; jb loop_start
+        self.asm.op_ext(true, 0x81, 0, RBP);
+        self.asm.emit(&(executed as u32).to_le_bytes());
+        self.asm.op_ext(true, 0x81, 7, RBP);
+        self.asm.emit(&LOOP_BUDGET.to_le_bytes());
+        self.asm.jcc(CC_B, self.loop_start);
+
+        self.exit(target, 0);
+    }
+
+    /// ORs the carry flag of the host into the overflow flag of the guest,
+    /// which is only ever set by arithmetic, never cleared.
+    fn carry_to_overflow(&mut self) {
+        // setc al; or [r15 + FLAGS + 4], al
+        self.asm.emit(&[0x0F, 0x92, 0xC0]);
+        self.asm.op_mem(false, &[0x08], RAX, R15, FLAGS + 4);
+    }
+
+    fn set_flag(&mut self, cc: u8, flag: i32) {
+        self.asm
+            .op_mem(false, &[0x0F, 0x90 | cc], 0, R15, FLAGS + flag);
+    }
+
+    /// Reads the heap word whose address is in RAX into EAX.
+    fn heap_read(&mut self) {
+        let (slow, done) = (self.asm.label(), self.asm.label());
+        let asm = &mut self.asm;
+
+        asm.op_mem(true, &[0x3B], RAX, R15, HEAP_CAP);
+        asm.jcc(CC_AE, slow);
+        asm.op_mem(true, &[0x8B], RCX, R15, HEAP_PTR);
+        // mov eax, [rcx + rax * 4]
+        asm.emit(&[0x8B, 0x04, 0x81]);
+        asm.jmp(done);
+
+        asm.bind(slow);
+        asm.mov(RSI, RAX);
+        asm.mov(RDI, R15);
+        asm.call(heap_read as *const () as usize);
+        // Only EAX is defined on return, so clear the rest of RAX.
+        asm.op_rr(false, 0x89, RAX, RAX);
+
+        asm.bind(done);
+    }
+
+    /// Writes EDX to the heap word whose address is in RAX, for the
+    /// instruction at `offset`, the `i`th of the block. When the heap can't
+    /// grow that far, the block is left before the instruction for the
+    /// interpreter to fault on.
+    fn heap_write(&mut self, offset: usize, i: usize) {
+        let (slow, done) = (self.asm.label(), self.asm.label());
+        let asm = &mut self.asm;
+
+        asm.op_mem(true, &[0x3B], RAX, R15, HEAP_CAP);
+        asm.jcc(CC_AE, slow);
+        asm.op_mem(true, &[0x8B], RCX, R15, HEAP_PTR);
+        // mov [rcx + rax * 4], edx
+        asm.emit(&[0x89, 0x14, 0x81]);
+        asm.jmp(done);
+
+        asm.bind(slow);
+        asm.mov(RSI, RAX);
+        asm.mov(RDI, R15);
+        asm.call(heap_write as *const () as usize);
+        asm.op_rr(false, 0x85, RAX, RAX);
+        asm.jcc(CC_NE, done);
+        self.exit(offset, i);
+
+        self.asm.bind(done);
+    }
+
+    fn store_regs(&mut self) {
+        for (i, &reg) in GUEST_REGS.iter().enumerate() {
+            self.asm
+                .op_mem(true, &[0x89], reg, R15, REGS + 8 * i as i32);
+        }
+    }
+
+    fn load_regs(&mut self) {
+        for (i, &reg) in GUEST_REGS.iter().enumerate() {
+            self.asm
+                .op_mem(true, &[0x8B], reg, R15, REGS + 8 * i as i32);
+        }
+    }
+
+    /// Compiles the instruction at `offset`, the `i`th of the block.
+    fn instr(&mut self, instr: Instr, offset: usize, len: usize, i: usize) {
+        let next = offset + len;
+
+        match instr {
+            Instr::Nop => {}
+            Instr::MovLitReg(reg, lit) => self.asm.mov_imm32(host(reg), lit),
+            Instr::MovRegReg(dst, src) => self.asm.mov(host(dst), host(src)),
+            Instr::MovLitHeap(addr, lit) => {
+                self.asm.mov_imm32(RAX, addr);
+                self.asm.mov_imm32(RDX, lit);
+                self.heap_write(offset, i);
+            }
+            Instr::MovHeapReg(reg, addr) => {
+                self.asm.mov_imm32(RAX, addr);
+                self.heap_read();
+                self.asm.mov(host(reg), RAX);
+            }
+            Instr::MovRegHeap(addr, reg) => {
+                self.asm.mov_imm32(RAX, addr);
+                self.asm.op_rr(false, 0x89, host(reg), RDX);
+                self.heap_write(offset, i);
+            }
+            Instr::MovHeapHeap(src, dst) => {
+                self.asm.mov_imm32(RAX, src);
+                self.heap_read();
+                self.asm.op_rr(false, 0x89, RAX, RDX);
+                self.asm.mov_imm32(RAX, dst);
+                self.heap_write(offset, i);
+            }
+            Instr::MovPtrReg(dst, ptr) => {
+                self.asm.mov(RAX, host(ptr));
+                self.heap_read();
+                self.asm.mov(host(dst), RAX);
+            }
+            Instr::MovRegPtr(ptr, src) => {
+                self.asm.mov(RAX, host(ptr));
+                self.asm.op_rr(false, 0x89, host(src), RDX);
+                self.heap_write(offset, i);
+            }
+            Instr::AddReg(a, b) => {
+                self.asm.op_rr(true, 0x01, host(b), host(a));
+                self.carry_to_overflow();
+            }
+            Instr::SubReg(a, b) => {
+                self.asm.op_rr(true, 0x29, host(b), host(a));
+                self.carry_to_overflow();
+            }
+            Instr::MulReg(a, b) => {
+                self.asm.mov(RAX, host(a));
+                self.asm.op_ext(true, 0xF7, 4, host(b));
+                self.asm.mov(host(a), RAX);
+                self.carry_to_overflow();
+            }
+            Instr::DivReg(a, b) => {
+                // Division by zero leaves the block before the instruction,
+                // for the interpreter to fault on.
+                let divide = self.asm.label();
+                self.asm.op_rr(true, 0x85, host(b), host(b));
+                self.asm.jcc(CC_NE, divide);
+                self.exit(offset, i);
+
+                self.asm.bind(divide);
+                self.asm.mov(RAX, host(a));
+                self.asm.op_rr(false, 0x31, RDX, RDX);
+                self.asm.op_ext(true, 0xF7, 6, host(b));
+                self.asm.mov(host(a), RAX);
+            }
+            Instr::NotReg(reg) => self.asm.op_ext(true, 0xF7, 2, host(reg)),
+            Instr::AndReg(a, b) => self.asm.op_rr(true, 0x21, host(b), host(a)),
+            Instr::OrReg(a, b) => self.asm.op_rr(true, 0x09, host(b), host(a)),
+            Instr::XorReg(a, b) => self.asm.op_rr(true, 0x31, host(b), host(a)),
+            Instr::IncReg(reg) | Instr::DecReg(reg) => {
+                // add or sub 1, as inc and dec leave the carry alone.
+                let ext = if let Instr::IncReg(_) = instr { 0 } else { 5 };
+                self.asm.op_ext(true, 0x83, ext, host(reg));
+                self.asm.code.push(1);
+                self.carry_to_overflow();
+            }
+            Instr::AddRegNum(reg, lit) | Instr::SubRegNum(reg, lit) => {
+                // The 32-bit operation clears the upper half, like the cast
+                // to u32 does.
+                let opcode = if let Instr::AddRegNum(..) = instr {
+                    0x05
+                } else {
+                    0x2D
+                };
+                self.asm.op_rr(false, 0x89, host(reg), RAX);
+                self.asm.code.push(opcode);
+                self.asm.emit(&lit.to_le_bytes());
+                self.asm.mov(host(reg), RAX);
+                self.carry_to_overflow();
+            }
+            Instr::MulRegNum(reg, lit) => {
+                self.asm.op_rr(false, 0x89, host(reg), RAX);
+                self.asm.mov_imm32(RCX, lit);
+                self.asm.op_ext(false, 0xF7, 4, RCX);
+                self.asm.mov(host(reg), RAX);
+                self.carry_to_overflow();
+            }
+            Instr::DivRegNum(reg, lit) => {
+                self.asm.mov(RAX, host(reg));
+                self.asm.op_rr(false, 0x31, RDX, RDX);
+                self.asm.mov_imm32(RCX, lit);
+                self.asm.op_ext(true, 0xF7, 6, RCX);
+                self.asm.mov(host(reg), RAX);
+            }
+            Instr::AndRegNum(reg, lit) | Instr::OrRegNum(reg, lit) | Instr::XorRegNum(reg, lit) => {
+                let opcode = match instr {
+                    Instr::AndRegNum(..) => 0x21,
+                    Instr::OrRegNum(..) => 0x09,
+                    _ => 0x31,
+                };
+                self.asm.mov_imm32(RAX, lit);
+                self.asm.op_rr(true, opcode, RAX, host(reg));
+            }
+            Instr::CompareRegReg(..) | Instr::CompareRegLit(..) => {
+                match instr {
+                    Instr::CompareRegReg(a, b) => self.asm.op_rr(false, 0x39, host(b), host(a)),
+                    Instr::CompareRegLit(reg, lit) => {
+                        self.asm.op_ext(false, 0x81, 7, host(reg));
+                        self.asm.emit(&lit.to_le_bytes());
+                    }
+                    _ => unreachable!(),
+                }
+
+                self.set_flag(CC_E, 0);
+                self.set_flag(CC_NE, 1);
+                self.set_flag(CC_A, 2);
+                self.set_flag(CC_B, 3);
+            }
+            Instr::Jump(addr) => self.branch(addr as usize, i + 1),
+            Instr::JumpEqual(addr)
+            | Instr::JumpNotEqual(addr)
+            | Instr::JumpGreater(addr)
+            | Instr::JumpSmaller(addr)
+            | Instr::JumpOverflow(addr) => {
+                let flag = match instr {
+                    Instr::JumpEqual(_) => 0,
+                    Instr::JumpNotEqual(_) => 1,
+                    Instr::JumpGreater(_) => 2,
+                    Instr::JumpSmaller(_) => 3,
+                    _ => 4,
+                };
+                let not_taken = self.asm.label();
+
+                // cmp byte [r15 + FLAGS + flag], 0
+                self.asm.op_mem(false, &[0x80], 7, R15, FLAGS + flag);
+                self.asm.code.push(0);
+                self.asm.jcc(CC_E, not_taken);
+                self.branch(addr as usize, i + 1);
+
+                self.asm.bind(not_taken);
+                self.exit(next, i + 1);
+            }
+            Instr::Syscall => {
+                self.store_regs();
+                self.asm.mov(RDI, R15);
+                self.asm.mov_imm64(RSI, offset as u64);
+                self.asm.call(syscall_hook as *const () as usize);
+                self.load_regs();
+
+                self.exit(next, i + 1);
+            }
+            _ => unreachable!("unsupported instructions end the block"),
+        }
+    }
+
+    fn compile(program: &Program, start: usize) -> Option<Block> {
+        let mut asm = Asm::default();
+        let (epilogue, loop_start) = (asm.label(), asm.label());
+
+        // Save the callee-saved registers, keeping the stack 16-byte aligned
+        // for the calls back into Rust.
+        for &reg in &[3, RBP, 12, 13, 14, R15] {
+            asm.rex(false, 0, reg);
+            asm.code.push(0x50 + (reg & 7));
+        }
+        asm.emit(&[0x48, 0x83, 0xEC, 0x08]);
+
+        let mut compiler = Compiler {
+            asm,
+            program,
+            start,
+            epilogue,
+            loop_start,
+        };
+        compiler.asm.mov(R15, RDI);
+        compiler.asm.op_rr(false, 0x31, RBP, RBP);
+        compiler.load_regs();
+        compiler.asm.bind(loop_start);
+
+        let (mut offset, mut last, mut i) = (start, start, 0);
+        loop {
+            let (instr, len) = match compiler.program.get(offset) {
+                Some((instr, len)) if Compiler::supports(instr) && i < MAX_BLOCK_LEN => {
+                    (instr, len)
+                }
+                _ => {
+                    compiler.exit(offset, i);
+                    break;
+                }
+            };
+
+            compiler.instr(instr, offset, len, i);
+            last = offset;
+            offset += len;
+            i += 1;
+
+            if Compiler::ends_block(instr) {
+                break;
+            }
+        }
+
+        if i == 0 {
+            return None;
+        }
+
+        let mut asm = compiler.asm;
+        asm.bind(epilogue);
+        for (i, &reg) in GUEST_REGS.iter().enumerate() {
+            asm.op_mem(true, &[0x89], reg, R15, REGS + 8 * i as i32);
+        }
+        asm.emit(&[0x48, 0x83, 0xC4, 0x08]);
+        for &reg in [3, RBP, 12, 13, 14, R15].iter().rev() {
+            asm.rex(false, 0, reg);
+            asm.code.push(0x58 + (reg & 7));
+        }
+        asm.code.push(0xC3);
+
+        Some(Block {
+            code: Code::new(&asm.finish())?,
+            last,
+        })
+    }
+}
+
+impl VM {
+    /// Whether running a whole block at once is the same as stepping through
+    /// it. Timers and time slices count single instructions, and the hooks
+    /// want to see every one of them.
+    fn can_run_compiled(&self) -> bool {
+        self.timer_period == 0
+            && (self.time_slice == 0 || self.threads.len() < 2)
+            && self.trace.is_none()
+            && self.profile.is_none()
+            && self.coverage.is_none()
+            && self.heap.journal.is_none()
+    }
+
+    /// Runs the compiled block starting at `prgrm_cntr`, compiling it first if
+    /// it got hot. Returns whether it did, otherwise the instruction there is
+    /// left to the interpreter.
+    pub(crate) fn run_compiled(&mut self) -> bool {
+        if !self.can_run_compiled() {
+            return false;
+        }
+
+        let start = self.prgrm_cntr;
+        let jit = &mut self.jit;
+
+        if jit.counts.len() != self.decoded.len() {
+            jit.counts = vec![0; self.decoded.len()];
+            jit.blocks.clear();
+        }
+
+        let count = match jit.counts.get_mut(start) {
+            Some(count) => count,
+            None => return false,
+        };
+
+        match *count {
+            COMPILED => {}
+            UNCOMPILABLE => return false,
+            _ if *count + 1 < HOT_THRESHOLD => {
+                *count += 1;
+                return false;
+            }
+            _ => match Compiler::compile(&self.decoded, start) {
+                Some(block) => {
+                    *count = COMPILED;
+                    jit.blocks.insert(start, block);
+                }
+                None => {
+                    *count = UNCOMPILABLE;
+                    return false;
+                }
+            },
+        }
+
+        let block = &self.jit.blocks[&start];
+        let (entry, last) = (block.code.entry(), block.last);
+
+        self.blocked = false;
+
+        let mut ctx = Context::new(self);
+        entry(&mut ctx);
+        ctx.store_into(self);
+
+        let executed = ctx.executed as u32;
+        if executed == 0 {
+            return false;
+        }
+
+        if self.fault.is_some() {
+            // The interpreter does not count the instruction that faulted.
+            self.clock.tick_n(executed - 1);
+            return true;
+        }
+
+        // Nor the syscall that blocked, which is going to be retried.
+        if self.blocked {
+            self.clock.tick_n(executed - 1);
+            self.prgrm_cntr = last;
+            self.yield_requested = self.threads.len() > 1;
+        } else {
+            self.clock.tick_n(executed);
+            self.prgrm_cntr = ctx.exit_pc as usize;
+        }
+
+        if self.yield_requested {
+            self.schedule();
+        }
+        self.service_interrupts();
+
+        true
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt::{self, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// How deeply arrays and objects may nest, so that a hostile message can't
+/// overflow the stack of the parser.
+const MAX_DEPTH: usize = 64;
+
+/// Just enough JSON for the debug adapter protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn parse(text: &str) -> Option<Json> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars, 0)?;
+
+        skip_whitespace(&mut chars);
+
+        match chars.next() {
+            Some(_) => None,
+            None => Some(value),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as u64),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(elems) => elems,
+            _ => &[],
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elems: Vec<Json>) -> Json {
+        Json::Array(elems)
+    }
+}
+
+/// Builds a `Json::Object` out of `key => value` pairs.
+macro_rules! object {
+    ($($key:expr => $value:expr),* $(,)?) => {
+        $crate::json::Json::Object(vec![$(($key.to_string(), $crate::json::Json::from($value))),*])
+    };
+}
+
+pub(crate) use object;
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    f.write_char('"')?;
+
+    for c in string.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    f.write_char('"')
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Json::Number(_) => f.write_str("null"),
+            Json::String(string) => write_string(f, string),
+            Json::Array(elems) => {
+                f.write_char('[')?;
+
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+
+                f.write_char(']')
+            }
+            Json::Object(members) => {
+                f.write_char('{')?;
+
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars<'_>>, word: &str) -> Option<()> {
+    for expected in word.chars() {
+        if chars.next()? != expected {
+            return None;
+        }
+    }
+
+    Some(())
+}
+
+fn parse_value(chars: &mut Peekable<Chars<'_>>, depth: usize) -> Option<Json> {
+    skip_whitespace(chars);
+
+    match *chars.peek()? {
+        'n' => expect(chars, "null").map(|_| Json::Null),
+        't' => expect(chars, "true").map(|_| Json::Bool(true)),
+        'f' => expect(chars, "false").map(|_| Json::Bool(false)),
+        '"' => parse_string(chars).map(Json::String),
+        '[' => parse_array(chars, depth + 1),
+        '{' => parse_object(chars, depth + 1),
+        _ => parse_number(chars),
+    }
+}
+
+fn parse_number(chars: &mut Peekable<Chars<'_>>) -> Option<Json> {
+    let mut number = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+            break;
+        }
+
+        number.push(c);
+        chars.next();
+    }
+
+    number.parse().ok().map(Json::Number)
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars<'_>>) -> Option<u32> {
+    let digits = chars.by_ref().take(4).collect::<String>();
+
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(&digits, 16).ok()
+}
+
+fn parse_string(chars: &mut Peekable<Chars<'_>>) -> Option<String> {
+    let mut string = String::new();
+    chars.next();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => match chars.next()? {
+                'n' => string.push('\n'),
+                'r' => string.push('\r'),
+                't' => string.push('\t'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let mut code = parse_hex4(chars)?;
+
+                    // Characters outside of the BMP come as a surrogate pair.
+                    if (0xd800..0xdc00).contains(&code) {
+                        expect(chars, "\\u")?;
+                        let low = parse_hex4(chars)?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return None;
+                        }
+
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                    }
+
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => string.push(c),
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+fn parse_array(chars: &mut Peekable<Chars<'_>>, depth: usize) -> Option<Json> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let mut elems = Vec::new();
+    chars.next();
+
+    skip_whitespace(chars);
+    if chars.peek() == Some(&']') {
+        chars.next();
+        return Some(Json::Array(elems));
+    }
+
+    loop {
+        elems.push(parse_value(chars, depth)?);
+        skip_whitespace(chars);
+
+        match chars.next()? {
+            ',' => {}
+            ']' => return Some(Json::Array(elems)),
+            _ => return None,
+        }
+    }
+}
+
+fn parse_object(chars: &mut Peekable<Chars<'_>>, depth: usize) -> Option<Json> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let mut members = Vec::new();
+    chars.next();
+
+    skip_whitespace(chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return Some(Json::Object(members));
+    }
+
+    loop {
+        skip_whitespace(chars);
+        if chars.peek() != Some(&'"') {
+            return None;
+        }
+
+        let key = parse_string(chars)?;
+        skip_whitespace(chars);
+        expect(chars, ":")?;
+
+        members.push((key, parse_value(chars, depth)?));
+        skip_whitespace(chars);
+
+        match chars.next()? {
+            ',' => {}
+            '}' => return Some(Json::Object(members)),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let value = object! {
+            "null" => Json::Null,
+            "bool" => true,
+            "number" => 42u64,
+            "negative" => Json::Number(-1.5e3),
+            "string" => "say \"hi\"\\\n\r\t\u{1}é😀",
+            "array" => vec![Json::from(1u64), Json::Array(vec![]), object! {}],
+        };
+
+        assert_eq!(Json::parse(&value.to_string()), Some(value));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let string = Json::from("\"\\\n\r\t\u{1f}/é");
+
+        assert_eq!(string.to_string(), r#""\"\\\n\r\t\u001f/é""#);
+    }
+
+    #[test]
+    fn escapes_are_parsed() {
+        let string = r#""\"\\\/\b\f\n\r\t\u0041\u00e9""#;
+
+        assert_eq!(
+            Json::parse(string),
+            Some(Json::from("\"\\/\u{8}\u{c}\n\r\tAé"))
+        );
+    }
+
+    #[test]
+    fn surrogate_pairs_make_one_character() {
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Some(Json::from("😀")));
+        assert_eq!(Json::parse(r#""\uD83D\uDE00""#), Some(Json::from("😀")));
+
+        // A high surrogate has to be followed by a low one.
+        assert_eq!(Json::parse(r#""\ud83d""#), None);
+        assert_eq!(Json::parse(r#""\ud83dx""#), None);
+        assert_eq!(Json::parse(r#""\ud83d\u0041""#), None);
+        assert_eq!(Json::parse(r#""\ud83d\ud83d""#), None);
+
+        // A low one on its own isn't a character at all.
+        assert_eq!(Json::parse(r#""\ude00""#), Some(Json::from("\u{fffd}")));
+    }
+
+    #[test]
+    fn unicode_escapes_take_four_hex_digits() {
+        assert_eq!(Json::parse(r#""\u+041""#), None);
+        assert_eq!(Json::parse(r#""\u41""#), None);
+    }
+
+    #[test]
+    fn malformed_text_is_refused() {
+        for text in [
+            "",
+            "nul",
+            "[1,]",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{1: 2}",
+            "\"open",
+            "1 2",
+            "{}}",
+        ] {
+            assert_eq!(Json::parse(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_some());
+        assert_eq!(Json::parse(&nested(MAX_DEPTH + 1)), None);
+        assert_eq!(Json::parse(&"{\"a\":".repeat(100_000)), None);
+    }
+}
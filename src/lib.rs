@@ -1,60 +1,49 @@
-#![feature(ptr_internals)]
-
 use std::io::{self, Read, Write};
-use std::process::Command;
+
+pub mod channel;
+pub mod clock;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod debuginfo;
+pub mod decode;
+pub mod fs;
+pub mod gdb;
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+mod jit;
+mod json;
+pub mod opt;
+pub mod profile;
+pub mod random;
+pub mod replay;
+pub mod scheduler;
+pub mod syscall;
+mod thread;
+pub mod to_c;
+pub mod to_wat;
+
+use channel::Channel;
+use clock::Clock;
+use debuginfo::{DebugInfo, Location};
+use decode::{Dispatch, Program};
+use fs::{DiskFs, FileHandle, FileSystem};
+use random::Rng;
+use syscall::syscall;
+use thread::Thread;
 
 fn nop(_vm: &mut VM) {}
 
 fn exit(vm: &mut VM) {
-    vm.flags.set(Flag::Stop, true);
+    vm.exit_thread();
 }
 
-fn syscall(vm: &mut VM) {
-    match vm.regs[Register::A as usize] {
-        0 => {
-            let ptr = vm.regs[1];
-            let len = vm.regs[2];
-
-            let mut buf = Vec::with_capacity(len * 4);
-            let mut stdout = io::stdout();
-
-            for i in 0..len {
-                let value = vm.heap.read(ptr + i).to_be_bytes();
-                buf[i * 4] = value[0];
-                buf[i * 4 + 1] = value[1];
-                buf[i * 4 + 2] = value[2];
-                buf[i * 4 + 3] = value[3];
-            }
-
-            if stdout.write(&buf).is_err() {
-                panic!("Could not proceed with syscall to write to stdout!");
-            }
-        }
-        1 => {
-            let ptr = vm.regs[1];
-            let len = vm.regs[2];
-
-            let mut buf = Vec::with_capacity(len);
-            let mut stdin = io::stdin();
-
-            if stdin.read(&mut buf).is_err() {
-                panic!("Could not proceed with syscall to read from stdin!");
-            }
-
-            for (i, value) in buf.iter().enumerate() {
-                vm.heap.write(ptr + i, *value as u32);
-            }
-        }
-        2 => {
-            let command = if cfg!(windows) { "cls" } else { "clear" };
+fn exit_reg(vm: &mut VM) {
+    let reg = vm.fetch_reg();
 
-            if Command::new(command).output().is_err() {
-                panic!("Could not proceed with syscall to clear screen!");
-            }
-        }
-        3 => println!("{}", vm.regs[Register::B as usize]),
-        _ => {}
+    if vm.current_thread == 0 {
+        vm.exit_code = vm.regs[reg as usize] as u32;
     }
+    vm.exit_thread();
 }
 
 fn push_lit(vm: &mut VM) {
@@ -72,7 +61,7 @@ fn pop_reg(vm: &mut VM) {
 
     match value {
         Some(value) => vm.regs[reg as usize] = value as usize,
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -80,8 +69,8 @@ fn pop_heap(vm: &mut VM) {
     let (addr, value) = (vm.fetch_lit(), vm.stack.pop());
 
     match value {
-        Some(value) => vm.heap.write(addr as usize, value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        Some(value) => vm.write_heap(addr as usize, value),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -99,7 +88,7 @@ fn mov_lit_heap(vm: &mut VM) {
     let addr = vm.fetch_lit();
     let value = vm.fetch_lit();
 
-    vm.heap.write(addr as usize, value);
+    vm.write_heap(addr as usize, value);
 }
 
 fn mov_heap_reg(vm: &mut VM) {
@@ -111,7 +100,7 @@ fn mov_heap_reg(vm: &mut VM) {
 fn mov_reg_heap(vm: &mut VM) {
     let (addr, reg) = (vm.fetch_lit(), vm.fetch_reg());
 
-    vm.heap.write(addr as usize, vm.regs[reg as usize] as u32);
+    vm.write_heap(addr as usize, vm.regs[reg as usize] as u32);
 }
 
 fn mov_reg_reg(vm: &mut VM) {
@@ -124,7 +113,19 @@ fn mov_heap_heap(vm: &mut VM) {
     let (addr_src, addr_dst) = (vm.fetch_lit(), vm.fetch_lit());
 
     let value = vm.heap.read(addr_src as usize);
-    vm.heap.write(addr_dst as usize, value);
+    vm.write_heap(addr_dst as usize, value);
+}
+
+fn mov_ptr_reg(vm: &mut VM) {
+    let (reg_dst, reg_ptr) = (vm.fetch_reg(), vm.fetch_reg());
+
+    vm.regs[reg_dst as usize] = vm.heap.read(vm.regs[reg_ptr as usize]) as usize;
+}
+
+fn mov_reg_ptr(vm: &mut VM) {
+    let (reg_ptr, reg_src) = (vm.fetch_reg(), vm.fetch_reg());
+
+    vm.write_heap(vm.regs[reg_ptr as usize], vm.regs[reg_src as usize] as u32);
 }
 
 fn push_heap(vm: &mut VM) {
@@ -136,12 +137,13 @@ fn push_heap(vm: &mut VM) {
 
 fn math_add_reg(vm: &mut VM) {
     let (a, b) = (vm.fetch_reg(), vm.fetch_reg());
+    let (value, overflow) = vm.regs[a as usize].overflowing_add(vm.regs[b as usize]);
 
-    if vm.regs[a as usize] > usize::MAX - vm.regs[b as usize] {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[a as usize] += vm.regs[b as usize];
+    vm.regs[a as usize] = value;
 }
 
 fn math_add_stack(vm: &mut VM) {
@@ -149,24 +151,27 @@ fn math_add_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => {
-            if a > u32::MAX - b {
+            let (value, overflow) = a.overflowing_add(b);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(a + b);
+            vm.stack.push(value);
         }
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_sub_reg(vm: &mut VM) {
     let (a, b) = (vm.fetch_reg(), vm.fetch_reg());
+    let (value, overflow) = vm.regs[a as usize].overflowing_sub(vm.regs[b as usize]);
 
-    if vm.regs[a as usize] < vm.regs[b as usize] {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[a as usize] -= vm.regs[b as usize];
+    vm.regs[a as usize] = value;
 }
 
 fn math_sub_stack(vm: &mut VM) {
@@ -174,24 +179,27 @@ fn math_sub_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => {
-            if a < b {
+            let (value, overflow) = a.overflowing_sub(b);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(a - b);
+            vm.stack.push(value);
         }
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_mul_reg(vm: &mut VM) {
     let (a, b) = (vm.fetch_reg(), vm.fetch_reg());
+    let (value, overflow) = vm.regs[a as usize].overflowing_mul(vm.regs[b as usize]);
 
-    if vm.regs[a as usize] > usize::MAX / vm.regs[b as usize] {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[a as usize] *= vm.regs[b as usize];
+    vm.regs[a as usize] = value;
 }
 
 fn math_mul_stack(vm: &mut VM) {
@@ -199,28 +207,36 @@ fn math_mul_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => {
-            if a > u32::MAX / b {
+            let (value, overflow) = a.overflowing_mul(b);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(a * b);
+            vm.stack.push(value);
         }
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_div_reg(vm: &mut VM) {
     let (a, b) = (vm.fetch_reg(), vm.fetch_reg());
 
-    vm.regs[a as usize] /= vm.regs[b as usize];
+    match vm.regs[a as usize].checked_div(vm.regs[b as usize]) {
+        Some(value) => vm.regs[a as usize] = value,
+        None => vm.raise_fault(FaultKind::DivisionByZero),
+    }
 }
 
 fn math_div_stack(vm: &mut VM) {
     let (a, b) = (vm.stack.pop(), vm.stack.pop());
 
     match (a, b) {
-        (Some(a), Some(b)) => vm.stack.push(a / b),
-        _ => panic!("Could not pop the stack as it's empty!"),
+        (Some(a), Some(b)) => match a.checked_div(b) {
+            Some(value) => vm.stack.push(value),
+            None => vm.raise_fault(FaultKind::DivisionByZero),
+        },
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -234,7 +250,7 @@ fn math_not_stack(vm: &mut VM) {
 
     match value {
         Some(value) => vm.stack.push(!value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -248,7 +264,7 @@ fn math_and_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => vm.stack.push(a & b),
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -262,7 +278,7 @@ fn math_or_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => vm.stack.push(a | b),
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -276,7 +292,7 @@ fn math_xor_stack(vm: &mut VM) {
 
     match (a, b) {
         (Some(a), Some(b)) => vm.stack.push(a ^ b),
-        _ => panic!("Could not pop the stack as it's empty!"),
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -303,46 +319,68 @@ fn compare_stack_lit(vm: &mut VM) {
 
 fn jump_absolute(vm: &mut VM) {
     let addr = vm.fetch_lit();
-    vm.prgrm_cntr = (addr as usize) - 1;
+    vm.jump(addr as usize);
 }
 
 fn jump_equal(vm: &mut VM) {
     let addr = vm.fetch_lit();
 
-    if vm.flags.get(Flag::Equal) {
-        vm.prgrm_cntr = (addr as usize) - 1;
-    }
+    vm.jump_if(vm.flags.get(Flag::Equal), addr as usize);
 }
 
 fn jump_not_equal(vm: &mut VM) {
     let addr = vm.fetch_lit();
 
-    if vm.flags.get(Flag::NotEqual) {
-        vm.prgrm_cntr = (addr as usize) - 1;
-    }
+    vm.jump_if(vm.flags.get(Flag::NotEqual), addr as usize);
 }
 
 fn jump_greater(vm: &mut VM) {
     let addr = vm.fetch_lit();
 
-    if vm.flags.get(Flag::Greater) {
-        vm.prgrm_cntr = (addr as usize) - 1;
-    }
+    vm.jump_if(vm.flags.get(Flag::Greater), addr as usize);
 }
 
 fn jump_smaller(vm: &mut VM) {
     let addr = vm.fetch_lit();
 
-    if vm.flags.get(Flag::Smaller) {
-        vm.prgrm_cntr = (addr as usize) - 1;
-    }
+    vm.jump_if(vm.flags.get(Flag::Smaller), addr as usize);
 }
 
 fn jump_overflow(vm: &mut VM) {
     let addr = vm.fetch_lit();
 
-    if vm.flags.get(Flag::Overflow) {
-        vm.prgrm_cntr = (addr as usize) - 1;
+    vm.jump_if(vm.flags.get(Flag::Overflow), addr as usize);
+}
+
+/// Pushes the return address and the caller's `base_ptr`, which then points
+/// right above them, so the frames of the guest form a chain on the stack.
+fn call(vm: &mut VM) {
+    let addr = vm.fetch_lit();
+
+    vm.stack.push(vm.prgrm_cntr.wrapping_add(1) as u32);
+    vm.stack.push(vm.base_ptr);
+    vm.base_ptr = vm.stack.len() as u32;
+
+    vm.jump(addr as usize);
+}
+
+/// Drops whatever the callee left on top of its frame before returning.
+fn ret(vm: &mut VM) {
+    let frame = vm.base_ptr as usize;
+
+    if vm.stack.len() < frame {
+        return vm.raise_fault(FaultKind::StackUnderflow);
+    }
+    while vm.stack.len() > frame {
+        vm.stack.pop();
+    }
+
+    match (vm.stack.pop(), vm.stack.pop()) {
+        (Some(base_ptr), Some(addr)) => {
+            vm.base_ptr = base_ptr;
+            vm.jump(addr as usize);
+        }
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -356,22 +394,24 @@ fn flag_reset(vm: &mut VM) {
 
 fn math_inc_reg(vm: &mut VM) {
     let reg = vm.fetch_reg();
+    let (value, overflow) = vm.regs[reg as usize].overflowing_add(1);
 
-    if vm.regs[reg as usize] == usize::MAX {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[reg as usize] += 1;
+    vm.regs[reg as usize] = value;
 }
 
 fn math_dec_reg(vm: &mut VM) {
     let reg = vm.fetch_reg();
+    let (value, overflow) = vm.regs[reg as usize].overflowing_sub(1);
 
-    if vm.regs[reg as usize] == 0 {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[reg as usize] -= 1;
+    vm.regs[reg as usize] = value;
 }
 
 fn math_inc_stack(vm: &mut VM) {
@@ -379,13 +419,15 @@ fn math_inc_stack(vm: &mut VM) {
 
     match value {
         Some(value) => {
-            if value == u32::MAX {
+            let (value, overflow) = value.overflowing_add(1);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(value + 1);
+            vm.stack.push(value);
         }
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -394,94 +436,105 @@ fn math_dec_stack(vm: &mut VM) {
 
     match value {
         Some(value) => {
-            if value == 0 {
+            let (value, overflow) = value.overflowing_sub(1);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(value - 1);
+            vm.stack.push(value);
         }
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_add_reg_num(vm: &mut VM) {
     let reg = vm.fetch_reg();
     let lit = vm.fetch_lit();
+    let (value, overflow) = (vm.regs[reg as usize] as u32).overflowing_add(lit);
 
-    if vm.regs[reg as usize] as u32 > u32::MAX - lit {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[reg as usize] += lit as usize;
+    vm.regs[reg as usize] = value as usize;
 }
 
 fn math_add_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
         Some(value) => {
-            if reg as u32 > u32::MAX - value {
+            let (value, overflow) = (num as u32).overflowing_add(value);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(reg as u32 + value);
+            vm.stack.push(value);
         }
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_sub_reg_num(vm: &mut VM) {
     let reg = vm.fetch_reg();
     let lit = vm.fetch_lit();
+    let (value, overflow) = (vm.regs[reg as usize] as u32).overflowing_sub(lit);
 
-    if (reg as u32) < lit {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[reg as usize] -= lit as usize;
+    vm.regs[reg as usize] = value as usize;
 }
 
 fn math_sub_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
         Some(value) => {
-            if (reg as u32) < value {
+            let (value, overflow) = (num as u32).overflowing_sub(value);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(reg as u32 - value);
+            vm.stack.push(value);
         }
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
 fn math_mul_reg_num(vm: &mut VM) {
     let reg = vm.fetch_reg();
     let lit = vm.fetch_lit();
+    let (value, overflow) = (vm.regs[reg as usize] as u32).overflowing_mul(lit);
 
-    if vm.regs[reg as usize] as u32 > u32::MAX / lit {
+    if overflow {
         vm.flags.set(Flag::Overflow, true);
     }
 
-    vm.regs[reg as usize] *= lit as usize;
+    vm.regs[reg as usize] = value as usize;
 }
 
 fn math_mul_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
         Some(value) => {
-            if reg as u32 > u32::MAX / value {
+            let (value, overflow) = (num as u32).overflowing_mul(value);
+
+            if overflow {
                 vm.flags.set(Flag::Overflow, true);
             }
 
-            vm.stack.push(reg as u32 * value);
+            vm.stack.push(value);
         }
-        None => panic!("Could not pop the stack as it's empty!"),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -489,16 +542,22 @@ fn math_div_reg_num(vm: &mut VM) {
     let reg = vm.fetch_reg();
     let lit = vm.fetch_lit();
 
-    vm.regs[reg as usize] /= lit as usize;
+    match vm.regs[reg as usize].checked_div(lit as usize) {
+        Some(value) => vm.regs[reg as usize] = value,
+        None => vm.raise_fault(FaultKind::DivisionByZero),
+    }
 }
 
 fn math_div_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
-        Some(value) => vm.stack.push(reg as u32 / value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        Some(value) => match (num as u32).checked_div(value) {
+            Some(value) => vm.stack.push(value),
+            None => vm.raise_fault(FaultKind::DivisionByZero),
+        },
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -510,12 +569,12 @@ fn math_and_reg_num(vm: &mut VM) {
 }
 
 fn math_and_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
-        Some(value) => vm.stack.push(reg as u32 & value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        Some(value) => vm.stack.push(num as u32 & value),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -527,12 +586,12 @@ fn math_or_reg_num(vm: &mut VM) {
 }
 
 fn math_or_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
-        Some(value) => vm.stack.push(reg as u32 | value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        Some(value) => vm.stack.push(num as u32 | value),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
@@ -544,15 +603,70 @@ fn math_xor_reg_num(vm: &mut VM) {
 }
 
 fn math_xor_stack_num(vm: &mut VM) {
-    let reg = vm.fetch_reg();
+    let num = vm.fetch_byte();
     let value = vm.stack.pop();
 
     match value {
-        Some(value) => vm.stack.push(reg as u32 ^ value),
-        None => panic!("Could not pop the stack as it's empty!"),
+        Some(value) => vm.stack.push(num as u32 ^ value),
+        None => vm.raise_fault(FaultKind::StackUnderflow),
     }
 }
 
+fn int_enable(vm: &mut VM) {
+    vm.flags.set(Flag::Interrupt, true);
+}
+
+fn int_disable(vm: &mut VM) {
+    vm.flags.set(Flag::Interrupt, false);
+}
+
+fn int_return(vm: &mut VM) {
+    let (flags, addr) = (vm.stack.pop(), vm.stack.pop());
+
+    match (flags, addr) {
+        (Some(flags), Some(addr)) => {
+            let stop = vm.flags.get(Flag::Stop);
+            vm.flags.set_bits(flags);
+            vm.flags.set(Flag::Stop, stop);
+
+            vm.jump(addr as usize);
+        }
+        _ => vm.raise_fault(FaultKind::StackUnderflow),
+    }
+}
+
+fn int_table_lit(vm: &mut VM) {
+    let addr = vm.fetch_lit();
+    vm.ivt_base = addr as usize;
+}
+
+fn timer_set_lit(vm: &mut VM) {
+    let period = vm.fetch_lit();
+
+    vm.timer_period = period;
+    vm.timer_count = 0;
+}
+
+fn thread_spawn(vm: &mut VM) {
+    let (reg, addr) = (vm.fetch_reg(), vm.fetch_lit());
+
+    vm.regs[reg as usize] = vm.spawn_thread(addr as usize);
+}
+
+fn thread_yield(vm: &mut VM) {
+    vm.yield_requested = true;
+}
+
+fn thread_join(vm: &mut VM) {
+    let reg = vm.fetch_reg();
+    vm.join_thread(vm.regs[reg as usize]);
+}
+
+fn thread_self(vm: &mut VM) {
+    let reg = vm.fetch_reg();
+    vm.regs[reg as usize] = vm.current_thread;
+}
+
 const OP_CODES: [fn(&mut VM); 256] = [
     exit,               // 0x00
     push_lit,           // 0x01
@@ -567,9 +681,9 @@ const OP_CODES: [fn(&mut VM); 256] = [
     mov_reg_reg,        // 0x0A
     mov_heap_heap,      // 0x0B
     push_heap,          // 0x0C
-    nop,                // 0x0D
-    nop,                // 0x0E
-    nop,                // 0x0F
+    mov_ptr_reg,        // 0x0D
+    mov_reg_ptr,        // 0x0E
+    exit_reg,           // 0x0F
     math_add_reg,       // 0x10
     math_add_stack,     // 0x11
     math_sub_reg,       // 0x12
@@ -587,8 +701,8 @@ const OP_CODES: [fn(&mut VM); 256] = [
     math_xor_reg,       // 0x1E
     math_xor_stack,     // 0x1F
     jump_absolute,      // 0x20
-    nop,                // 0x21
-    nop,                // 0x22
+    call,               // 0x21
+    ret,                // 0x22
     nop,                // 0x23
     nop,                // 0x24
     nop,                // 0x25
@@ -682,11 +796,11 @@ const OP_CODES: [fn(&mut VM); 256] = [
     math_xor_stack_num, // 0x7D
     nop,                // 0x7E
     nop,                // 0x7F
-    nop,                // 0x80
-    nop,                // 0x81
-    nop,                // 0x82
-    nop,                // 0x83
-    nop,                // 0x84
+    int_enable,         // 0x80
+    int_disable,        // 0x81
+    int_return,         // 0x82
+    int_table_lit,      // 0x83
+    timer_set_lit,      // 0x84
     nop,                // 0x85
     nop,                // 0x86
     nop,                // 0x87
@@ -698,10 +812,10 @@ const OP_CODES: [fn(&mut VM); 256] = [
     nop,                // 0x8D
     nop,                // 0x8E
     nop,                // 0x8F
-    nop,                // 0x90
-    nop,                // 0x91
-    nop,                // 0x92
-    nop,                // 0x93
+    thread_spawn,       // 0x90
+    thread_yield,       // 0x91
+    thread_join,        // 0x92
+    thread_self,        // 0x93
     nop,                // 0x94
     nop,                // 0x95
     nop,                // 0x96
@@ -812,15 +926,19 @@ const OP_CODES: [fn(&mut VM); 256] = [
     syscall,            // 0xFF
 ];
 
-use std::alloc::{alloc, dealloc, realloc, Layout};
+use std::fmt;
 use std::mem;
-use std::process;
-use std::ptr::{self, Unique};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 
 const HEAP_INITIAL_CAPACITY: usize = 256; // 1KB
+const HEAP_DEFAULT_LIMIT: usize = 1 << 26; // 256MB
 const STACK_INITIAL_CAPACITY: usize = 128; // 512B
 
-const NO_OF_FLAGS: usize = 6;
+const NO_OF_FLAGS: usize = 7;
+const NO_OF_INTERRUPTS: usize = 32;
+
+pub const TIMER_INTERRUPT: u8 = 0;
 
 #[derive(Debug)]
 pub enum Flag {
@@ -830,6 +948,7 @@ pub enum Flag {
     Smaller,
     Overflow,
     Stop,
+    Interrupt,
 }
 
 #[derive(Debug)]
@@ -847,6 +966,19 @@ impl FlagSet {
     pub fn set(&mut self, flag: Flag, value: bool) {
         self.0[flag as usize] = value;
     }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &value)| bits | ((value as u32) << i))
+    }
+
+    pub fn set_bits(&mut self, bits: u32) {
+        for (i, value) in self.0.iter_mut().enumerate() {
+            *value = bits & (1 << i) != 0;
+        }
+    }
 }
 
 impl Default for FlagSet {
@@ -857,76 +989,97 @@ impl Default for FlagSet {
 
 #[derive(Debug)]
 pub struct Heap {
-    ptr: Unique<u32>,
-    cap: usize,
+    words: Vec<u32>,
+    /// How many words the heap may grow to.
+    limit: usize,
+    journal: Option<Vec<(usize, u32)>>,
 }
 
 impl Heap {
-    fn ptr(&self) -> *mut u32 {
-        self.ptr.as_ptr()
+    /// The heap for native code to use directly, which stays valid until
+    /// the heap grows.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u32 {
+        self.words.as_mut_ptr()
     }
 
-    fn grow(&mut self, new_cap: usize) {
-        unsafe {
-            let elem_size = mem::size_of::<u32>();
-            let align = mem::align_of::<u32>();
+    pub fn new() -> Heap {
+        Heap {
+            words: vec![0; HEAP_INITIAL_CAPACITY],
+            limit: HEAP_DEFAULT_LIMIT,
+            journal: None,
+        }
+    }
 
-            let ptr = {
-                let layout = Layout::from_size_align_unchecked(self.cap * elem_size, align);
-                realloc(self.ptr.as_ptr() as *mut _, layout, new_cap * elem_size)
-            };
+    /// Caps the heap at `limit` words. Writes that would grow it past that
+    /// fail instead.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
 
-            if ptr.is_null() {
-                eprintln!("Failed to reallocate(grow) VM heap! Aborting!");
+    pub fn read(&self, addr: usize) -> u32 {
+        self.words.get(addr).copied().unwrap_or(0)
+    }
 
-                process::abort();
+    pub fn write(&mut self, addr: usize, value: u32) -> Result<(), FaultKind> {
+        if addr >= self.words.len() {
+            if value == 0 {
+                return Ok(());
+            }
+            if addr >= self.limit {
+                return Err(FaultKind::OutOfMemory);
             }
 
-            self.ptr = Unique::new_unchecked(ptr as *mut _);
-            self.cap = new_cap;
+            self.words.resize(addr + 1, 0);
         }
-    }
-
-    pub fn new() -> Heap {
-        let cap = HEAP_INITIAL_CAPACITY;
 
-        let elem_size = mem::size_of::<u32>();
-        let align = mem::align_of::<u32>();
-        let ptr = unsafe {
-            let layout = Layout::from_size_align_unchecked(cap * elem_size, align);
-            alloc(layout)
-        };
+        if let Some(journal) = &mut self.journal {
+            journal.push((addr, self.words[addr]));
+        }
 
-        if ptr.is_null() {
-            eprintln!("Failed to allocate VM heap! Aborting!");
+        self.words[addr] = value;
+        Ok(())
+    }
 
-            process::abort();
-        }
+    pub fn capacity(&self) -> usize {
+        self.words.len()
+    }
 
-        let ptr = unsafe { Unique::new_unchecked(ptr as *mut _) };
+    /// Gives back the words past `len`, which the heap grew by since it was
+    /// that long.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.words.truncate(len);
+    }
 
-        Heap { ptr, cap }
+    pub fn as_slice(&self) -> &[u32] {
+        &self.words
     }
 
-    pub fn read(&self, addr: usize) -> u32 {
-        if addr > self.cap {
-            return 0;
-        }
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Vec<u8> {
+        // Past the end of the address space reads as zeroes, the same as
+        // past the end of the heap.
+        let mut buf = (0..len.div_ceil(4))
+            .flat_map(|i| {
+                addr.checked_add(i)
+                    .map_or(0, |addr| self.read(addr))
+                    .to_be_bytes()
+            })
+            .collect::<Vec<_>>();
 
-        unsafe { ptr::read(self.ptr().add(addr)) }
+        buf.truncate(len);
+        buf
     }
 
-    pub fn write(&mut self, addr: usize, value: u32) {
-        if value == 0 {
-            return;
-        }
-        if addr >= self.cap {
-            self.grow(addr + 1);
-        }
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), FaultKind> {
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
 
-        unsafe {
-            ptr::write(self.ptr().add(addr), value);
+            let addr = addr.checked_add(i).ok_or(FaultKind::OutOfMemory)?;
+            self.write(addr, u32::from_be_bytes(word))?;
         }
+
+        Ok(())
     }
 }
 
@@ -943,131 +1096,106 @@ pub enum Register {
     D,
 }
 
-#[derive(Debug)]
-struct RawStack {
-    ptr: Unique<u32>,
-    cap: usize,
+/// An interrupt number past the last one the vector table has room for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptOutOfRange(pub u8);
+
+impl fmt::Display for InterruptOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Could not raise interrupt {} as it's out of range!",
+            self.0
+        )
+    }
 }
 
-impl RawStack {
-    fn new() -> RawStack {
-        let cap = STACK_INITIAL_CAPACITY;
+impl std::error::Error for InterruptOutOfRange {}
 
-        let elem_size = mem::size_of::<u32>();
-        let align = mem::align_of::<u32>();
-        let ptr = unsafe {
-            let layout = Layout::from_size_align_unchecked(cap * elem_size, align);
-            alloc(layout)
-        };
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle(Arc<AtomicU32>);
 
-        if ptr.is_null() {
-            eprintln!("Failed to allocate VM stack! Aborting!");
-
-            process::abort();
+impl InterruptHandle {
+    pub fn raise(&self, n: u8) -> Result<(), InterruptOutOfRange> {
+        if n as usize >= NO_OF_INTERRUPTS {
+            return Err(InterruptOutOfRange(n));
         }
 
-        let ptr = unsafe { Unique::new_unchecked(ptr as *mut _) };
-
-        RawStack { ptr, cap }
+        self.0.fetch_or(1 << n, Ordering::SeqCst);
+        Ok(())
     }
 
-    fn grow(&mut self) {
-        unsafe {
-            let elem_size = mem::size_of::<u32>();
-            let align = mem::align_of::<u32>();
-
-            let (new_cap, ptr) = {
-                let new_cap = self.cap * 2;
-
-                let layout = Layout::from_size_align_unchecked(self.cap * elem_size, align);
-                let ptr = realloc(self.ptr.as_ptr() as *mut _, layout, new_cap * elem_size);
-
-                (new_cap, ptr)
-            };
-
-            if ptr.is_null() {
-                eprintln!("Failed to reallocate(grow) VM stack! Aborting!");
-
-                process::abort();
-            }
+    /// The interrupts raised but not serviced yet, one bit each.
+    pub fn pending(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
 
-            self.ptr = Unique::new_unchecked(ptr as *mut _);
-            self.cap = new_cap;
-        }
+    fn clear(&self, n: u8) {
+        self.0.fetch_and(!(1 << n), Ordering::SeqCst);
     }
 }
 
-impl Drop for RawStack {
-    fn drop(&mut self) {
-        let elem_size = mem::size_of::<u32>();
-
-        if self.cap != 0 {
-            let align = mem::align_of::<u32>();
-
-            unsafe {
-                let layout = Layout::from_size_align_unchecked(self.cap * elem_size, align);
-                dealloc(self.ptr.as_ptr() as *mut _, layout);
-            }
-        }
-    }
+#[derive(Clone, Copy, Debug)]
+enum StackChange {
+    Push,
+    Pop(u32),
 }
 
 #[derive(Debug)]
 pub struct Stack {
-    buf: RawStack,
-    len: usize,
+    buf: Vec<u32>,
+    journal: Option<Vec<StackChange>>,
 }
 
 impl Stack {
-    fn ptr(&self) -> *mut u32 {
-        self.buf.ptr.as_ptr()
-    }
-
     fn cap(&self) -> usize {
-        self.buf.cap
+        self.buf.capacity()
     }
 
     pub fn new() -> Stack {
         Stack {
-            buf: RawStack::new(),
-            len: 0,
+            buf: Vec::with_capacity(STACK_INITIAL_CAPACITY),
+            journal: None,
         }
     }
 
     pub fn push(&mut self, elem: u32) {
-        if self.len == self.cap() {
-            self.buf.grow();
-        }
+        self.buf.push(elem);
 
-        unsafe {
-            ptr::write(self.ptr().add(self.len), elem);
+        if let Some(journal) = &mut self.journal {
+            journal.push(StackChange::Push);
         }
-
-        self.len += 1;
     }
 
     pub fn pop(&mut self) -> Option<u32> {
-        if self.len == 0 {
-            return None;
-        }
+        let elem = self.buf.pop()?;
 
-        self.len -= 1;
-        let elem = unsafe { ptr::read(self.ptr().add(self.len)) };
+        if let Some(journal) = &mut self.journal {
+            journal.push(StackChange::Pop(elem));
+        }
 
         Some(elem)
     }
 
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u32] {
+        &self.buf
+    }
+
+    /// The word on top of the stack, or 0 if it's empty.
     pub fn peek(&self) -> u32 {
-        unsafe { ptr::read(self.ptr().add(self.len)) }
+        self.buf.last().copied().unwrap_or(0)
     }
 
     pub fn clear(&mut self) {
-        unsafe {
-            let slice = ptr::slice_from_raw_parts_mut(self.ptr(), self.len);
-            ptr::drop_in_place(slice);
-
-            self.len = 0;
-        }
+        self.buf.clear();
     }
 }
 
@@ -1077,9 +1205,145 @@ impl Default for Stack {
     }
 }
 
-impl Drop for Stack {
-    fn drop(&mut self) {
-        while self.pop().is_some() {}
+struct Input(Box<dyn Read + Send>);
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Input")
+    }
+}
+
+impl Default for Input {
+    fn default() -> Input {
+        Input(Box::new(io::stdin()))
+    }
+}
+
+struct Output(Box<dyn Write + Send>);
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output(Box::new(io::stdout()))
+    }
+}
+
+/// The code a host process exits with when the `VM` faults.
+pub const FAULT_EXIT_CODE: u8 = 70;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExitStatus(pub u32);
+
+impl ExitStatus {
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+
+    pub fn success(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The code a host process exits with for this status. Only the low 8
+    /// bits of it would reach the parent, so larger codes are clamped
+    /// instead of wrapping around to 0, and a guest can't pass for a fault
+    /// by exiting with `FAULT_EXIT_CODE`.
+    pub fn process_code(&self) -> u8 {
+        match self.0 {
+            code if code == FAULT_EXIT_CODE as u32 => FAULT_EXIT_CODE + 1,
+            code @ 0..=255 => code as u8,
+            _ => 255,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FaultKind {
+    StackUnderflow,
+    DivisionByZero,
+    InvalidRegister(u8),
+    ProgramCounterOutOfBounds,
+    InvalidThread(usize),
+    Deadlock,
+    SyscallFailed(usize),
+    ReplayDiverged,
+    RecordingFailed,
+    OutOfMemory,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::StackUnderflow => write!(f, "Could not pop the stack as it's empty!"),
+            FaultKind::DivisionByZero => write!(f, "Could not divide by zero!"),
+            FaultKind::InvalidRegister(reg) => {
+                write!(f, "Could not access register {} as it doesn't exist!", reg)
+            }
+            FaultKind::ProgramCounterOutOfBounds => {
+                write!(f, "Could not fetch past the end of the program!")
+            }
+            FaultKind::InvalidThread(tid) => {
+                write!(f, "Could not join thread {} as it can't be waited on!", tid)
+            }
+            FaultKind::Deadlock => {
+                write!(f, "Could not schedule a thread as all of them are blocked!")
+            }
+            FaultKind::SyscallFailed(number) => {
+                write!(f, "Could not proceed with syscall {}!", number)
+            }
+            FaultKind::ReplayDiverged => {
+                write!(
+                    f,
+                    "Could not replay the recording as the program diverged from it!"
+                )
+            }
+            FaultKind::RecordingFailed => write!(f, "Could not write to the recording!"),
+            FaultKind::OutOfMemory => write!(f, "Could not grow the heap past its limit!"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub prgrm_cntr: usize,
+    pub location: Option<Location>,
+    /// The faulting instruction followed by the return address of every
+    /// frame it was called from, innermost first.
+    pub backtrace: Vec<Frame>,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "{} (at {:#x} in {})",
+                self.kind, self.prgrm_cntr, location
+            ),
+            None => write!(f, "{} (at {:#x})", self.kind, self.prgrm_cntr),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub prgrm_cntr: usize,
+    pub location: Option<Location>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{:#06x} in {}", self.prgrm_cntr, location),
+            None => write!(f, "{:#06x}", self.prgrm_cntr),
+        }
     }
 }
 
@@ -1090,9 +1354,43 @@ pub struct VM {
     stack: Stack,
     pub heap: Heap,
     bytecode: Vec<u8>,
+    decoded: Program,
+    dispatch: Dispatch,
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    jit: jit::Jit,
     pub prgrm_cntr: usize,
     base_ptr: u32,
     hdr_size: usize,
+    interrupts: InterruptHandle,
+    ivt_base: usize,
+    timer_period: u32,
+    timer_count: u32,
+    threads: Vec<Thread>,
+    current_thread: usize,
+    yield_requested: bool,
+    time_slice: u32,
+    slice_count: u32,
+    input: Input,
+    output: Output,
+    channels: Vec<Channel>,
+    blocked: bool,
+    filesystem: Option<Box<dyn FileSystem>>,
+    files: Vec<Option<Box<dyn FileHandle>>>,
+    instr_start: usize,
+    exit_code: u32,
+    fault: Option<Fault>,
+    clock: Box<dyn Clock>,
+    rng: Rng,
+    replay: replay::Mode,
+    /// The inputs taken from the host while the debugger journals a step.
+    input_journal: Option<Vec<replay::Event>>,
+    /// Inputs the debugger stepped back over, the next one last, to be
+    /// handed out again before any new ones.
+    rewound_inputs: Vec<replay::Event>,
+    debug_info: Option<DebugInfo>,
+    trace: Option<Output>,
+    profile: Option<profile::Profile>,
+    coverage: Option<coverage::Coverage>,
 }
 
 impl VM {
@@ -1103,54 +1401,442 @@ impl VM {
     fn parse_header(&mut self) {
         self.hdr_size = 4;
 
-        for i in 0..=self.bytecode.len() - 4 {
+        for i in 0..self.bytecode.len().saturating_sub(3) {
             if self.is_at_end_header(i) {
                 break;
             }
 
-            self.heap.write(i, self.bytecode[i] as u32);
+            self.write_heap(i, self.bytecode[i] as u32);
             self.hdr_size += 1;
         }
     }
 
     fn step_program(&mut self) {
-        let instruction = self.bytecode[self.prgrm_cntr + self.hdr_size];
+        self.instr_start = self.prgrm_cntr;
 
-        OP_CODES[instruction as usize](self);
+        if self.dispatch != Dispatch::Bytecode {
+            if let Some((instr, len)) = self.decoded.get(self.prgrm_cntr) {
+                return self.execute(instr, len);
+            }
+        }
+
+        match self.bytecode.get(self.prgrm_cntr + self.hdr_size) {
+            Some(&instruction) => OP_CODES[instruction as usize](self),
+            None => self.raise_fault(FaultKind::ProgramCounterOutOfBounds),
+        }
+    }
+
+    fn tick_timer(&mut self) {
+        if self.timer_period == 0 {
+            return;
+        }
+
+        self.timer_count += 1;
+
+        if self.timer_count >= self.timer_period {
+            self.timer_count = 0;
+            // The timer's interrupt is always in range.
+            let _ = self.raise_interrupt(TIMER_INTERRUPT);
+        }
+    }
+
+    fn tick_time_slice(&mut self) {
+        if self.time_slice == 0 || self.threads.len() < 2 {
+            return;
+        }
+
+        self.slice_count += 1;
+
+        if self.slice_count >= self.time_slice {
+            self.yield_requested = true;
+        }
+    }
+
+    fn service_interrupts(&mut self) {
+        if !self.flags.get(Flag::Interrupt) {
+            return;
+        }
+
+        // Interrupts without a handler yet stay pending until one is
+        // installed, rather than getting lost.
+        let mut pending = self.interrupts.pending();
+        let (n, handler) = loop {
+            if pending == 0 {
+                return;
+            }
+
+            let n = pending.trailing_zeros() as u8;
+            let handler = self.heap.read(self.ivt_base + n as usize);
+            if handler != 0 {
+                break (n, handler);
+            }
+
+            pending &= !(1 << n);
+        };
+
+        self.interrupts.clear(n);
+
+        self.stack.push(self.prgrm_cntr as u32);
+        self.stack.push(self.flags.bits());
+
+        self.flags.set(Flag::Interrupt, false);
+        self.prgrm_cntr = handler as usize;
     }
 
     pub fn new() -> VM {
         Default::default()
     }
 
-    pub fn load_program(&mut self, bytecode: Vec<u8>) {
+    /// Loads `bytecode`, along with the debug info appended to it, if any.
+    pub fn load_program(&mut self, mut bytecode: Vec<u8>) {
+        self.debug_info = DebugInfo::split_off(&mut bytecode);
         self.bytecode = bytecode;
+        self.parse_header();
+
+        self.decoded = Program::decode(self.bytecode.get(self.hdr_size..).unwrap_or_default());
     }
 
-    pub fn run_program(&mut self) {
-        self.parse_header();
+    /// Loads `bytecode` for a translator, which has nothing to go on when
+    /// the header, or its end marker, is missing.
+    pub(crate) fn load_translatable(bytecode: Vec<u8>) -> io::Result<VM> {
+        let mut vm = VM::new();
+        vm.load_program(bytecode);
 
-        while !self.flags.get(Flag::Stop) {
-            self.step_program();
-            self.prgrm_cntr += 1;
+        if vm.hdr_size > vm.bytecode.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not rsvm bytecode: the header's end marker is missing",
+            ));
         }
+
+        Ok(vm)
     }
 
-    pub fn fetch_reg(&mut self) -> u8 {
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    pub fn locate(&self, prgrm_cntr: usize) -> Option<Location> {
+        self.debug_info.as_ref()?.locate(prgrm_cntr)
+    }
+
+    /// Walks the frames linked through `base_ptr`, starting with the
+    /// instruction at `prgrm_cntr`. Return addresses point past the `call`
+    /// that pushed them, so they are located by the `call` itself.
+    pub fn backtrace(&self, prgrm_cntr: usize) -> Vec<Frame> {
+        let callers = self.return_addrs().map(|addr| {
+            let mut location = self.locate(addr.saturating_sub(1));
+            if let Some((_, offset)) = location.as_mut().and_then(|l| l.symbol.as_mut()) {
+                *offset += 1;
+            }
+
+            Frame {
+                prgrm_cntr: addr,
+                location,
+            }
+        });
+
+        std::iter::once(Frame {
+            prgrm_cntr,
+            location: self.locate(prgrm_cntr),
+        })
+        .chain(callers)
+        .collect()
+    }
+
+    /// The return addresses of the frames linked through `base_ptr`,
+    /// innermost first.
+    pub(crate) fn return_addrs(&self) -> impl Iterator<Item = usize> + '_ {
+        let stack = self.stack.as_slice();
+        let mut frame = self.base_ptr as usize;
+
+        std::iter::from_fn(move || {
+            if frame < 2 || frame > stack.len() {
+                return None;
+            }
+
+            let (addr, caller) = (stack[frame - 2] as usize, stack[frame - 1] as usize);
+
+            // A frame can only be linked to one further down the stack.
+            frame = if caller < frame { caller } else { 0 };
+
+            Some(addr)
+        })
+    }
+
+    /// Lays out `args` and `env` right after the data of the header, each
+    /// string as its length in bytes followed by the bytes themselves. The
+    /// counts and the addresses of the two pointer arrays are passed in the
+    /// registers, argc and argv in A and B, envc and envp in C and D.
+    pub fn set_args<S: AsRef<str>>(&mut self, args: &[S], env: &[S]) {
+        let argv = self.hdr_size.saturating_sub(4);
+        let envp = argv + args.len();
+        let mut addr = envp + env.len();
+
+        // The two pointer arrays are contiguous, so one pass fills both.
+        for (i, string) in args.iter().chain(env).enumerate() {
+            let bytes = string.as_ref().as_bytes();
+
+            self.write_heap(argv + i, addr as u32);
+            self.write_heap(addr, bytes.len() as u32);
+            self.write_heap_bytes(addr + 1, bytes);
+
+            addr += 1 + bytes.len().div_ceil(4);
+        }
+
+        self.regs[Register::A as usize] = args.len();
+        self.regs[Register::B as usize] = argv;
+        self.regs[Register::C as usize] = env.len();
+        self.regs[Register::D as usize] = envp;
+    }
+
+    pub fn run_program(&mut self) -> Result<ExitStatus, Fault> {
+        while !self.is_stopped() {
+            #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+            let compiled = self.dispatch == Dispatch::Compiled && self.run_compiled();
+            #[cfg(not(all(feature = "jit", target_arch = "x86_64", target_os = "linux")))]
+            let compiled = false;
+
+            if !compiled {
+                self.step();
+            }
+
+            if self.blocked && self.threads.len() < 2 {
+                std::thread::yield_now();
+            }
+        }
+
+        match &self.fault {
+            Some(fault) => Err(fault.clone()),
+            None => Ok(ExitStatus(self.exit_code)),
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.blocked = false;
+
+        if self.trace.is_some() {
+            self.trace_instruction();
+        }
+        if self.profile.is_some() {
+            self.prepare_sample();
+        }
+        self.step_program();
+
+        // A blocked instruction is run again later, and only counts then.
+        if !self.blocked {
+            if self.profile.is_some() {
+                self.sample();
+            }
+            self.cover();
+        }
+
+        if self.fault.is_some() {
+            return;
+        }
+
+        // Time only passes for instructions that went ahead, so a blocked
+        // one retried many times doesn't make a virtual clock race ahead.
+        if self.blocked {
+            self.yield_requested = self.threads.len() > 1;
+        } else {
+            self.clock.tick();
+            self.prgrm_cntr = self.prgrm_cntr.wrapping_add(1);
+        }
+
+        self.tick_timer();
+        self.tick_time_slice();
+
+        if self.yield_requested {
+            self.schedule();
+        }
+
+        self.service_interrupts();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.flags.get(Flag::Stop) || self.fault.is_some()
+    }
+
+    pub fn exit_status(&self) -> Option<Result<ExitStatus, Fault>> {
+        match &self.fault {
+            Some(fault) => Some(Err(fault.clone())),
+            None if self.flags.get(Flag::Stop) => Some(Ok(ExitStatus(self.exit_code))),
+            None => None,
+        }
+    }
+
+    pub(crate) fn raise_fault(&mut self, kind: FaultKind) {
+        if self.fault.is_none() {
+            self.fault = Some(Fault {
+                kind,
+                prgrm_cntr: self.instr_start,
+                location: self.locate(self.instr_start),
+                backtrace: self.backtrace(self.instr_start),
+            });
+        }
+    }
+
+    /// Writes `value` to the heap, faulting when the heap can't grow to
+    /// `addr`.
+    pub(crate) fn write_heap(&mut self, addr: usize, value: u32) {
+        if let Err(kind) = self.heap.write(addr, value) {
+            self.raise_fault(kind);
+        }
+    }
+
+    pub(crate) fn write_heap_bytes(&mut self, addr: usize, bytes: &[u8]) {
+        if let Err(kind) = self.heap.write_bytes(addr, bytes) {
+            self.raise_fault(kind);
+        }
+    }
+
+    pub(crate) fn exit(&mut self, code: u32) {
+        self.exit_code = code;
+        self.flags.set(Flag::Stop, true);
+    }
+
+    pub(crate) fn jump(&mut self, addr: usize) {
+        self.prgrm_cntr = addr.wrapping_sub(1);
+    }
+
+    /// A conditional jump, whose outcome counts towards the coverage.
+    pub(crate) fn jump_if(&mut self, condition: bool, addr: usize) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_branch(self.instr_start, condition);
+        }
+
+        if condition {
+            self.jump(addr);
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    pub(crate) fn block(&mut self) {
+        self.blocked = true;
+    }
+
+    pub fn set_filesystem<F: FileSystem + 'static>(&mut self, filesystem: F) {
+        self.filesystem = Some(Box::new(filesystem));
+    }
+
+    pub fn set_fs_root<P: AsRef<std::path::Path>>(&mut self, root: P) -> io::Result<()> {
+        self.set_filesystem(DiskFs::new(root)?);
+        Ok(())
+    }
+
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn seed_rng_from_entropy(&mut self) {
+        self.rng = Rng::from_entropy();
+    }
+
+    pub fn attach_channel(&mut self, channel: Channel) -> usize {
+        self.channels.push(channel);
+        self.channels.len() - 1
+    }
+
+    /// Makes the `VM` log the offset of every instruction it executes to
+    /// `trace`, along with its location in the source if known.
+    pub fn set_trace<W: Write + Send + 'static>(&mut self, trace: W) {
+        self.trace = Some(Output(Box::new(trace)));
+    }
+
+    fn trace_instruction(&mut self) {
+        let line = match self.locate(self.prgrm_cntr) {
+            Some(location) => format!("{:#06x} {}", self.prgrm_cntr, location),
+            None => format!("{:#06x}", self.prgrm_cntr),
+        };
+
+        if let Some(Output(trace)) = &mut self.trace {
+            let _ = writeln!(trace, "{}", line);
+        }
+    }
+
+    /// Where `READ_STDIN` reads from, stdin by default.
+    pub fn set_input<R: Read + Send + 'static>(&mut self, input: R) {
+        self.input = Input(Box::new(input));
+    }
+
+    pub fn set_output<W: Write + Send + 'static>(&mut self, output: W) {
+        self.output = Output(Box::new(output));
+    }
+
+    pub fn registers(&self) -> &[usize; 4] {
+        &self.regs
+    }
+
+    pub fn flags(&self) -> &FlagSet {
+        &self.flags
+    }
+
+    pub fn prgrm_cntr(&self) -> usize {
+        self.prgrm_cntr
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        let stacks = self.stack.cap() + self.threads.iter().map(|t| t.stack_cap()).sum::<usize>();
+
+        (self.heap.capacity() + stacks) * mem::size_of::<u32>()
+    }
+
+    pub fn raise_interrupt(&self, n: u8) -> Result<(), InterruptOutOfRange> {
+        self.interrupts.raise(n)
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupts.clone()
+    }
+
+    pub fn fetch_byte(&mut self) -> u8 {
         self.prgrm_cntr += 1;
 
-        self.bytecode[self.prgrm_cntr + self.hdr_size]
+        match self.bytecode.get(self.prgrm_cntr + self.hdr_size) {
+            Some(&byte) => byte,
+            None => {
+                self.raise_fault(FaultKind::ProgramCounterOutOfBounds);
+                0
+            }
+        }
+    }
+
+    pub fn fetch_reg(&mut self) -> u8 {
+        let reg = self.fetch_byte();
+
+        if reg as usize >= self.regs.len() {
+            self.raise_fault(FaultKind::InvalidRegister(reg));
+            return 0;
+        }
+
+        reg
     }
 
     pub fn fetch_lit(&mut self) -> u32 {
-        let regs = (
-            self.fetch_reg() as u32,
-            self.fetch_reg() as u32,
-            self.fetch_reg() as u32,
-            self.fetch_reg() as u32,
+        let bytes = (
+            self.fetch_byte() as u32,
+            self.fetch_byte() as u32,
+            self.fetch_byte() as u32,
+            self.fetch_byte() as u32,
         );
 
-        (regs.0 << 24) + (regs.1 << 16) + (regs.2 << 8) + regs.3
+        (bytes.0 << 24) + (bytes.1 << 16) + (bytes.2 << 8) + bytes.3
     }
 
     pub fn compare_numbers(&mut self, a: u32, b: u32) {
@@ -1,12 +1,64 @@
-use std::{env, fmt, fs, process};
+use std::net::TcpListener;
+use std::{env, fmt, fs, io, process};
 
-use rsvm::VM;
+use rsvm::debugger::Debugger;
+use rsvm::gdb::{Ending, GdbServer};
+use rsvm::{ExitStatus, Fault, FAULT_EXIT_CODE, VM};
 
 use colored::Colorize;
 
+const KILLED_EXIT_CODE: u32 = 137;
+const PROFILE_REPORT_LEN: usize = 10;
+
+const USAGE: &str = "\
+Usage: rsvm [OPTIONS] <PROGRAM> [ARGS]...
+       rsvm dap
+
+Runs PROGRAM, passing it ARGS, or serves the Debug Adapter Protocol over
+the standard streams.
+
+Options:
+  --root <DIR>          Let the guest open files under DIR
+  --env <VAR[=VALUE]>   Pass VAR to the guest, with VALUE or the host's value
+  --seed <N|entropy>    Seed the random number syscalls
+  --record <FILE>       Log the inputs the guest takes from the host to FILE
+  --replay <FILE>       Feed the inputs logged to FILE back to the guest
+  --gdb <PORT>          Wait for a GDB connection on localhost:PORT
+  --profile <FILE>      Write a folded stack profile to FILE
+  --coverage <FILE>     Write lcov coverage to FILE
+  --jit                 Compile hot loops to native code (jit builds only)
+  --trace               Log every executed instruction to stderr
+  --dump                Print the state of the VM once it stops
+  --help                Print this help
+
+Exit status:
+  The exit code of the guest, clamped to 255. A guest exiting with 70
+  exits with 71 instead, as 70 is left for faults of the VM.
+";
+
 enum CliError {
     NoFileProvided,
     FailedToOpenFile,
+    MissingOptionValue(&'static str),
+    ConflictingOptions(&'static str, &'static str),
+    InvalidRoot,
+    InvalidSeed,
+    FailedToOpenRecording,
+    InvalidPort,
+    FailedToListen(u16),
+    DebuggerDisconnected,
+    FailedToWriteProfile,
+    FailedToWriteCoverage,
+    Fault(Fault),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Fault(_) => FAULT_EXIT_CODE as i32,
+            _ => 1,
+        }
+    }
 }
 
 impl fmt::Debug for CliError {
@@ -31,29 +83,328 @@ impl fmt::Debug for CliError {
                     "Please make sure the file exists and can be read.".white()
                 )
             }
+            CliError::MissingOptionValue(option) => {
+                write!(
+                    f,
+                    "{}{} {} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Missing value for option".cyan(),
+                    option.white()
+                )
+            }
+            CliError::ConflictingOptions(first, second) => {
+                write!(
+                    f,
+                    "{}{} {} {} {} {} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Options".cyan(),
+                    first.white(),
+                    "and".cyan(),
+                    second.white(),
+                    "can't be used together.".cyan()
+                )
+            }
+            CliError::InvalidRoot => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Invalid filesystem root!".cyan(),
+                    "Please make sure the directory exists.".white()
+                )
+            }
+            CliError::InvalidSeed => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Invalid seed!".cyan(),
+                    "Please provide a number or `entropy`.".white()
+                )
+            }
+            CliError::FailedToOpenRecording => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to open recording!".cyan(),
+                    "Please make sure the recording exists and was made by rsvm.".white()
+                )
+            }
+            CliError::InvalidPort => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Invalid port!".cyan(),
+                    "Please provide a number between 0 and 65535.".white()
+                )
+            }
+            CliError::FailedToListen(port) => {
+                write!(
+                    f,
+                    "{}{} {} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to listen on port".cyan(),
+                    port.to_string().white()
+                )
+            }
+            CliError::DebuggerDisconnected => {
+                write!(
+                    f,
+                    "{}{} {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Lost the connection to the debugger!".cyan()
+                )
+            }
+            CliError::FailedToWriteProfile => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to write profile!".cyan(),
+                    "Please make sure the file can be created.".white()
+                )
+            }
+            CliError::FailedToWriteCoverage => {
+                write!(
+                    f,
+                    "{}{} {}\n    {}",
+                    "[ERROR]".bright_red(),
+                    ":".bright_white(),
+                    "Failed to write coverage!".cyan(),
+                    "Please make sure the file can be created.".white()
+                )
+            }
+            CliError::Fault(fault) => {
+                write!(
+                    f,
+                    "{}{} {}",
+                    "[FAULT]".bright_red(),
+                    ":".bright_white(),
+                    fault.to_string().cyan()
+                )?;
+
+                // A lone frame would only repeat the message.
+                if fault.backtrace.len() > 1 {
+                    for (i, frame) in fault.backtrace.iter().enumerate() {
+                        write!(
+                            f,
+                            "\n    {} {}",
+                            format!("#{}", i).bright_white(),
+                            frame.to_string().white()
+                        )?;
+                    }
+                }
+
+                Ok(())
+            }
         }
     }
 }
 
-fn try_main() -> Result<(), CliError> {
-    let args = env::args().collect::<Vec<_>>();
+fn debug_over_gdb(debugger: &mut Debugger, port: u16) -> Result<Ending, CliError> {
+    let listener =
+        TcpListener::bind(("127.0.0.1", port)).map_err(|_| CliError::FailedToListen(port))?;
+
+    eprintln!(
+        "{}{} {} {}",
+        "[GDB]".bright_green(),
+        ":".bright_white(),
+        "Waiting for a debugger on".cyan(),
+        listener
+            .local_addr()
+            .map_err(|_| CliError::FailedToListen(port))?
+            .to_string()
+            .white()
+    );
+
+    let (stream, _) = listener
+        .accept()
+        .map_err(|_| CliError::DebuggerDisconnected)?;
+
+    GdbServer::new(debugger, stream)
+        .serve()
+        .map_err(|_| CliError::DebuggerDisconnected)
+}
+
+fn try_main() -> Result<ExitStatus, CliError> {
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().map(String::as_str) == Some("dap") {
+        rsvm::dap::serve_stdio().map_err(|_| CliError::DebuggerDisconnected)?;
+
+        return Ok(ExitStatus(0));
+    }
+    let mut dump = false;
+    let mut trace = false;
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    let mut jit = false;
+    let mut root = None;
+    let mut seed = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut gdb = None;
+    let mut profile = None;
+    let mut coverage = None;
+    let mut guest_env = Vec::new();
+    let mut filename = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => {
+                print!("{}", USAGE);
+                return Ok(ExitStatus(0));
+            }
+            "--dump" => dump = true,
+            "--trace" => trace = true,
+            #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+            "--jit" => jit = true,
+            "--root" => root = Some(args.next().ok_or(CliError::MissingOptionValue("--root"))?),
+            "--seed" => seed = Some(args.next().ok_or(CliError::MissingOptionValue("--seed"))?),
+            "--record" => {
+                record = Some(
+                    args.next()
+                        .ok_or(CliError::MissingOptionValue("--record"))?,
+                )
+            }
+            "--replay" => {
+                replay = Some(
+                    args.next()
+                        .ok_or(CliError::MissingOptionValue("--replay"))?,
+                )
+            }
+            "--profile" => {
+                profile = Some(
+                    args.next()
+                        .ok_or(CliError::MissingOptionValue("--profile"))?,
+                )
+            }
+            "--coverage" => {
+                coverage = Some(
+                    args.next()
+                        .ok_or(CliError::MissingOptionValue("--coverage"))?,
+                )
+            }
+            "--gdb" => gdb = Some(args.next().ok_or(CliError::MissingOptionValue("--gdb"))?),
+            "--env" => {
+                let var = args.next().ok_or(CliError::MissingOptionValue("--env"))?;
+
+                if var.contains('=') {
+                    guest_env.push(var);
+                } else if let Ok(value) = env::var(&var) {
+                    guest_env.push(format!("{}={}", var, value));
+                }
+            }
+            _ => {
+                filename = Some(arg);
+                break;
+            }
+        }
+    }
 
-    let filename = args.get(1).ok_or(CliError::NoFileProvided)?;
+    if record.is_some() && replay.is_some() {
+        return Err(CliError::ConflictingOptions("--record", "--replay"));
+    }
+
+    let filename = filename.ok_or(CliError::NoFileProvided)?;
     let input = fs::read(&filename).map_err(|_| CliError::FailedToOpenFile)?;
 
+    let guest_args = std::iter::once(filename).chain(args).collect::<Vec<_>>();
+
     let mut vm = VM::new();
     vm.load_program(input);
+    vm.set_args(&guest_args, &guest_env);
+
+    match seed.as_deref() {
+        Some("entropy") => vm.seed_rng_from_entropy(),
+        Some(seed) => vm.seed_rng(seed.parse().map_err(|_| CliError::InvalidSeed)?),
+        None => {}
+    }
+
+    if trace {
+        vm.set_trace(io::stderr());
+    }
+
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    if jit {
+        vm.set_dispatch(rsvm::decode::Dispatch::Compiled);
+    }
+
+    if profile.is_some() {
+        vm.enable_profiling();
+    }
+    if coverage.is_some() {
+        vm.enable_coverage();
+    }
 
-    vm.run_program();
+    if let Some(root) = root {
+        vm.set_fs_root(root).map_err(|_| CliError::InvalidRoot)?;
+    }
+
+    if let Some(record) = record {
+        let log = fs::File::create(record).map_err(|_| CliError::FailedToOpenRecording)?;
+        vm.record_to(io::BufWriter::new(log))
+            .map_err(|_| CliError::FailedToOpenRecording)?;
+    } else if let Some(replay) = replay {
+        let log = fs::File::open(replay).map_err(|_| CliError::FailedToOpenRecording)?;
+        vm.replay_from(io::BufReader::new(log))
+            .map_err(|_| CliError::FailedToOpenRecording)?;
+    }
+
+    if let Some(port) = gdb {
+        let port = port.parse().map_err(|_| CliError::InvalidPort)?;
+        let mut debugger = Debugger::new(vm);
+
+        if debug_over_gdb(&mut debugger, port)? == Ending::Killed {
+            return Ok(ExitStatus(KILLED_EXIT_CODE));
+        }
+
+        vm = debugger.into_vm();
+    }
+
+    let status = vm.run_program();
 
-    println!("{:?}", vm);
+    if let (Some(path), Some(profile)) = (profile, vm.profile()) {
+        let folded = fs::File::create(path).map_err(|_| CliError::FailedToWriteProfile)?;
+        profile
+            .write_folded(io::BufWriter::new(folded), vm.debug_info())
+            .map_err(|_| CliError::FailedToWriteProfile)?;
 
-    Ok(())
+        let _ = profile.write_report(io::stderr(), PROFILE_REPORT_LEN, vm.debug_info());
+    }
+
+    if let (Some(path), Some(coverage)) = (coverage, vm.coverage()) {
+        let lcov = fs::File::create(path).map_err(|_| CliError::FailedToWriteCoverage)?;
+        coverage
+            .write_lcov(io::BufWriter::new(lcov), vm.debug_info())
+            .map_err(|_| CliError::FailedToWriteCoverage)?;
+
+        let _ = coverage.write_summary(io::stderr(), vm.debug_info());
+    }
+
+    if dump {
+        eprintln!("{:?}", vm);
+    }
+
+    status.map_err(CliError::Fault)
 }
 
 fn main() {
-    if let Err(error) = try_main() {
-        eprintln!("{:?}", error);
-        process::exit(1)
+    match try_main() {
+        Ok(status) => process::exit(status.process_code() as i32),
+        Err(error) => {
+            eprintln!("{:?}", error);
+            process::exit(error.exit_code())
+        }
     }
 }
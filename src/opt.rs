@@ -0,0 +1,774 @@
+use std::collections::BTreeSet;
+
+use crate::decode::Instr;
+use crate::scheduler::SharedBuffer;
+use crate::{ExitStatus, FaultKind, VM};
+
+/// How many times the passes go over the code at most, each getting to
+/// what the ones before it left behind.
+const MAX_ROUNDS: usize = 16;
+
+/// How many instructions `verify` lets the original program run for.
+pub const VERIFY_STEPS: usize = 1 << 28;
+
+const EQUAL: usize = 0;
+const NOT_EQUAL: usize = 1;
+const GREATER: usize = 2;
+const SMALLER: usize = 3;
+const OVERFLOW: usize = 4;
+
+/// Why a program was left the way it was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unsupported {
+    /// Some of the code does not decode, or static jumps lead into the
+    /// middle of an instruction.
+    Undecodable,
+    /// The program takes interrupts, whose handlers may run between any two
+    /// instructions and find the registers and stack as they were there.
+    Interrupts,
+}
+
+/// How the optimized program behaved differently from the original.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    /// The original program did not stop within `VERIFY_STEPS` instructions.
+    Unfinished,
+    Status {
+        original: Result<ExitStatus, FaultKind>,
+        optimized: Result<ExitStatus, FaultKind>,
+    },
+    Output,
+}
+
+/// What is known about the registers and flags at some point of a basic
+/// block.
+#[derive(Clone, Copy, Debug, Default)]
+struct Known {
+    regs: [Option<usize>; 4],
+    /// Registers known to fit in a word, which pushing and popping them
+    /// back leaves as they are.
+    narrow: [bool; 4],
+    /// The comparison flags and overflow.
+    flags: [Option<bool>; 5],
+}
+
+impl Known {
+    /// The register an arithmetic or move instruction writes and the value
+    /// it writes, along with whether it overflows, when its operands are
+    /// known.
+    fn eval(&self, instr: Instr) -> Option<(u8, usize, bool)> {
+        let reg = |reg: u8| self.regs[reg as usize];
+        let word = |reg: u8| self.regs[reg as usize].map(|value| value as u32);
+        let wide = |(value, overflow): (u32, bool)| (value as usize, overflow);
+
+        let (dst, (value, overflow)) = match instr {
+            Instr::MovLitReg(dst, lit) => (dst, (lit as usize, false)),
+            Instr::MovRegReg(dst, src) => (dst, (reg(src)?, false)),
+            Instr::AddReg(a, b) => (a, reg(a)?.overflowing_add(reg(b)?)),
+            Instr::SubReg(a, b) => (a, reg(a)?.overflowing_sub(reg(b)?)),
+            Instr::MulReg(a, b) => (a, reg(a)?.overflowing_mul(reg(b)?)),
+            Instr::DivReg(a, b) => (a, (reg(a)?.checked_div(reg(b)?)?, false)),
+            Instr::NotReg(dst) => (dst, (!reg(dst)?, false)),
+            Instr::AndReg(a, b) => (a, (reg(a)? & reg(b)?, false)),
+            Instr::OrReg(a, b) => (a, (reg(a)? | reg(b)?, false)),
+            Instr::XorReg(a, b) => (a, (reg(a)? ^ reg(b)?, false)),
+            Instr::IncReg(dst) => (dst, reg(dst)?.overflowing_add(1)),
+            Instr::DecReg(dst) => (dst, reg(dst)?.overflowing_sub(1)),
+            Instr::AddRegNum(dst, lit) => (dst, wide(word(dst)?.overflowing_add(lit))),
+            Instr::SubRegNum(dst, lit) => (dst, wide(word(dst)?.overflowing_sub(lit))),
+            Instr::MulRegNum(dst, lit) => (dst, wide(word(dst)?.overflowing_mul(lit))),
+            Instr::DivRegNum(dst, lit) => (dst, (reg(dst)?.checked_div(lit as usize)?, false)),
+            Instr::AndRegNum(dst, lit) => (dst, (reg(dst)? & lit as usize, false)),
+            Instr::OrRegNum(dst, lit) => (dst, (reg(dst)? | lit as usize, false)),
+            Instr::XorRegNum(dst, lit) => (dst, (reg(dst)? ^ lit as usize, false)),
+            _ => return None,
+        };
+
+        Some((dst, value, overflow))
+    }
+
+    /// Whether the register `instr` writes fits in a word whatever it holds.
+    fn narrow(&self, instr: Instr) -> bool {
+        let narrow = |reg: u8| self.narrow[reg as usize];
+
+        match instr {
+            Instr::MovLitReg(..)
+            | Instr::PopReg(_)
+            | Instr::MovHeapReg(..)
+            | Instr::MovPtrReg(..)
+            | Instr::AddRegNum(..)
+            | Instr::SubRegNum(..)
+            | Instr::MulRegNum(..)
+            | Instr::AndRegNum(..)
+            | Instr::ThreadSelf(_) => true,
+            Instr::MovRegReg(_, src) => narrow(src),
+            Instr::DivReg(reg, _)
+            | Instr::DivRegNum(reg, _)
+            | Instr::OrRegNum(reg, _)
+            | Instr::XorRegNum(reg, _) => narrow(reg),
+            Instr::AndReg(a, b) => narrow(a) || narrow(b),
+            Instr::OrReg(a, b) | Instr::XorReg(a, b) => narrow(a) && narrow(b),
+            _ => false,
+        }
+    }
+
+    fn compare(&mut self, operands: Option<(u32, u32)>) {
+        let flags = operands.map(|(a, b)| [a == b, a != b, a > b, a < b]);
+
+        for (flag, value) in self.flags.iter_mut().zip(0..SMALLER + 1) {
+            *flag = flags.map(|flags| flags[value]);
+        }
+    }
+
+    /// Whether comparing `operands` leaves the flags as they are.
+    fn compares_to_same(&self, operands: Option<(u32, u32)>) -> bool {
+        let mut known = *self;
+        known.compare(operands);
+
+        operands.is_some() && known.flags == self.flags
+    }
+
+    /// Forgets that overflow is clear, for an instruction that may set it.
+    fn may_overflow(&mut self) {
+        if self.flags[OVERFLOW] == Some(false) {
+            self.flags[OVERFLOW] = None;
+        }
+    }
+
+    /// Moves past `instr` to the next instruction in the block.
+    fn learn(&mut self, instr: Instr) {
+        let reg = |reg: u8| self.regs[reg as usize].map(|value| value as u32);
+
+        match instr {
+            Instr::Call(_)
+            | Instr::Ret
+            | Instr::IntReturn
+            | Instr::Jump(_)
+            | Instr::Exit
+            | Instr::ExitReg(_)
+            | Instr::Syscall
+            | Instr::ThreadSpawn(..)
+            | Instr::ThreadYield
+            | Instr::ThreadJoin(_) => return *self = Known::default(),
+            Instr::FlagReset => self.flags = [Some(false); 5],
+            Instr::CompareRegReg(a, b) => self.compare(reg(a).zip(reg(b))),
+            Instr::CompareRegLit(a, lit) => self.compare(reg(a).map(|a| (a, lit))),
+            Instr::CompareStackLit(_) => self.compare(None),
+            // Not taking the jump tells the flag is clear.
+            Instr::JumpEqual(_) => self.flags[EQUAL] = Some(false),
+            Instr::JumpNotEqual(_) => self.flags[NOT_EQUAL] = Some(false),
+            Instr::JumpGreater(_) => self.flags[GREATER] = Some(false),
+            Instr::JumpSmaller(_) => self.flags[SMALLER] = Some(false),
+            Instr::JumpOverflow(_) => self.flags[OVERFLOW] = Some(false),
+            Instr::AddStack
+            | Instr::SubStack
+            | Instr::MulStack
+            | Instr::IncStack
+            | Instr::DecStack
+            | Instr::AddStackNum(_)
+            | Instr::SubStackNum(_)
+            | Instr::MulStackNum(_) => self.may_overflow(),
+            _ => {}
+        }
+
+        let dst = match instr {
+            Instr::PopReg(dst)
+            | Instr::MovHeapReg(dst, _)
+            | Instr::MovPtrReg(dst, _)
+            | Instr::ThreadSelf(dst) => dst,
+            _ => match self.eval_target(instr) {
+                Some(dst) => dst,
+                None => return,
+            },
+        };
+
+        let narrow = self.narrow(instr);
+        match self.eval(instr) {
+            Some((_, value, overflow)) => {
+                self.regs[dst as usize] = Some(value);
+                self.narrow[dst as usize] = value <= u32::MAX as usize;
+
+                if overflow {
+                    self.flags[OVERFLOW] = Some(true);
+                }
+            }
+            None => {
+                self.regs[dst as usize] = None;
+                self.narrow[dst as usize] = narrow;
+
+                if matches!(
+                    instr,
+                    Instr::AddReg(..)
+                        | Instr::SubReg(..)
+                        | Instr::MulReg(..)
+                        | Instr::IncReg(_)
+                        | Instr::DecReg(_)
+                        | Instr::AddRegNum(..)
+                        | Instr::SubRegNum(..)
+                        | Instr::MulRegNum(..)
+                ) {
+                    self.may_overflow();
+                }
+            }
+        }
+    }
+
+    /// The register the instructions `eval` knows about write.
+    fn eval_target(&self, instr: Instr) -> Option<u8> {
+        match instr {
+            Instr::MovLitReg(dst, _)
+            | Instr::MovRegReg(dst, _)
+            | Instr::AddReg(dst, _)
+            | Instr::SubReg(dst, _)
+            | Instr::MulReg(dst, _)
+            | Instr::DivReg(dst, _)
+            | Instr::NotReg(dst)
+            | Instr::AndReg(dst, _)
+            | Instr::OrReg(dst, _)
+            | Instr::XorReg(dst, _)
+            | Instr::IncReg(dst)
+            | Instr::DecReg(dst)
+            | Instr::AddRegNum(dst, _)
+            | Instr::SubRegNum(dst, _)
+            | Instr::MulRegNum(dst, _)
+            | Instr::DivRegNum(dst, _)
+            | Instr::AndRegNum(dst, _)
+            | Instr::OrRegNum(dst, _)
+            | Instr::XorRegNum(dst, _) => Some(dst),
+            _ => None,
+        }
+    }
+
+    /// The flag a conditional jump tests.
+    fn tested(instr: Instr) -> Option<usize> {
+        match instr {
+            Instr::JumpEqual(_) => Some(EQUAL),
+            Instr::JumpNotEqual(_) => Some(NOT_EQUAL),
+            Instr::JumpGreater(_) => Some(GREATER),
+            Instr::JumpSmaller(_) => Some(SMALLER),
+            Instr::JumpOverflow(_) => Some(OVERFLOW),
+            _ => None,
+        }
+    }
+}
+
+/// Folds two literals pushed right before a stack operation, `top` having
+/// been pushed last. Operations that would overflow are left alone, as they
+/// set the flag.
+fn fold_stack(top: u32, below: u32, op: Instr) -> Option<u32> {
+    match op {
+        Instr::AddStack => top.checked_add(below),
+        Instr::SubStack => top.checked_sub(below),
+        Instr::MulStack => top.checked_mul(below),
+        Instr::DivStack => top.checked_div(below),
+        Instr::AndStack => Some(top & below),
+        Instr::OrStack => Some(top | below),
+        Instr::XorStack => Some(top ^ below),
+        _ => None,
+    }
+}
+
+/// Folds a literal pushed right before a stack operation on it alone.
+fn fold_stack_num(value: u32, op: Instr) -> Option<u32> {
+    match op {
+        Instr::IncStack => value.checked_add(1),
+        Instr::DecStack => value.checked_sub(1),
+        Instr::NotStack => Some(!value),
+        Instr::AddStackNum(num) => (num as u32).checked_add(value),
+        Instr::SubStackNum(num) => (num as u32).checked_sub(value),
+        Instr::MulStackNum(num) => (num as u32).checked_mul(value),
+        Instr::DivStackNum(num) => (num as u32).checked_div(value),
+        Instr::AndStackNum(num) => Some(num as u32 & value),
+        Instr::OrStackNum(num) => Some(num as u32 | value),
+        Instr::XorStackNum(num) => Some(num as u32 ^ value),
+        _ => None,
+    }
+}
+
+/// Merges a push with the pop right after it into a move, or nothing at
+/// all.
+fn fuse(push: Instr, pop: Instr, known: &Known) -> Option<Option<Instr>> {
+    let fused = match (push, pop) {
+        (Instr::PushLit(lit), Instr::PopReg(reg)) => Instr::MovLitReg(reg, lit),
+        (Instr::PushLit(lit), Instr::PopHeap(addr)) => Instr::MovLitHeap(addr, lit),
+        (Instr::PushHeap(addr), Instr::PopReg(reg)) => Instr::MovHeapReg(reg, addr),
+        (Instr::PushHeap(src), Instr::PopHeap(dst)) if src == dst => return Some(None),
+        (Instr::PushHeap(src), Instr::PopHeap(dst)) => Instr::MovHeapHeap(src, dst),
+        (Instr::PushReg(reg), Instr::PopHeap(addr)) => Instr::MovRegHeap(addr, reg),
+        // Going through the stack cuts a register down to a word, which
+        // only a register that fits in one can skip.
+        (Instr::PushReg(src), Instr::PopReg(dst)) if known.narrow[src as usize] => {
+            match src == dst {
+                true => return Some(None),
+                false => Instr::MovRegReg(dst, src),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Some(fused))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    /// Where the instruction was in the original code.
+    offset: usize,
+    /// What it became, if anything is left of it.
+    instr: Option<Instr>,
+}
+
+struct Code {
+    slots: Vec<Slot>,
+    len: usize,
+    /// Offsets reached other than from the instruction before them, which
+    /// start basic blocks.
+    leaders: BTreeSet<usize>,
+    /// Offsets that might be held as data and jumped to through memory, so
+    /// they have to stay where they are.
+    pinned: BTreeSet<usize>,
+}
+
+impl Code {
+    fn index(&self, offset: usize) -> Option<usize> {
+        self.slots
+            .binary_search_by_key(&offset, |slot| slot.offset)
+            .ok()
+    }
+
+    fn instr(&self, index: usize) -> Option<Instr> {
+        self.slots.get(index)?.instr
+    }
+
+    /// The first instruction left at or after the slot `index`.
+    fn live(&self, index: usize) -> Option<usize> {
+        (index..self.slots.len()).find(|&index| self.slots[index].instr.is_some())
+    }
+
+    /// The instruction a jump to `target` ends up running.
+    fn resolve(&self, target: u32) -> Option<usize> {
+        self.live(self.index(target as usize)?)
+    }
+
+    /// Whether anything but the instruction at `from` leads to `to`, with
+    /// everything in between removed.
+    fn entered(&self, from: usize, to: usize) -> bool {
+        let (from, to) = (self.slots[from].offset, self.slots[to].offset);
+
+        self.leaders.range(from + 1..=to).next().is_some()
+    }
+
+    /// The instruction after `index`, if it is in the same basic block.
+    fn following(&self, index: usize) -> Option<usize> {
+        let next = self.live(index + 1)?;
+
+        match self.entered(index, next) {
+            true => None,
+            false => Some(next),
+        }
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.slots[index].instr = None;
+    }
+
+    fn replace(&mut self, index: usize, instr: Instr) {
+        self.slots[index].instr = Some(instr);
+    }
+
+    /// Removes what the entry point and the pinned offsets cannot reach.
+    fn sweep(&mut self) -> bool {
+        let mut reached = vec![false; self.slots.len()];
+        let mut pending = std::iter::once(0)
+            .chain(self.pinned.iter().copied())
+            .filter_map(|offset| self.live(self.index(offset)?))
+            .collect::<Vec<_>>();
+
+        while let Some(index) = pending.pop() {
+            if std::mem::replace(&mut reached[index], true) {
+                continue;
+            }
+
+            let instr = self.slots[index].instr.unwrap();
+            let falls_through = !matches!(
+                instr,
+                Instr::Jump(_) | Instr::Ret | Instr::IntReturn | Instr::Exit | Instr::ExitReg(_)
+            );
+
+            if falls_through {
+                pending.extend(self.live(index + 1));
+            }
+            if let Some(target) = instr.target() {
+                pending.extend(self.resolve(target as u32));
+            }
+        }
+
+        let mut changed = false;
+        for (index, reached) in reached.into_iter().enumerate() {
+            if !reached && self.slots[index].instr.is_some() {
+                self.remove(index);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Rewrites the instruction at `index` given what is `known` before it,
+    /// telling whether anything changed.
+    fn simplify(&mut self, index: usize, known: &Known) -> bool {
+        let instr = self.slots[index].instr.unwrap();
+        let next = self.following(index);
+        let after = next.and_then(|next| self.following(next));
+
+        if let (Instr::PushLit(below), Some(Instr::PushLit(top)), Some(op)) = (
+            instr,
+            next.and_then(|next| self.instr(next)),
+            after.and_then(|after| self.instr(after)),
+        ) {
+            if let Some(value) = fold_stack(top, below, op) {
+                self.replace(index, Instr::PushLit(value));
+                self.remove(next.unwrap());
+                self.remove(after.unwrap());
+                return true;
+            }
+        }
+
+        if let Some(next) = next {
+            let op = self.instr(next).unwrap();
+
+            let fused = match instr {
+                Instr::PushLit(value) => {
+                    fold_stack_num(value, op).map(|value| Some(Instr::PushLit(value)))
+                }
+                _ => None,
+            }
+            .or_else(|| fuse(instr, op, known));
+
+            if let Some(fused) = fused {
+                match fused {
+                    Some(fused) => self.replace(index, fused),
+                    None => self.remove(index),
+                }
+                self.remove(next);
+                return true;
+            }
+        }
+
+        let follows = |target: u32| self.resolve(target) == self.live(index + 1);
+
+        match instr {
+            Instr::Nop => {
+                self.remove(index);
+                return true;
+            }
+            Instr::MovRegReg(dst, src) if dst == src => {
+                self.remove(index);
+                return true;
+            }
+            Instr::Jump(target) if follows(target) => {
+                self.remove(index);
+                return true;
+            }
+            Instr::Jump(target) => {
+                if let Some(
+                    end @ (Instr::Ret | Instr::IntReturn | Instr::Exit | Instr::ExitReg(_)),
+                ) = self.resolve(target).and_then(|at| self.instr(at))
+                {
+                    self.replace(index, end);
+                    return true;
+                }
+            }
+            Instr::CompareRegReg(a, b) => {
+                let word = |reg: u8| known.regs[reg as usize].map(|value| value as u32);
+
+                if known.compares_to_same(word(a).zip(word(b))) {
+                    self.remove(index);
+                    return true;
+                }
+            }
+            Instr::CompareRegLit(reg, lit) => {
+                let word = known.regs[reg as usize].map(|value| value as u32);
+
+                if known.compares_to_same(word.map(|word| (word, lit))) {
+                    self.remove(index);
+                    return true;
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(flag) = Known::tested(instr) {
+            let target = instr.target().unwrap() as u32;
+
+            match known.flags[flag] {
+                _ if follows(target) => self.remove(index),
+                Some(true) => self.replace(index, Instr::Jump(target)),
+                Some(false) => self.remove(index),
+                None => return self.thread(index, instr),
+            }
+            return true;
+        }
+
+        if let Some((dst, value, overflow)) = known.eval(instr) {
+            if !overflow && known.regs[dst as usize] == Some(value) {
+                self.remove(index);
+                return true;
+            }
+
+            let folded = Instr::MovLitReg(dst, value as u32);
+            let fits = value <= u32::MAX as usize && folded.encoded_len() <= instr.encoded_len();
+            if !overflow && fits && folded != instr {
+                self.replace(index, folded);
+                return true;
+            }
+        }
+
+        self.thread(index, instr)
+    }
+
+    /// Points a jump to another jump at where that one goes.
+    fn thread(&mut self, index: usize, instr: Instr) -> bool {
+        let target = match instr.target() {
+            Some(target) => target as u32,
+            None => return false,
+        };
+
+        let through = match self.resolve(target).and_then(|at| self.instr(at)) {
+            Some(Instr::Jump(through)) => through,
+            _ => return false,
+        };
+
+        if self.resolve(through) == self.resolve(target) {
+            return false;
+        }
+
+        self.replace(index, instr.retarget(|_| through));
+        true
+    }
+
+    /// Goes over every basic block once, with what is known about the
+    /// registers and flags along the way.
+    fn peephole(&mut self) -> bool {
+        let mut changed = false;
+        let mut known = Known::default();
+        let mut prev = None;
+        let mut index = 0;
+
+        while let Some(at) = self.live(index) {
+            let entered = match prev {
+                Some(prev) => self.entered(prev, at),
+                None => true,
+            };
+            if entered {
+                known = Known::default();
+            }
+
+            if self.simplify(at, &known) {
+                changed = true;
+                index = at;
+                continue;
+            }
+
+            known.learn(self.slots[at].instr.unwrap());
+            prev = Some(at);
+            index = at + 1;
+        }
+
+        changed
+    }
+
+    /// The offset every slot moves to, padding with `Nop`s in front of the
+    /// pinned ones, and the length of the code that makes.
+    fn layout(&self) -> (Vec<usize>, usize) {
+        let mut offsets = Vec::with_capacity(self.slots.len());
+        let mut at = 0;
+
+        for slot in &self.slots {
+            if self.pinned.contains(&slot.offset) {
+                at = slot.offset;
+            }
+
+            offsets.push(at);
+            at += slot.instr.map_or(0, Instr::encoded_len);
+        }
+
+        (offsets, at)
+    }
+
+    fn emit(&self) -> (Vec<u8>, impl Fn(usize) -> usize + '_) {
+        let (offsets, len) = self.layout();
+        let relocate = move |offset: usize| match self.index(offset) {
+            Some(index) => offsets[index],
+            None if offset == self.len => len,
+            None => offset,
+        };
+
+        let mut code = Vec::with_capacity(len);
+        for slot in &self.slots {
+            if self.pinned.contains(&slot.offset) {
+                code.resize(slot.offset, 0x2F);
+            }
+
+            if let Some(instr) = slot.instr {
+                instr
+                    .retarget(|target| relocate(target as usize) as u32)
+                    .encode(&mut code);
+            }
+        }
+
+        (code, relocate)
+    }
+}
+
+/// Optimizes a program, header and all: merges pushes with the pops right
+/// after them, folds arithmetic on literals and on registers known to hold
+/// them, drops `Nop`s, moves and compares that change nothing and jumps to
+/// the next instruction, decides conditional jumps on flags known ahead of
+/// time, threads jumps to jumps, and removes code nothing reaches.
+///
+/// Code addresses are only looked for in jumps and as literals the program
+/// pushes, moves or has in its header. Instructions at an offset such a
+/// literal holds keep it, so the literal still leads to them; a program
+/// that puts code addresses together any other way has to be left alone.
+pub fn optimize(bytecode: Vec<u8>) -> Result<Vec<u8>, Unsupported> {
+    if bytecode.len() < 4 {
+        return Ok(bytecode);
+    }
+
+    let mut vm = VM::new();
+    vm.load_program(bytecode.clone());
+
+    if vm.hdr_size > vm.bytecode.len() {
+        return Ok(bytecode);
+    }
+
+    let code = &vm.bytecode[vm.hdr_size..];
+    let instrs = vm.decoded.iter().collect::<Vec<_>>();
+
+    let decoded_len = instrs.iter().map(|&(_, _, len)| len).sum::<usize>();
+    if decoded_len != code.len() || vm.decoded.reachable(code).len() != instrs.len() {
+        return Err(Unsupported::Undecodable);
+    }
+    if vm
+        .decoded
+        .instrs()
+        .any(|instr| matches!(instr, Instr::IntEnable | Instr::IntReturn))
+    {
+        return Err(Unsupported::Interrupts);
+    }
+
+    let mut code = Code {
+        slots: instrs
+            .iter()
+            .map(|&(offset, instr, _)| Slot {
+                offset,
+                instr: Some(instr),
+            })
+            .collect(),
+        len: code.len(),
+        leaders: BTreeSet::new(),
+        pinned: BTreeSet::new(),
+    };
+
+    let data = &vm.bytecode[..vm.hdr_size - 4];
+    let literals = instrs
+        .iter()
+        .filter_map(|&(_, instr, _)| match instr {
+            Instr::PushLit(lit) | Instr::MovLitReg(_, lit) | Instr::MovLitHeap(_, lit) => {
+                Some(lit as usize)
+            }
+            _ => None,
+        })
+        .chain(data.iter().map(|&byte| byte as usize));
+    code.pinned = literals
+        .filter(|&offset| code.index(offset).is_some())
+        .collect();
+
+    code.leaders = instrs
+        .iter()
+        .filter_map(|&(_, instr, _)| instr.target())
+        .chain(std::iter::once(0))
+        .chain(code.pinned.iter().copied())
+        .collect();
+
+    for _ in 0..MAX_ROUNDS {
+        let swept = code.sweep();
+        if !code.peephole() && !swept {
+            break;
+        }
+    }
+
+    let (optimized, relocate) = code.emit();
+
+    let mut bytecode = vm.bytecode[..vm.hdr_size].to_vec();
+    bytecode.extend_from_slice(&optimized);
+
+    if let Some(mut info) = vm.debug_info.take() {
+        info.relocate(relocate);
+        info.append_to(&mut bytecode);
+    }
+
+    Ok(bytecode)
+}
+
+/// Runs a program for `verify`, with the inputs it takes from the host
+/// logged to or fed from `log`.
+fn run<S: AsRef<str>>(
+    bytecode: &[u8],
+    args: &[S],
+    log: Result<SharedBuffer, &[u8]>,
+) -> Result<(Result<ExitStatus, FaultKind>, Vec<u8>), Mismatch> {
+    let output = SharedBuffer::default();
+
+    let mut vm = VM::new();
+    vm.load_program(bytecode.to_vec());
+    vm.set_args(args, &[]);
+    vm.set_output(output.clone());
+
+    match log {
+        Ok(log) => vm.record_to(log),
+        Err(log) => vm.replay_from(log),
+    }
+    .map_err(|_| Mismatch::Unfinished)?;
+
+    for _ in 0..VERIFY_STEPS {
+        if vm.is_stopped() {
+            break;
+        }
+        vm.step();
+    }
+
+    let status = match vm.exit_status() {
+        Some(status) => status.map_err(|fault| fault.kind),
+        None => return Err(Mismatch::Unfinished),
+    };
+
+    Ok((status, output.take()))
+}
+
+/// Runs both programs with `args` and checks that they end the same way
+/// with the same output. The optimized one gets the inputs the original
+/// took from the host replayed to it. Faults are only told apart by kind,
+/// as they happen at different offsets.
+pub fn verify<S: AsRef<str>>(
+    original: &[u8],
+    optimized: &[u8],
+    args: &[S],
+) -> Result<(), Mismatch> {
+    let log = SharedBuffer::default();
+
+    let (original, expected) = run(original, args, Ok(log.clone()))?;
+    let (optimized, output) = run(optimized, args, Err(&log.take()))?;
+
+    if original != optimized {
+        return Err(Mismatch::Status {
+            original,
+            optimized,
+        });
+    }
+    if output != expected {
+        return Err(Mismatch::Output);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::debuginfo::DebugInfo;
+use crate::VM;
+
+/// Execution counts gathered while a `VM` runs with profiling enabled.
+/// Every executed instruction is one sample, attributed to its opcode, its
+/// offset and the guest call stack it ran on.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    samples: u64,
+    opcodes: Vec<u64>,
+    instructions: HashMap<usize, u64>,
+    /// Keyed by the return addresses of the frames, outermost first,
+    /// followed by the offset of the instruction itself.
+    stacks: HashMap<Vec<usize>, u64>,
+    scratch: Vec<usize>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            samples: 0,
+            opcodes: vec![0; 256],
+            instructions: HashMap::new(),
+            stacks: HashMap::new(),
+            scratch: Vec::new(),
+        }
+    }
+}
+
+/// Sorts counts from the highest down, breaking ties by key so that reports
+/// come out the same on every run.
+fn hottest<K: Copy + Ord>(counts: impl Iterator<Item = (K, u64)>, n: usize) -> Vec<(K, u64)> {
+    let mut counts = counts.filter(|&(_, count)| count > 0).collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(n);
+
+    counts
+}
+
+fn frame_name(debug_info: Option<&DebugInfo>, prgrm_cntr: usize) -> String {
+    match debug_info.and_then(|info| info.symbol(prgrm_cntr)) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{:#06x}", prgrm_cntr),
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Default::default()
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn opcode_count(&self, opcode: u8) -> u64 {
+        self.opcodes[opcode as usize]
+    }
+
+    pub fn instruction_count(&self, prgrm_cntr: usize) -> u64 {
+        self.instructions.get(&prgrm_cntr).copied().unwrap_or(0)
+    }
+
+    fn record(&mut self, opcode: Option<u8>, prgrm_cntr: usize) {
+        self.samples += 1;
+
+        if let Some(opcode) = opcode {
+            self.opcodes[opcode as usize] += 1;
+        }
+        *self.instructions.entry(prgrm_cntr).or_insert(0) += 1;
+
+        // Most samples land on a stack seen before, so the key is only
+        // allocated for new ones.
+        match self.stacks.get_mut(&self.scratch) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.scratch.clone(), 1);
+            }
+        }
+    }
+
+    /// Writes the samples in the folded stacks format taken by flamegraph
+    /// tooling, one `outer;inner count` line per distinct stack. Frames are
+    /// named after the label of the code they were in, or its offset when
+    /// there is no debug info.
+    pub fn write_folded<W: Write>(
+        &self,
+        mut w: W,
+        debug_info: Option<&DebugInfo>,
+    ) -> io::Result<()> {
+        let mut folded = BTreeMap::new();
+
+        for (stack, count) in &self.stacks {
+            let (leaf, callers) = match stack.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+
+            // Return addresses point past the `call`, which is the
+            // instruction that belongs to the caller.
+            let mut names = callers
+                .iter()
+                .map(|addr| frame_name(debug_info, addr.saturating_sub(1)))
+                .collect::<Vec<_>>();
+            names.push(frame_name(debug_info, *leaf));
+
+            *folded.entry(names.join(";")).or_insert(0) += count;
+        }
+
+        for (stack, count) in folded {
+            writeln!(w, "{} {}", stack, count)?;
+        }
+
+        w.flush()
+    }
+
+    /// Writes the `n` most executed opcodes and instructions, along with the
+    /// share of samples each took.
+    pub fn write_report<W: Write>(
+        &self,
+        mut w: W,
+        n: usize,
+        debug_info: Option<&DebugInfo>,
+    ) -> io::Result<()> {
+        let percent = |count: u64| 100.0 * count as f64 / self.samples.max(1) as f64;
+
+        writeln!(w, "{} instructions executed", self.samples)?;
+
+        writeln!(w, "\nHottest opcodes:")?;
+        let opcodes = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .map(|(opcode, &count)| (opcode, count));
+        for (opcode, count) in hottest(opcodes, n) {
+            writeln!(w, "{:>12} {:>7.2}%  {:#04x}", count, percent(count), opcode)?;
+        }
+
+        writeln!(w, "\nHottest instructions:")?;
+        let instructions = self
+            .instructions
+            .iter()
+            .map(|(&offset, &count)| (offset, count));
+        for (offset, count) in hottest(instructions, n) {
+            write!(w, "{:>12} {:>7.2}%  {:#06x}", count, percent(count), offset)?;
+
+            match debug_info.and_then(|info| info.locate(offset)) {
+                Some(location) => writeln!(w, " {}", location)?,
+                None => writeln!(w)?,
+            }
+        }
+
+        w.flush()
+    }
+}
+
+impl VM {
+    /// Makes the `VM` count every instruction it executes from now on.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Takes note of the call stack of the instruction about to run, which
+    /// that instruction might change.
+    pub(crate) fn prepare_sample(&mut self) {
+        let mut profile = match self.profile.take() {
+            Some(profile) => profile,
+            None => return,
+        };
+
+        profile.scratch.clear();
+        profile.scratch.extend(self.return_addrs());
+        profile.scratch.reverse();
+        profile.scratch.push(self.prgrm_cntr);
+
+        self.profile = Some(profile);
+    }
+
+    /// Counts the instruction that just ran on the stack `prepare_sample`
+    /// took note of.
+    pub(crate) fn sample(&mut self) {
+        let opcode = self.bytecode.get(self.instr_start + self.hdr_size).copied();
+
+        if let Some(profile) = &mut self.profile {
+            profile.record(opcode, self.instr_start);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::channel::{Channel, Message};
+use crate::syscall::{io_status, MAX_IO_LEN};
+use crate::{FaultKind, VM};
+
+const MAGIC: &[u8; 8] = b"RSVMREC1";
+
+const TAG_STDIN: u8 = 0;
+const TAG_MONOTONIC: u8 = 1;
+const TAG_WALL: u8 = 2;
+const TAG_RANDOM: u8 = 3;
+const TAG_RECEIVED: u8 = 4;
+const TAG_FILE_READ: u8 = 5;
+
+/// What a channel receive came across.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Received {
+    /// The message it took off the channel.
+    Taken(Message),
+    /// The message at the front, which it had to leave there.
+    Refused(Message),
+    Empty,
+    Closed,
+}
+
+/// A nondeterministic input consumed by a syscall.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Stdin(Vec<u8>),
+    Monotonic(Duration),
+    Wall(Duration),
+    Random(Vec<u32>),
+    Received(Received),
+    /// The bytes a file read got, or the status it failed with.
+    FileRead(Result<Vec<u8>, usize>),
+}
+
+fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+fn parse_words(payload: &[u8]) -> Option<Vec<u32>> {
+    if !payload.len().is_multiple_of(4) {
+        return None;
+    }
+
+    Some(
+        payload
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+// A message is a byte telling a word from a buffer, followed by its words.
+fn encode_message(kind: u8, message: &Message) -> Vec<u8> {
+    let (buffer, payload) = match message {
+        Message::Word(word) => (0, words(&[*word])),
+        Message::Buffer(buf) => (1, words(buf)),
+    };
+
+    let mut buf = vec![kind, buffer];
+    buf.extend_from_slice(&payload);
+    buf
+}
+
+fn decode_message(payload: &[u8]) -> Option<Message> {
+    let (&buffer, payload) = payload.split_first()?;
+    let words = parse_words(payload)?;
+
+    match (buffer, words.as_slice()) {
+        (0, &[word]) => Some(Message::Word(word)),
+        (1, _) => Some(Message::Buffer(words)),
+        _ => None,
+    }
+}
+
+impl Event {
+    fn encode(&self) -> (u8, Vec<u8>) {
+        let duration = |duration: &Duration| {
+            let mut buf = duration.as_secs().to_be_bytes().to_vec();
+            buf.extend_from_slice(&duration.subsec_nanos().to_be_bytes());
+            buf
+        };
+
+        match self {
+            Event::Stdin(bytes) => (TAG_STDIN, bytes.clone()),
+            Event::Monotonic(now) => (TAG_MONOTONIC, duration(now)),
+            Event::Wall(now) => (TAG_WALL, duration(now)),
+            Event::Random(random) => (TAG_RANDOM, words(random)),
+            Event::Received(received) => (
+                TAG_RECEIVED,
+                match received {
+                    Received::Taken(message) => encode_message(0, message),
+                    Received::Refused(message) => encode_message(1, message),
+                    Received::Empty => vec![2],
+                    Received::Closed => vec![3],
+                },
+            ),
+            Event::FileRead(Ok(bytes)) => {
+                let mut buf = vec![0];
+                buf.extend_from_slice(bytes);
+                (TAG_FILE_READ, buf)
+            }
+            Event::FileRead(Err(status)) => {
+                let mut buf = vec![1];
+                buf.extend_from_slice(&(*status as u32).to_be_bytes());
+                (TAG_FILE_READ, buf)
+            }
+        }
+    }
+
+    fn decode(tag: u8, payload: Vec<u8>) -> io::Result<Event> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed replay event");
+
+        let duration = |payload: &[u8]| -> io::Result<Duration> {
+            if payload.len() != 12 {
+                return Err(invalid());
+            }
+
+            let secs = u64::from_be_bytes(payload[..8].try_into().unwrap());
+            let nanos = u32::from_be_bytes(payload[8..].try_into().unwrap());
+
+            Ok(Duration::new(secs, nanos))
+        };
+
+        match tag {
+            TAG_STDIN => Ok(Event::Stdin(payload)),
+            TAG_MONOTONIC => Ok(Event::Monotonic(duration(&payload)?)),
+            TAG_WALL => Ok(Event::Wall(duration(&payload)?)),
+            TAG_RANDOM => parse_words(&payload).map(Event::Random).ok_or_else(invalid),
+            TAG_RECEIVED => {
+                let received = match payload.split_first() {
+                    Some((0, message)) => decode_message(message).map(Received::Taken),
+                    Some((1, message)) => decode_message(message).map(Received::Refused),
+                    Some((2, [])) => Some(Received::Empty),
+                    Some((3, [])) => Some(Received::Closed),
+                    _ => None,
+                };
+
+                received.map(Event::Received).ok_or_else(invalid)
+            }
+            TAG_FILE_READ => match payload.split_first() {
+                Some((0, bytes)) => Ok(Event::FileRead(Ok(bytes.to_vec()))),
+                Some((1, status)) if status.len() == 4 => Ok(Event::FileRead(Err(
+                    u32::from_be_bytes(status.try_into().unwrap()) as usize,
+                ))),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) enum Mode {
+    #[default]
+    Live,
+    Record(Box<dyn Write + Send>),
+    Replay(VecDeque<Event>),
+}
+
+impl fmt::Debug for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Live => f.write_str("Live"),
+            Mode::Record(_) => f.write_str("Record"),
+            Mode::Replay(events) => write!(f, "Replay({} events left)", events.len()),
+        }
+    }
+}
+
+pub fn write_event<W: Write + ?Sized>(writer: &mut W, event: &Event) -> io::Result<()> {
+    let (tag, payload) = event.encode();
+
+    writer.write_all(&[tag])?;
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(&payload)?;
+    writer.flush()
+}
+
+pub fn read_events<R: Read>(mut reader: R) -> io::Result<VecDeque<Event>> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an rsvm recording",
+        ));
+    }
+
+    let mut events = VecDeque::new();
+    let mut tag = [0; 1];
+
+    while reader.read(&mut tag)? == 1 {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+
+        // The length comes from the file, so the payload is only allocated
+        // as it's actually read.
+        let len = u32::from_be_bytes(len) as u64;
+        let mut payload = Vec::new();
+        if reader.by_ref().take(len).read_to_end(&mut payload)? as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        events.push_back(Event::decode(tag[0], payload)?);
+    }
+
+    Ok(events)
+}
+
+impl VM {
+    /// Logs every nondeterministic input consumed by the syscalls to `log`.
+    pub fn record_to<W: Write + Send + 'static>(&mut self, mut log: W) -> io::Result<()> {
+        log.write_all(MAGIC)?;
+        self.replay = Mode::Record(Box::new(log));
+
+        Ok(())
+    }
+
+    /// Feeds the inputs logged by `record_to` back to the syscalls instead of
+    /// consulting the host. Running out of events or finding an event of the
+    /// wrong kind faults the `VM`.
+    pub fn replay_from<R: Read>(&mut self, log: R) -> io::Result<()> {
+        self.replay = Mode::Replay(read_events(log)?);
+
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.replay, Mode::Replay(_))
+    }
+
+    /// Produces an input live, logging it when recording, or takes it from
+    /// the log when replaying. `extract` picks the input out of an event of
+    /// the right kind.
+    ///
+    /// Inputs the debugger stepped back over come first, the way they came
+    /// the first time around.
+    fn nondet<T, P, E>(&mut self, produce: P, wrap: fn(T) -> Event, extract: E) -> Option<T>
+    where
+        T: Clone,
+        P: FnOnce(&mut VM) -> Option<T>,
+        E: FnOnce(Event) -> Option<T>,
+    {
+        let replayed = match self.rewound_inputs.pop() {
+            Some(event) => Some(Some(event)),
+            None => match &mut self.replay {
+                Mode::Replay(events) => Some(events.pop_front()),
+                _ => None,
+            },
+        };
+
+        let value = match replayed {
+            Some(event) => {
+                let value = event.and_then(extract);
+
+                if value.is_none() {
+                    self.raise_fault(FaultKind::ReplayDiverged);
+                }
+                value
+            }
+            None => {
+                let value = produce(self)?;
+
+                if let Mode::Record(log) = &mut self.replay {
+                    if write_event(log, &wrap(value.clone())).is_err() {
+                        self.raise_fault(FaultKind::RecordingFailed);
+                    }
+                }
+
+                Some(value)
+            }
+        };
+
+        if let (Some(value), Some(journal)) = (&value, &mut self.input_journal) {
+            journal.push(wrap(value.clone()));
+        }
+
+        value
+    }
+
+    pub(crate) fn read_stdin(&mut self, len: usize) -> Option<Vec<u8>> {
+        self.nondet(
+            |vm| {
+                let mut buf = vec![0; len.min(MAX_IO_LEN)];
+                let read = vm.input.0.read(&mut buf).ok()?;
+
+                buf.truncate(read);
+                Some(buf)
+            },
+            Event::Stdin,
+            |event| match event {
+                Event::Stdin(bytes) => Some(bytes),
+                _ => None,
+            },
+        )
+    }
+
+    pub(crate) fn receive<F>(&mut self, channel: &Channel, accept: F) -> Option<Received>
+    where
+        F: FnOnce(&Message) -> bool,
+    {
+        self.nondet(
+            |_| match channel.recv_if(accept) {
+                Ok(message) => Some(Received::Taken(message)),
+                Err(Some(message)) => Some(Received::Refused(message)),
+                Err(None) if channel.is_closed() => Some(Received::Closed),
+                Err(None) => Some(Received::Empty),
+            },
+            Event::Received,
+            |event| match event {
+                Event::Received(received) => Some(received),
+                _ => None,
+            },
+        )
+    }
+
+    /// Reads up to `len` bytes from the file open as `fd`, which has to be.
+    pub(crate) fn read_file(&mut self, fd: usize, len: usize) -> Option<Result<Vec<u8>, usize>> {
+        self.nondet(
+            |vm| {
+                let file = vm.files.get_mut(fd)?.as_mut()?;
+                let mut buf = vec![0; len];
+
+                Some(match file.read(&mut buf) {
+                    Ok(read) => {
+                        buf.truncate(read);
+                        Ok(buf)
+                    }
+                    Err(error) => Err(io_status(&error)),
+                })
+            },
+            Event::FileRead,
+            |event| match event {
+                Event::FileRead(read) => Some(read),
+                _ => None,
+            },
+        )
+    }
+
+    pub(crate) fn monotonic_time(&mut self) -> Option<Duration> {
+        self.nondet(
+            |vm| Some(vm.clock.monotonic()),
+            Event::Monotonic,
+            |event| match event {
+                Event::Monotonic(now) => Some(now),
+                _ => None,
+            },
+        )
+    }
+
+    pub(crate) fn wall_time(&mut self) -> Option<Duration> {
+        self.nondet(
+            |vm| Some(vm.clock.wall()),
+            Event::Wall,
+            |event| match event {
+                Event::Wall(now) => Some(now),
+                _ => None,
+            },
+        )
+    }
+
+    pub(crate) fn random_words(&mut self, len: usize) -> Option<Vec<u32>> {
+        self.nondet(
+            |vm| Some((0..len).map(|_| vm.rng.next_u32()).collect()),
+            Event::Random,
+            |event| match event {
+                Event::Random(words) if words.len() == len => Some(words),
+                _ => None,
+            },
+        )
+    }
+}
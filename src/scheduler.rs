@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::{ExitStatus, Fault, FaultKind, VM};
+
+const DEFAULT_QUANTUM: u32 = 1024;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_memory: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Exited(ExitStatus),
+    Faulted(Fault),
+    StepLimitExceeded,
+    MemoryLimitExceeded,
+    Panicked(String),
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub id: usize,
+    pub status: Status,
+    pub steps: u64,
+    pub output: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Instance {
+    id: usize,
+    vm: VM,
+    limits: Limits,
+    output: SharedBuffer,
+    steps: u64,
+    status: Status,
+}
+
+impl Instance {
+    fn run_quantum(&mut self, quantum: u32) {
+        let (vm, limits, steps) = (&mut self.vm, self.limits, &mut self.steps);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for _ in 0..quantum {
+                if vm.is_stopped() {
+                    break;
+                }
+                if limits.max_steps.is_some_and(|max| *steps >= max) {
+                    return Status::StepLimitExceeded;
+                }
+
+                vm.step();
+
+                // Waiting counts against the budget too, or an instance
+                // blocked for good would be handed quanta forever.
+                *steps += 1;
+                if vm.is_blocked() {
+                    return Status::Running;
+                }
+
+                if limits.max_memory.is_some_and(|max| vm.memory_usage() > max) {
+                    return Status::MemoryLimitExceeded;
+                }
+            }
+
+            match vm.exit_status() {
+                Some(Ok(status)) => Status::Exited(status),
+                Some(Err(fault))
+                    if fault.kind == FaultKind::OutOfMemory && limits.max_memory.is_some() =>
+                {
+                    Status::MemoryLimitExceeded
+                }
+                Some(Err(fault)) => Status::Faulted(fault),
+                None => Status::Running,
+            }
+        }));
+
+        self.status = match result {
+            Ok(status) => status,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+
+                Status::Panicked(message)
+            }
+        };
+    }
+
+    fn into_outcome(self) -> Outcome {
+        Outcome {
+            id: self.id,
+            status: self.status,
+            steps: self.steps,
+            output: self.output.take(),
+        }
+    }
+}
+
+/// The instances waiting for a worker to run them, along with how many
+/// workers are running one.
+#[derive(Debug)]
+struct Queue {
+    waiting: VecDeque<Instance>,
+    running: usize,
+}
+
+/// Runs many `VM`s side by side, switching between them every `quantum`
+/// executed instructions.
+#[derive(Debug)]
+pub struct Scheduler {
+    instances: Vec<Instance>,
+    quantum: u32,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            instances: Vec::new(),
+            quantum: DEFAULT_QUANTUM,
+        }
+    }
+
+    pub fn set_quantum(&mut self, quantum: u32) {
+        self.quantum = quantum.max(1);
+    }
+
+    pub fn spawn(&mut self, bytecode: Vec<u8>, limits: Limits) -> usize {
+        let mut vm = VM::new();
+        vm.load_program(bytecode);
+
+        self.add(vm, limits)
+    }
+
+    pub fn add(&mut self, mut vm: VM, limits: Limits) -> usize {
+        let id = self.instances.len();
+        let output = SharedBuffer::default();
+        vm.set_output(output.clone());
+        // The heap stops short of the limit before it grows past it.
+        if let Some(max) = limits.max_memory {
+            vm.heap.set_limit(max / mem::size_of::<u32>());
+        }
+
+        self.instances.push(Instance {
+            id,
+            vm,
+            limits,
+            output,
+            steps: 0,
+            status: Status::Running,
+        });
+
+        id
+    }
+
+    pub fn run(self) -> Vec<Outcome> {
+        let quantum = self.quantum;
+        let mut queue = self.instances.into_iter().collect::<VecDeque<_>>();
+        let mut finished = Vec::with_capacity(queue.len());
+
+        while let Some(mut instance) = queue.pop_front() {
+            instance.run_quantum(quantum);
+
+            if instance.status == Status::Running {
+                queue.push_back(instance);
+            } else {
+                finished.push(instance.into_outcome());
+            }
+        }
+
+        finished.sort_by_key(|outcome| outcome.id);
+        finished
+    }
+
+    pub fn run_parallel(self, workers: usize) -> Vec<Outcome> {
+        let quantum = self.quantum;
+        let total = self.instances.len();
+        let queue = Mutex::new(Queue {
+            waiting: self.instances.into_iter().collect(),
+            running: 0,
+        });
+        let requeued = Condvar::new();
+        let finished = Mutex::new(Vec::with_capacity(total));
+
+        thread::scope(|scope| {
+            for _ in 0..workers.max(1) {
+                scope.spawn(|| loop {
+                    let mut instance = {
+                        let mut queue = queue.lock().unwrap();
+
+                        // With nothing waiting, whatever other workers are
+                        // running might still come back to the queue.
+                        loop {
+                            if let Some(instance) = queue.waiting.pop_front() {
+                                queue.running += 1;
+                                break instance;
+                            }
+                            if queue.running == 0 {
+                                return;
+                            }
+
+                            queue = requeued.wait(queue).unwrap();
+                        }
+                    };
+
+                    instance.run_quantum(quantum);
+
+                    let mut queue = queue.lock().unwrap();
+                    queue.running -= 1;
+
+                    if instance.status == Status::Running {
+                        queue.waiting.push_back(instance);
+                    } else {
+                        finished.lock().unwrap().push(instance.into_outcome());
+                    }
+
+                    requeued.notify_all();
+                });
+            }
+        });
+
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort_by_key(|outcome| outcome.id);
+        finished
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
@@ -0,0 +1,436 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::channel::{ChannelError, Message};
+use crate::replay::Received;
+use crate::{FaultKind, Register, VM};
+
+pub const WRITE_STDOUT: usize = 0x00;
+pub const READ_STDIN: usize = 0x01;
+pub const CLEAR_SCREEN: usize = 0x02;
+pub const PRINT_NUMBER: usize = 0x03;
+pub const EXIT: usize = 0x04;
+
+pub const CHAN_SEND_WORD: usize = 0x10;
+pub const CHAN_SEND_BUF: usize = 0x11;
+pub const CHAN_RECV_WORD: usize = 0x12;
+pub const CHAN_RECV_BUF: usize = 0x13;
+pub const CHAN_TRY_SEND_WORD: usize = CHAN_SEND_WORD | NON_BLOCKING;
+pub const CHAN_TRY_SEND_BUF: usize = CHAN_SEND_BUF | NON_BLOCKING;
+pub const CHAN_TRY_RECV_WORD: usize = CHAN_RECV_WORD | NON_BLOCKING;
+pub const CHAN_TRY_RECV_BUF: usize = CHAN_RECV_BUF | NON_BLOCKING;
+
+pub const NON_BLOCKING: usize = 0x04;
+
+pub const FILE_OPEN: usize = 0x20;
+pub const FILE_READ: usize = 0x21;
+pub const FILE_WRITE: usize = 0x22;
+pub const FILE_SEEK: usize = 0x23;
+pub const FILE_CLOSE: usize = 0x24;
+pub const FILE_STAT: usize = 0x25;
+
+// Times are 64-bit values split across B (high) and C (low) words. The
+// monotonic clock counts nanoseconds, the wall clock counts seconds since the
+// Unix epoch and returns the remaining nanoseconds in D.
+pub const CLOCK_MONOTONIC: usize = 0x30;
+pub const CLOCK_WALL: usize = 0x31;
+pub const SLEEP: usize = 0x32;
+
+pub const RANDOM: usize = 0x40;
+pub const RANDOM_FILL: usize = 0x41;
+
+pub const SEEK_START: usize = 0;
+pub const SEEK_CURRENT: usize = 1;
+pub const SEEK_END: usize = 2;
+
+// Reads and writes larger than this are cut short, the amount actually
+// transferred is returned in register D.
+pub(crate) const MAX_IO_LEN: usize = 1 << 20;
+
+// Paths longer than this in bytes are refused outright.
+const MAX_PATH_LEN: usize = 4096;
+
+pub const STAT_FILE: usize = 1;
+pub const STAT_DIR: usize = 2;
+
+// Status codes returned in register A by the syscalls that can fail.
+pub const OK: usize = 0;
+pub const WOULD_BLOCK: usize = 1;
+pub const BAD_CHANNEL: usize = 2;
+pub const CLOSED: usize = 3;
+pub const TOO_LONG: usize = 4;
+pub const WRONG_KIND: usize = 5;
+pub const NOT_FOUND: usize = 6;
+pub const PERMISSION_DENIED: usize = 7;
+pub const ALREADY_EXISTS: usize = 8;
+pub const BAD_FD: usize = 9;
+pub const INVALID: usize = 10;
+pub const IO_ERROR: usize = 11;
+
+fn reg(vm: &VM, reg: Register) -> usize {
+    vm.regs[reg as usize]
+}
+
+fn set_reg(vm: &mut VM, reg: Register, value: usize) {
+    vm.regs[reg as usize] = value;
+}
+
+/// The heap words a buffer of `len` words from `ptr` on takes up, as long as
+/// there are no more than `MAX_IO_LEN` of them and they don't run past the
+/// end of the address space.
+fn heap_range(ptr: usize, len: usize) -> Result<Range<usize>, usize> {
+    if len > MAX_IO_LEN {
+        return Err(TOO_LONG);
+    }
+
+    let end = ptr.checked_add(len).ok_or(TOO_LONG)?;
+    Ok(ptr..end)
+}
+
+fn chan_send(vm: &mut VM, number: usize) {
+    let channel = match vm.channels.get(reg(vm, Register::B)) {
+        Some(channel) => channel.clone(),
+        None => return set_reg(vm, Register::A, BAD_CHANNEL),
+    };
+
+    let message = if number & !NON_BLOCKING == CHAN_SEND_WORD {
+        Message::Word(reg(vm, Register::C) as u32)
+    } else {
+        let range = match heap_range(reg(vm, Register::C), reg(vm, Register::D)) {
+            Ok(range) => range,
+            Err(status) => return set_reg(vm, Register::A, status),
+        };
+        Message::Buffer(range.map(|addr| vm.heap.read(addr)).collect())
+    };
+
+    let status = match channel.send(message) {
+        Ok(()) => OK,
+        Err(ChannelError::Closed(_)) => CLOSED,
+        Err(ChannelError::Full(_)) if number & NON_BLOCKING != 0 => WOULD_BLOCK,
+        Err(ChannelError::Full(_)) => return vm.block(),
+    };
+
+    set_reg(vm, Register::A, status);
+}
+
+fn chan_recv(vm: &mut VM, number: usize) {
+    let channel = match vm.channels.get(reg(vm, Register::B)) {
+        Some(channel) => channel.clone(),
+        None => return set_reg(vm, Register::A, BAD_CHANNEL),
+    };
+
+    let wants_word = number & !NON_BLOCKING == CHAN_RECV_WORD;
+    let (ptr, max_len) = (reg(vm, Register::C), reg(vm, Register::D));
+
+    let received = vm.receive(&channel, |message| match message {
+        Message::Word(_) => wants_word || heap_range(ptr, 1).is_ok(),
+        Message::Buffer(buf) => {
+            !wants_word && buf.len() <= max_len && heap_range(ptr, buf.len()).is_ok()
+        }
+    });
+
+    let status = match received {
+        None => return,
+        Some(Received::Taken(Message::Word(word))) if wants_word => {
+            set_reg(vm, Register::B, word as usize);
+            OK
+        }
+        Some(Received::Taken(message)) => {
+            let buf = match message {
+                Message::Word(word) => vec![word],
+                Message::Buffer(buf) => buf,
+            };
+            for (i, value) in buf.iter().enumerate() {
+                if let Err(kind) = vm.heap.write(ptr + i, *value) {
+                    return vm.raise_fault(kind);
+                }
+            }
+            set_reg(vm, Register::D, buf.len());
+            OK
+        }
+        Some(Received::Refused(Message::Buffer(_))) if wants_word => WRONG_KIND,
+        Some(Received::Refused(message)) => {
+            set_reg(vm, Register::D, message.len());
+            TOO_LONG
+        }
+        Some(Received::Closed) => CLOSED,
+        Some(Received::Empty) if number & NON_BLOCKING != 0 => WOULD_BLOCK,
+        Some(Received::Empty) => return vm.block(),
+    };
+
+    set_reg(vm, Register::A, status);
+}
+
+pub(crate) fn io_status(error: &io::Error) -> usize {
+    match error.kind() {
+        io::ErrorKind::NotFound => NOT_FOUND,
+        io::ErrorKind::PermissionDenied => PERMISSION_DENIED,
+        io::ErrorKind::AlreadyExists => ALREADY_EXISTS,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => INVALID,
+        _ => IO_ERROR,
+    }
+}
+
+fn read_path(vm: &VM) -> Result<String, usize> {
+    let len = reg(vm, Register::C);
+    if len > MAX_PATH_LEN {
+        return Err(TOO_LONG);
+    }
+
+    let bytes = vm.heap.read_bytes(reg(vm, Register::B), len);
+
+    String::from_utf8(bytes).map_err(|_| INVALID)
+}
+
+fn file_open(vm: &mut VM) -> Result<(), usize> {
+    let filesystem = vm.filesystem.as_ref().ok_or(PERMISSION_DENIED)?;
+    let path = read_path(vm)?;
+    let file = filesystem
+        .open(&path, reg(vm, Register::D) as u32)
+        .map_err(|error| io_status(&error))?;
+
+    let fd = match vm.files.iter().position(Option::is_none) {
+        Some(fd) => {
+            vm.files[fd] = Some(file);
+            fd
+        }
+        None => {
+            vm.files.push(Some(file));
+            vm.files.len() - 1
+        }
+    };
+    set_reg(vm, Register::B, fd);
+
+    Ok(())
+}
+
+fn file_read(vm: &mut VM) -> Result<(), usize> {
+    let (fd, ptr, len) = (
+        reg(vm, Register::B),
+        reg(vm, Register::C),
+        reg(vm, Register::D),
+    );
+    if !vm.files.get(fd).is_some_and(Option::is_some) {
+        return Err(BAD_FD);
+    }
+
+    let buf = match vm.read_file(fd, len.min(MAX_IO_LEN)) {
+        Some(read) => read?,
+        None => return Ok(()),
+    };
+
+    vm.write_heap_bytes(ptr, &buf);
+    set_reg(vm, Register::D, buf.len());
+
+    Ok(())
+}
+
+fn file_write(vm: &mut VM) -> Result<(), usize> {
+    let (fd, ptr, len) = (
+        reg(vm, Register::B),
+        reg(vm, Register::C),
+        reg(vm, Register::D),
+    );
+    let buf = vm.heap.read_bytes(ptr, len.min(MAX_IO_LEN));
+    let file = vm
+        .files
+        .get_mut(fd)
+        .and_then(Option::as_mut)
+        .ok_or(BAD_FD)?;
+
+    let written = file.write(&buf).map_err(|error| io_status(&error))?;
+    set_reg(vm, Register::D, written);
+
+    Ok(())
+}
+
+fn file_seek(vm: &mut VM) -> Result<(), usize> {
+    let (fd, offset) = (reg(vm, Register::B), reg(vm, Register::C) as u32 as i32);
+    let pos = match reg(vm, Register::D) {
+        SEEK_START if offset >= 0 => SeekFrom::Start(offset as u64),
+        SEEK_CURRENT => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(INVALID),
+    };
+    let file = vm
+        .files
+        .get_mut(fd)
+        .and_then(Option::as_mut)
+        .ok_or(BAD_FD)?;
+
+    let pos = file.seek(pos).map_err(|error| io_status(&error))?;
+    set_reg(vm, Register::B, pos as usize);
+
+    Ok(())
+}
+
+fn file_close(vm: &mut VM) -> Result<(), usize> {
+    let fd = reg(vm, Register::B);
+
+    match vm.files.get_mut(fd).and_then(Option::take) {
+        Some(_) => Ok(()),
+        None => Err(BAD_FD),
+    }
+}
+
+fn file_stat(vm: &mut VM) -> Result<(), usize> {
+    let filesystem = vm.filesystem.as_ref().ok_or(PERMISSION_DENIED)?;
+    let path = read_path(vm)?;
+    let stat = filesystem.stat(&path).map_err(|error| io_status(&error))?;
+
+    let kind = if stat.is_dir { STAT_DIR } else { STAT_FILE };
+    set_reg(vm, Register::B, stat.len as usize);
+    set_reg(vm, Register::C, kind);
+
+    Ok(())
+}
+
+fn file_syscall(vm: &mut VM, handler: fn(&mut VM) -> Result<(), usize>) {
+    let status = match handler(vm) {
+        Ok(()) => OK,
+        Err(status) => status,
+    };
+
+    set_reg(vm, Register::A, status);
+}
+
+fn set_split(vm: &mut VM, value: u64) {
+    set_reg(vm, Register::B, (value >> 32) as usize);
+    set_reg(vm, Register::C, value as u32 as usize);
+}
+
+fn clock_monotonic(vm: &mut VM) {
+    let now = match vm.monotonic_time() {
+        Some(now) => now,
+        None => return,
+    };
+
+    set_split(vm, now.as_nanos() as u64);
+    set_reg(vm, Register::A, OK);
+}
+
+fn clock_wall(vm: &mut VM) {
+    let now = match vm.wall_time() {
+        Some(now) => now,
+        None => return,
+    };
+
+    set_split(vm, now.as_secs());
+    set_reg(vm, Register::D, now.subsec_nanos() as usize);
+    set_reg(vm, Register::A, OK);
+}
+
+fn sleep(vm: &mut VM) {
+    let millis = reg(vm, Register::B) as u32;
+
+    // Whatever the guest observes after sleeping comes from the recording.
+    if !vm.is_replaying() {
+        vm.clock.sleep(Duration::from_millis(millis as u64));
+    }
+    set_reg(vm, Register::A, OK);
+}
+
+fn random(vm: &mut VM) {
+    let words = match vm.random_words(3) {
+        Some(words) => words,
+        None => return,
+    };
+
+    for (value, reg) in words
+        .into_iter()
+        .zip([Register::B, Register::C, Register::D])
+    {
+        set_reg(vm, reg, value as usize);
+    }
+
+    set_reg(vm, Register::A, OK);
+}
+
+fn random_fill(vm: &mut VM) {
+    let range = match heap_range(reg(vm, Register::B), reg(vm, Register::C)) {
+        Ok(range) => range,
+        Err(status) => return set_reg(vm, Register::A, status),
+    };
+
+    let words = match vm.random_words(range.len()) {
+        Some(words) => words,
+        None => return,
+    };
+
+    for (addr, value) in range.zip(words) {
+        if let Err(kind) = vm.heap.write(addr, value) {
+            return vm.raise_fault(kind);
+        }
+    }
+
+    set_reg(vm, Register::A, OK);
+}
+
+pub(crate) fn syscall(vm: &mut VM) {
+    match reg(vm, Register::A) {
+        WRITE_STDOUT => {
+            let range = match heap_range(reg(vm, Register::B), reg(vm, Register::C)) {
+                Ok(range) => range,
+                Err(status) => return set_reg(vm, Register::A, status),
+            };
+
+            let buf = vm.heap.read_bytes(range.start, range.len() * 4);
+
+            if vm.output.0.write_all(&buf).is_err() {
+                vm.raise_fault(FaultKind::SyscallFailed(WRITE_STDOUT));
+            }
+        }
+        READ_STDIN => {
+            let ptr = vm.regs[1];
+            let len = vm.regs[2];
+
+            let buf = match vm.read_stdin(len) {
+                Some(buf) => buf,
+                None => return vm.raise_fault(FaultKind::SyscallFailed(READ_STDIN)),
+            };
+
+            // The bytes go four to a word, the way the other syscalls pack
+            // strings, so how many there were is passed back in D.
+            vm.write_heap_bytes(ptr, &buf);
+            vm.regs[3] = buf.len();
+        }
+        CLEAR_SCREEN => {
+            let command = if cfg!(windows) { "cls" } else { "clear" };
+
+            if Command::new(command).output().is_err() {
+                vm.raise_fault(FaultKind::SyscallFailed(CLEAR_SCREEN));
+            }
+        }
+        PRINT_NUMBER => {
+            let value = vm.regs[Register::B as usize];
+
+            if writeln!(vm.output.0, "{}", value).is_err() {
+                vm.raise_fault(FaultKind::SyscallFailed(PRINT_NUMBER));
+            }
+        }
+        EXIT => {
+            let code = reg(vm, Register::B) as u32;
+            vm.exit(code);
+        }
+        number @ (CHAN_SEND_WORD | CHAN_SEND_BUF | CHAN_TRY_SEND_WORD | CHAN_TRY_SEND_BUF) => {
+            chan_send(vm, number)
+        }
+        number @ (CHAN_RECV_WORD | CHAN_RECV_BUF | CHAN_TRY_RECV_WORD | CHAN_TRY_RECV_BUF) => {
+            chan_recv(vm, number)
+        }
+        FILE_OPEN => file_syscall(vm, file_open),
+        FILE_READ => file_syscall(vm, file_read),
+        FILE_WRITE => file_syscall(vm, file_write),
+        FILE_SEEK => file_syscall(vm, file_seek),
+        FILE_CLOSE => file_syscall(vm, file_close),
+        FILE_STAT => file_syscall(vm, file_stat),
+        CLOCK_MONOTONIC => clock_monotonic(vm),
+        CLOCK_WALL => clock_wall(vm),
+        SLEEP => sleep(vm),
+        RANDOM => random(vm),
+        RANDOM_FILL => random_fill(vm),
+        _ => {}
+    }
+}
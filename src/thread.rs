@@ -0,0 +1,126 @@
+use std::mem;
+
+use crate::{FaultKind, Flag, FlagSet, Stack, VM};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) enum ThreadState {
+    #[default]
+    Ready,
+    Joining(usize),
+    Finished,
+}
+
+/// Saved context of a green thread. The context of the running thread lives
+/// in the `VM` itself, its slot only keeps track of its state.
+#[derive(Debug, Default)]
+pub(crate) struct Thread {
+    regs: [usize; 4],
+    flags: FlagSet,
+    stack: Stack,
+    prgrm_cntr: usize,
+    base_ptr: u32,
+    pub(crate) state: ThreadState,
+}
+
+impl Thread {
+    pub(crate) fn stack_cap(&self) -> usize {
+        self.stack.cap()
+    }
+
+    pub(crate) fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+}
+
+impl VM {
+    fn swap_context(&mut self, tid: usize) {
+        let thread = &mut self.threads[tid];
+
+        mem::swap(&mut self.regs, &mut thread.regs);
+        mem::swap(&mut self.flags, &mut thread.flags);
+        mem::swap(&mut self.stack, &mut thread.stack);
+        mem::swap(&mut self.prgrm_cntr, &mut thread.prgrm_cntr);
+        mem::swap(&mut self.base_ptr, &mut thread.base_ptr);
+    }
+
+    fn is_runnable(&self, tid: usize) -> bool {
+        match self.threads[tid].state {
+            ThreadState::Ready => true,
+            ThreadState::Joining(other) => self.threads[other].state == ThreadState::Finished,
+            ThreadState::Finished => false,
+        }
+    }
+
+    pub(crate) fn spawn_thread(&mut self, addr: usize) -> usize {
+        if self.threads.is_empty() {
+            self.threads.push(Thread::default());
+        }
+
+        let thread = Thread {
+            regs: self.regs,
+            prgrm_cntr: addr,
+            ..Default::default()
+        };
+        self.threads.push(thread);
+
+        self.threads.len() - 1
+    }
+
+    pub(crate) fn join_thread(&mut self, tid: usize) {
+        if tid >= self.threads.len().max(1) || tid == self.current_thread {
+            return self.raise_fault(FaultKind::InvalidThread(tid));
+        }
+
+        self.threads[self.current_thread].state = ThreadState::Joining(tid);
+        self.yield_requested = true;
+    }
+
+    pub(crate) fn exit_thread(&mut self) {
+        if self.current_thread == 0 {
+            self.flags.set(Flag::Stop, true);
+            return;
+        }
+
+        self.threads[self.current_thread].state = ThreadState::Finished;
+        self.stack = Stack::new();
+        self.yield_requested = true;
+    }
+
+    pub(crate) fn schedule(&mut self) {
+        self.yield_requested = false;
+        self.slice_count = 0;
+
+        // Before anything is spawned, the main thread has no slot and is
+        // the one thread there is to run.
+        if self.threads.is_empty() {
+            return;
+        }
+
+        let len = self.threads.len();
+        let next = (1..=len)
+            .map(|offset| (self.current_thread + offset) % len)
+            .find(|&tid| self.is_runnable(tid));
+
+        let next = match next {
+            Some(next) => next,
+            None => return self.raise_fault(FaultKind::Deadlock),
+        };
+
+        self.threads[next].state = ThreadState::Ready;
+
+        if next != self.current_thread {
+            self.swap_context(self.current_thread);
+            self.swap_context(next);
+            self.current_thread = next;
+        }
+    }
+
+    pub fn set_time_slice(&mut self, instructions: u32) {
+        self.time_slice = instructions;
+        self.slice_count = 0;
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len().max(1)
+    }
+}
@@ -0,0 +1,373 @@
+use std::io::{self, Write};
+
+use crate::decode::{Instr, Reachable};
+use crate::{FaultKind, VM};
+
+/// The stack, heap, threads and syscalls of the translated program, which
+/// come before its code in the output.
+const RUNTIME: &str = include_str!("to_c/runtime.c");
+
+fn label(offset: usize) -> String {
+    format!("L_{:04x}", offset)
+}
+
+fn fault_kind(kind: &FaultKind) -> (&'static str, usize) {
+    match kind {
+        FaultKind::InvalidRegister(reg) => ("RSVM_INVALID_REGISTER", *reg as usize),
+        FaultKind::DivisionByZero => ("RSVM_DIVISION_BY_ZERO", 0),
+        _ => ("RSVM_PROGRAM_COUNTER_OUT_OF_BOUNDS", 0),
+    }
+}
+
+struct Translator<'a, W> {
+    w: W,
+    instrs: &'a Reachable,
+}
+
+impl<'a, W: Write> Translator<'a, W> {
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.w, "    {}", line)
+    }
+
+    /// Carries on at `to` once the instruction at `at` is done, straight
+    /// there when its translation is known.
+    fn next(&mut self, at: usize, to: usize) -> io::Result<()> {
+        if self.instrs.contains_key(&to) {
+            self.line(&format!("RSVM_NEXT({:#x}, {:#x}, {});", at, to, label(to)))
+        } else {
+            self.line(&format!("RSVM_JUMP({:#x}, {:#x});", at, to))
+        }
+    }
+
+    fn fault(&mut self, at: usize, kind: &FaultKind) -> io::Result<()> {
+        let (kind, arg) = fault_kind(kind);
+
+        self.line(&format!("RSVM_FAULT({}, {}, {:#x});", kind, arg, at))
+    }
+
+    fn branch(&mut self, at: usize, flag: &str, to: usize, next: usize) -> io::Result<()> {
+        self.line(&format!("if (vm->flags[{}])", flag))?;
+        write!(self.w, "    ")?;
+        self.next(at, to)?;
+        self.next(at, next)
+    }
+
+    fn pop(&mut self, at: usize, vars: &[&str]) -> io::Result<()> {
+        let pops = vars
+            .iter()
+            .map(|var| format!("!rsvm_pop(vm, &{})", var))
+            .collect::<Vec<_>>();
+
+        self.line(&format!("if ({})", pops.join(" || ")))?;
+        write!(self.w, "    ")?;
+        self.fault(at, &FaultKind::StackUnderflow)
+    }
+
+    /// Pops two words, `a` from the top, and pushes `expr` of them.
+    fn stack_op(&mut self, at: usize, expr: &str) -> io::Result<()> {
+        self.pop(at, &["a", "b"])?;
+        self.line(&format!("rsvm_push(vm, {});", expr))
+    }
+
+    /// Pops a word into `a` and pushes `expr` of it.
+    fn stack_op_num(&mut self, at: usize, expr: &str) -> io::Result<()> {
+        self.pop(at, &["a"])?;
+        self.line(&format!("rsvm_push(vm, {});", expr))
+    }
+
+    fn instr(&mut self, at: usize, instr: Instr, len: usize) -> io::Result<()> {
+        let r = |reg: u8| format!("vm->regs[{}]", reg);
+        let next = at + len;
+
+        match instr {
+            Instr::Nop => {}
+            Instr::Exit => self.line("rsvm_exit_thread(vm);")?,
+            Instr::ExitReg(reg) => {
+                self.line("if (vm->current_thread == 0)")?;
+                self.line(&format!("    vm->exit_code = (uint32_t){};", r(reg)))?;
+                self.line("rsvm_exit_thread(vm);")?;
+            }
+            Instr::PushLit(lit) => self.line(&format!("rsvm_push(vm, {}u);", lit))?,
+            Instr::PushReg(reg) => self.line(&format!("rsvm_push(vm, (uint32_t){});", r(reg)))?,
+            Instr::PopReg(reg) => {
+                self.pop(at, &["a"])?;
+                self.line(&format!("{} = a;", r(reg)))?;
+            }
+            Instr::PopHeap(addr) => {
+                self.pop(at, &["a"])?;
+                self.line(&format!("rsvm_write(vm, {}u, a);", addr))?;
+            }
+            Instr::StackDupe => self.line("rsvm_push(vm, rsvm_peek(vm));")?,
+            Instr::MovLitReg(reg, lit) => self.line(&format!("{} = {}u;", r(reg), lit))?,
+            Instr::MovLitHeap(addr, lit) => {
+                self.line(&format!("rsvm_write(vm, {}u, {}u);", addr, lit))?
+            }
+            Instr::MovHeapReg(reg, addr) => {
+                self.line(&format!("{} = rsvm_read(vm, {}u);", r(reg), addr))?
+            }
+            Instr::MovRegHeap(addr, reg) => {
+                self.line(&format!("rsvm_write(vm, {}u, (uint32_t){});", addr, r(reg)))?
+            }
+            Instr::MovRegReg(dst, src) => self.line(&format!("{} = {};", r(dst), r(src)))?,
+            Instr::MovHeapHeap(src, dst) => self.line(&format!(
+                "rsvm_write(vm, {}u, rsvm_read(vm, {}u));",
+                dst, src
+            ))?,
+            Instr::PushHeap(addr) => {
+                self.line(&format!("rsvm_push(vm, rsvm_read(vm, {}u));", addr))?
+            }
+            Instr::MovPtrReg(dst, ptr) => {
+                self.line(&format!("{} = rsvm_read(vm, {});", r(dst), r(ptr)))?
+            }
+            Instr::MovRegPtr(ptr, src) => self.line(&format!(
+                "rsvm_write(vm, {}, (uint32_t){});",
+                r(ptr),
+                r(src)
+            ))?,
+            Instr::AddReg(a, b) => {
+                self.line(&format!("{0} = rsvm_add(vm, {0}, {1});", r(a), r(b)))?
+            }
+            Instr::SubReg(a, b) => {
+                self.line(&format!("{0} = rsvm_sub(vm, {0}, {1});", r(a), r(b)))?
+            }
+            Instr::MulReg(a, b) => {
+                self.line(&format!("{0} = rsvm_mul(vm, {0}, {1});", r(a), r(b)))?
+            }
+            Instr::DivReg(a, b) => {
+                self.line(&format!("if ({} == 0)", r(b)))?;
+                write!(self.w, "    ")?;
+                self.fault(at, &FaultKind::DivisionByZero)?;
+                self.line(&format!("{} /= {};", r(a), r(b)))?;
+            }
+            Instr::AddStack => self.stack_op(at, "rsvm_add32(vm, a, b)")?,
+            Instr::SubStack => self.stack_op(at, "rsvm_sub32(vm, a, b)")?,
+            Instr::MulStack => self.stack_op(at, "rsvm_mul32(vm, a, b)")?,
+            Instr::DivStack => {
+                self.pop(at, &["a", "b"])?;
+                self.line("if (b == 0)")?;
+                write!(self.w, "    ")?;
+                self.fault(at, &FaultKind::DivisionByZero)?;
+                self.line("rsvm_push(vm, a / b);")?;
+            }
+            Instr::NotReg(reg) => self.line(&format!("{0} = ~{0};", r(reg)))?,
+            Instr::NotStack => self.stack_op_num(at, "~a")?,
+            Instr::AndReg(a, b) => self.line(&format!("{} &= {};", r(a), r(b)))?,
+            Instr::AndStack => self.stack_op(at, "a & b")?,
+            Instr::OrReg(a, b) => self.line(&format!("{} |= {};", r(a), r(b)))?,
+            Instr::OrStack => self.stack_op(at, "a | b")?,
+            Instr::XorReg(a, b) => self.line(&format!("{} ^= {};", r(a), r(b)))?,
+            Instr::XorStack => self.stack_op(at, "a ^ b")?,
+            Instr::Jump(addr) => return self.next(at, addr as usize),
+            Instr::Call(addr) => {
+                self.line(&format!("rsvm_push(vm, {}u);", next as u32))?;
+                self.line("rsvm_push(vm, vm->base_ptr);")?;
+                self.line("vm->base_ptr = (uint32_t)vm->stack.len;")?;
+                return self.next(at, addr as usize);
+            }
+            Instr::Ret | Instr::IntReturn => {
+                let function = match instr {
+                    Instr::Ret => "rsvm_ret",
+                    _ => "rsvm_int_return",
+                };
+
+                self.line(&format!("if (!{}(vm))", function))?;
+                write!(self.w, "    ")?;
+                self.fault(at, &FaultKind::StackUnderflow)?;
+                return self.line(&format!("RSVM_JUMP({:#x}, vm->pc);", at));
+            }
+            Instr::CompareRegReg(a, b) => self.line(&format!(
+                "rsvm_compare(vm, (uint32_t){}, (uint32_t){});",
+                r(a),
+                r(b)
+            ))?,
+            Instr::CompareRegLit(reg, lit) => self.line(&format!(
+                "rsvm_compare(vm, (uint32_t){}, {}u);",
+                r(reg),
+                lit
+            ))?,
+            Instr::CompareStackLit(lit) => {
+                self.line(&format!("rsvm_compare(vm, rsvm_peek(vm), {}u);", lit))?
+            }
+            Instr::JumpEqual(addr) => return self.branch(at, "RSVM_EQUAL", addr as usize, next),
+            Instr::JumpNotEqual(addr) => {
+                return self.branch(at, "RSVM_NOT_EQUAL", addr as usize, next)
+            }
+            Instr::JumpGreater(addr) => {
+                return self.branch(at, "RSVM_GREATER", addr as usize, next)
+            }
+            Instr::JumpSmaller(addr) => {
+                return self.branch(at, "RSVM_SMALLER", addr as usize, next)
+            }
+            Instr::JumpOverflow(addr) => {
+                return self.branch(at, "RSVM_OVERFLOW", addr as usize, next)
+            }
+            Instr::FlagReset => {
+                for flag in ["EQUAL", "NOT_EQUAL", "GREATER", "SMALLER", "OVERFLOW"] {
+                    self.line(&format!("vm->flags[RSVM_{}] = 0;", flag))?;
+                }
+            }
+            Instr::IncReg(reg) => self.line(&format!("{0} = rsvm_add(vm, {0}, 1);", r(reg)))?,
+            Instr::DecReg(reg) => self.line(&format!("{0} = rsvm_sub(vm, {0}, 1);", r(reg)))?,
+            Instr::IncStack => self.stack_op_num(at, "rsvm_add32(vm, a, 1)")?,
+            Instr::DecStack => self.stack_op_num(at, "rsvm_sub32(vm, a, 1)")?,
+            Instr::AddRegNum(reg, lit) => self.line(&format!(
+                "{0} = rsvm_add32(vm, (uint32_t){0}, {1}u);",
+                r(reg),
+                lit
+            ))?,
+            Instr::SubRegNum(reg, lit) => self.line(&format!(
+                "{0} = rsvm_sub32(vm, (uint32_t){0}, {1}u);",
+                r(reg),
+                lit
+            ))?,
+            Instr::MulRegNum(reg, lit) => self.line(&format!(
+                "{0} = rsvm_mul32(vm, (uint32_t){0}, {1}u);",
+                r(reg),
+                lit
+            ))?,
+            Instr::DivRegNum(_, 0) => self.fault(at, &FaultKind::DivisionByZero)?,
+            Instr::DivRegNum(reg, lit) => self.line(&format!("{} /= {}u;", r(reg), lit))?,
+            Instr::AndRegNum(reg, lit) => self.line(&format!("{} &= {}u;", r(reg), lit))?,
+            Instr::OrRegNum(reg, lit) => self.line(&format!("{} |= {}u;", r(reg), lit))?,
+            Instr::XorRegNum(reg, lit) => self.line(&format!("{} ^= {}u;", r(reg), lit))?,
+            Instr::AddStackNum(num) => {
+                self.stack_op_num(at, &format!("rsvm_add32(vm, {}u, a)", num))?
+            }
+            Instr::SubStackNum(num) => {
+                self.stack_op_num(at, &format!("rsvm_sub32(vm, {}u, a)", num))?
+            }
+            Instr::MulStackNum(num) => {
+                self.stack_op_num(at, &format!("rsvm_mul32(vm, {}u, a)", num))?
+            }
+            Instr::DivStackNum(num) => {
+                self.pop(at, &["a"])?;
+                self.line("if (a == 0)")?;
+                write!(self.w, "    ")?;
+                self.fault(at, &FaultKind::DivisionByZero)?;
+                self.line(&format!("rsvm_push(vm, {}u / a);", num))?;
+            }
+            Instr::AndStackNum(num) => self.stack_op_num(at, &format!("{}u & a", num))?,
+            Instr::OrStackNum(num) => self.stack_op_num(at, &format!("{}u | a", num))?,
+            Instr::XorStackNum(num) => self.stack_op_num(at, &format!("{}u ^ a", num))?,
+            Instr::IntEnable => self.line("vm->flags[RSVM_INTERRUPT] = 1;")?,
+            Instr::IntDisable => self.line("vm->flags[RSVM_INTERRUPT] = 0;")?,
+            Instr::IntTable(addr) => self.line(&format!("vm->ivt_base = {}u;", addr))?,
+            Instr::TimerSet(period) => self.line(&format!("rsvm_set_timer(vm, {}u);", period))?,
+            Instr::ThreadSpawn(reg, addr) => {
+                self.line(&format!("{} = rsvm_spawn(vm, {}u);", r(reg), addr))?
+            }
+            Instr::ThreadYield => {
+                self.line("vm->yield_requested = 1;")?;
+                self.line("vm->events = 1;")?;
+            }
+            Instr::ThreadJoin(reg) => {
+                self.line(&format!("if (!rsvm_join(vm, {}, {:#x}))", r(reg), at))?;
+                self.line("    return;")?;
+            }
+            Instr::ThreadSelf(reg) => self.line(&format!("{} = vm->current_thread;", r(reg)))?,
+            Instr::Syscall => {
+                self.line(&format!("if (rsvm_syscall(vm, {:#x}))", at))?;
+                self.line("    return;")?;
+            }
+        }
+
+        self.next(at, next)
+    }
+
+    fn run(&mut self, code_len: usize) -> io::Result<()> {
+        writeln!(self.w, "static void rsvm_run(struct rsvm *vm)")?;
+        writeln!(self.w, "{{")?;
+        self.line("uint32_t a, b;")?;
+        writeln!(self.w)?;
+        self.line("(void)a;")?;
+        self.line("(void)b;")?;
+        writeln!(self.w, "dispatch:")?;
+        self.line("if (vm->flags[RSVM_STOP] || vm->faulted)")?;
+        self.line("    return;")?;
+        writeln!(self.w)?;
+        self.line("switch (vm->pc) {")?;
+        for &offset in self.instrs.keys() {
+            self.line(&format!("case {:#x}: goto {};", offset, label(offset)))?;
+        }
+        self.line("}")?;
+        self.line(&format!("if (vm->pc >= {:#x})", code_len))?;
+        self.line("    RSVM_FAULT(RSVM_PROGRAM_COUNTER_OUT_OF_BOUNDS, 0, vm->pc);")?;
+        self.line("RSVM_FAULT(RSVM_NOT_TRANSLATED, 0, vm->pc);")?;
+
+        for (&offset, decoded) in self.instrs {
+            writeln!(self.w)?;
+
+            match decoded {
+                Ok((instr, len)) => {
+                    writeln!(
+                        self.w,
+                        "{}: /* {:#06x} {:?} */",
+                        label(offset),
+                        offset,
+                        instr
+                    )?;
+                    self.instr(offset, *instr, *len)?;
+                }
+                Err(kind) => {
+                    writeln!(self.w, "{}: /* {:#06x} */", label(offset), offset)?;
+                    self.fault(offset, kind)?;
+                }
+            }
+        }
+
+        writeln!(self.w, "}}")
+    }
+}
+
+/// Translates a program, header and all, to C that runs it the way the `VM`
+/// would. The output is a whole C program, with the runtime it needs, that
+/// takes its arguments from the command line. Bytecode without a header is
+/// refused with `io::ErrorKind::InvalidData` before anything is written.
+pub fn translate<W: Write>(bytecode: Vec<u8>, mut w: W) -> io::Result<()> {
+    let vm = VM::load_translatable(bytecode)?;
+
+    let data = &vm.bytecode[..vm.hdr_size - 4];
+    let code_len = vm.bytecode.len().saturating_sub(vm.hdr_size);
+    let code = vm.bytecode.get(vm.hdr_size..).unwrap_or_default();
+    let instrs = vm.decoded.reachable(code);
+
+    writeln!(w, "/* Translated from rsvm bytecode by rsvm-to-c. */")?;
+    writeln!(w)?;
+    w.write_all(RUNTIME.as_bytes())?;
+    writeln!(w)?;
+
+    if !data.is_empty() {
+        writeln!(w, "static const uint32_t rsvm_data[] = {{")?;
+        for chunk in data.chunks(8) {
+            let words = chunk
+                .iter()
+                .map(|byte| format!("{:#04x}", byte))
+                .collect::<Vec<_>>();
+            writeln!(w, "    {},", words.join(", "))?;
+        }
+        writeln!(w, "}};")?;
+        writeln!(w)?;
+    }
+
+    writeln!(w, "static size_t rsvm_load(struct rsvm *vm)")?;
+    writeln!(w, "{{")?;
+    if data.is_empty() {
+        writeln!(w, "    (void)vm;")?;
+    } else {
+        writeln!(w, "    size_t i;")?;
+        writeln!(w)?;
+        writeln!(w, "    for (i = 0; i < {}; i++)", data.len())?;
+        writeln!(w, "        rsvm_write(vm, i, rsvm_data[i]);")?;
+    }
+    writeln!(w, "    return {};", data.len())?;
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+
+    Translator {
+        w: &mut w,
+        instrs: &instrs,
+    }
+    .run(code_len)?;
+
+    w.flush()
+}
@@ -0,0 +1,827 @@
+/*
+ * The runtime of programs translated by rsvm-to-c. It mirrors the parts of
+ * the VM the translated code cannot do inline: the stack and the heap, green
+ * threads, the timer interrupt and the syscalls. There are no channels and
+ * no filesystem, just like when rsvm runs a program without any attached or
+ * without --root.
+ */
+
+#define _POSIX_C_SOURCE 200809L
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+#define RSVM_HEAP_INITIAL_CAPACITY 256
+#define RSVM_STACK_INITIAL_CAPACITY 128
+
+#define RSVM_FAULT_EXIT_CODE 70
+
+enum {
+    RSVM_EQUAL,
+    RSVM_NOT_EQUAL,
+    RSVM_GREATER,
+    RSVM_SMALLER,
+    RSVM_OVERFLOW,
+    RSVM_STOP,
+    RSVM_INTERRUPT,
+    RSVM_NO_OF_FLAGS
+};
+
+enum {
+    RSVM_STACK_UNDERFLOW,
+    RSVM_DIVISION_BY_ZERO,
+    RSVM_INVALID_REGISTER,
+    RSVM_PROGRAM_COUNTER_OUT_OF_BOUNDS,
+    RSVM_INVALID_THREAD,
+    RSVM_DEADLOCK,
+    RSVM_SYSCALL_FAILED,
+    RSVM_NOT_TRANSLATED
+};
+
+enum { RSVM_READY, RSVM_JOINING, RSVM_FINISHED };
+
+#define RSVM_WRITE_STDOUT 0x00
+#define RSVM_READ_STDIN 0x01
+#define RSVM_CLEAR_SCREEN 0x02
+#define RSVM_PRINT_NUMBER 0x03
+#define RSVM_EXIT 0x04
+#define RSVM_FILE_OPEN 0x20
+#define RSVM_FILE_READ 0x21
+#define RSVM_FILE_WRITE 0x22
+#define RSVM_FILE_SEEK 0x23
+#define RSVM_FILE_CLOSE 0x24
+#define RSVM_FILE_STAT 0x25
+#define RSVM_CLOCK_MONOTONIC 0x30
+#define RSVM_CLOCK_WALL 0x31
+#define RSVM_SLEEP 0x32
+#define RSVM_RANDOM 0x40
+#define RSVM_RANDOM_FILL 0x41
+
+#define RSVM_SEEK_START 0
+#define RSVM_SEEK_CURRENT 1
+#define RSVM_SEEK_END 2
+
+#define RSVM_MAX_IO_LEN (1 << 20)
+
+#define RSVM_OK 0
+#define RSVM_BAD_CHANNEL 2
+#define RSVM_TOO_LONG 4
+#define RSVM_PERMISSION_DENIED 7
+#define RSVM_BAD_FD 9
+#define RSVM_INVALID 10
+
+struct rsvm_stack {
+    uint32_t *buf;
+    size_t len;
+    size_t cap;
+};
+
+struct rsvm_thread {
+    uint64_t regs[4];
+    unsigned char flags[RSVM_NO_OF_FLAGS];
+    struct rsvm_stack stack;
+    uint64_t pc;
+    uint32_t base_ptr;
+    int state;
+    uint64_t joining;
+};
+
+struct rsvm {
+    uint64_t regs[4];
+    unsigned char flags[RSVM_NO_OF_FLAGS];
+    struct rsvm_stack stack;
+    uint32_t *heap;
+    size_t heap_cap;
+    /* Where the program continues once the current instruction is done. */
+    uint64_t pc;
+    uint32_t base_ptr;
+    uint64_t ivt_base;
+    uint32_t timer_period;
+    uint32_t timer_count;
+    uint32_t pending;
+    struct rsvm_thread *threads;
+    size_t thread_count;
+    size_t current_thread;
+    int yield_requested;
+    /* Set whenever something has to happen between instructions, so that
+     * straight-line code only has to check this one field. */
+    int events;
+    uint32_t exit_code;
+    int faulted;
+    uint64_t rng[4];
+    struct timespec start;
+};
+
+static size_t rsvm_load(struct rsvm *vm);
+static void rsvm_run(struct rsvm *vm);
+
+/* The helpers below are static inline, since a program only calls those for
+ * the instructions it has, and unused static functions would warn. */
+
+static inline void rsvm_stack_init(struct rsvm_stack *stack)
+{
+    stack->buf = calloc(RSVM_STACK_INITIAL_CAPACITY, sizeof(uint32_t));
+    stack->len = 0;
+    stack->cap = RSVM_STACK_INITIAL_CAPACITY;
+
+    if (stack->buf == NULL) {
+        fprintf(stderr, "Failed to allocate VM stack! Aborting!\n");
+        abort();
+    }
+}
+
+static inline void rsvm_push(struct rsvm *vm, uint32_t value)
+{
+    struct rsvm_stack *stack = &vm->stack;
+
+    if (stack->len == stack->cap) {
+        uint32_t *buf = realloc(stack->buf, 2 * stack->cap * sizeof(uint32_t));
+
+        if (buf == NULL) {
+            fprintf(stderr, "Failed to reallocate(grow) VM stack! Aborting!\n");
+            abort();
+        }
+
+        memset(buf + stack->cap, 0, stack->cap * sizeof(uint32_t));
+        stack->buf = buf;
+        stack->cap *= 2;
+    }
+
+    stack->buf[stack->len++] = value;
+}
+
+static inline int rsvm_pop(struct rsvm *vm, uint32_t *value)
+{
+    if (vm->stack.len == 0)
+        return 0;
+
+    *value = vm->stack.buf[--vm->stack.len];
+    return 1;
+}
+
+/* The word on top of the stack, or 0 if it's empty. */
+static inline uint32_t rsvm_peek(struct rsvm *vm)
+{
+    return vm->stack.len > 0 ? vm->stack.buf[vm->stack.len - 1] : 0;
+}
+
+static inline uint32_t rsvm_read(struct rsvm *vm, uint64_t addr)
+{
+    return addr < vm->heap_cap ? vm->heap[addr] : 0;
+}
+
+static inline void rsvm_write(struct rsvm *vm, uint64_t addr, uint32_t value)
+{
+    if (addr >= vm->heap_cap) {
+        uint64_t cap = addr + 1 > 2 * (uint64_t)vm->heap_cap ? addr + 1 : 2 * (uint64_t)vm->heap_cap;
+        uint32_t *heap = NULL;
+
+        if (value == 0)
+            return;
+
+        if (cap <= SIZE_MAX / sizeof(uint32_t))
+            heap = realloc(vm->heap, (size_t)cap * sizeof(uint32_t));
+        if (heap == NULL) {
+            fprintf(stderr, "Failed to reallocate(grow) VM heap! Aborting!\n");
+            abort();
+        }
+
+        memset(heap + vm->heap_cap, 0, (cap - vm->heap_cap) * sizeof(uint32_t));
+        vm->heap = heap;
+        vm->heap_cap = cap;
+    }
+
+    vm->heap[addr] = value;
+}
+
+/* Writes `len` bytes, four to a word with the first one on top. */
+static inline void rsvm_write_bytes(struct rsvm *vm, uint64_t addr, const unsigned char *bytes, size_t len)
+{
+    size_t i;
+
+    for (i = 0; i < len; i += 4) {
+        uint32_t word = 0;
+        size_t j;
+
+        for (j = 0; j < 4; j++)
+            word = word << 8 | (i + j < len ? bytes[i + j] : 0);
+
+        rsvm_write(vm, addr + i / 4, word);
+    }
+}
+
+static inline unsigned char *rsvm_read_bytes(struct rsvm *vm, uint64_t addr, size_t len)
+{
+    unsigned char *bytes = malloc(len + 1);
+    size_t i;
+
+    if (bytes == NULL) {
+        fprintf(stderr, "Failed to allocate a buffer! Aborting!\n");
+        abort();
+    }
+
+    for (i = 0; i < len; i++)
+        bytes[i] = rsvm_read(vm, addr + i / 4) >> (24 - 8 * (i % 4));
+
+    return bytes;
+}
+
+static inline uint64_t rsvm_add(struct rsvm *vm, uint64_t a, uint64_t b)
+{
+    if (a + b < a)
+        vm->flags[RSVM_OVERFLOW] = 1;
+    return a + b;
+}
+
+static inline uint64_t rsvm_sub(struct rsvm *vm, uint64_t a, uint64_t b)
+{
+    if (a < b)
+        vm->flags[RSVM_OVERFLOW] = 1;
+    return a - b;
+}
+
+static inline uint64_t rsvm_mul(struct rsvm *vm, uint64_t a, uint64_t b)
+{
+    if (a != 0 && a * b / a != b)
+        vm->flags[RSVM_OVERFLOW] = 1;
+    return a * b;
+}
+
+static inline uint32_t rsvm_add32(struct rsvm *vm, uint32_t a, uint32_t b)
+{
+    uint32_t value = a + b;
+
+    if (value < a)
+        vm->flags[RSVM_OVERFLOW] = 1;
+    return value;
+}
+
+static inline uint32_t rsvm_sub32(struct rsvm *vm, uint32_t a, uint32_t b)
+{
+    return (uint32_t)rsvm_sub(vm, a, b);
+}
+
+static inline uint32_t rsvm_mul32(struct rsvm *vm, uint32_t a, uint32_t b)
+{
+    uint64_t value = (uint64_t)a * b;
+
+    if (value > UINT32_MAX)
+        vm->flags[RSVM_OVERFLOW] = 1;
+    return (uint32_t)value;
+}
+
+static inline void rsvm_compare(struct rsvm *vm, uint32_t a, uint32_t b)
+{
+    vm->flags[RSVM_EQUAL] = a == b;
+    vm->flags[RSVM_NOT_EQUAL] = a != b;
+    vm->flags[RSVM_GREATER] = a > b;
+    vm->flags[RSVM_SMALLER] = a < b;
+}
+
+static inline uint32_t rsvm_flag_bits(struct rsvm *vm)
+{
+    uint32_t bits = 0;
+    int i;
+
+    for (i = 0; i < RSVM_NO_OF_FLAGS; i++)
+        bits |= (uint32_t)vm->flags[i] << i;
+    return bits;
+}
+
+static inline void rsvm_fault(struct rsvm *vm, int kind, uint64_t arg, uint64_t at)
+{
+    size_t frame = vm->base_ptr, frames = 1;
+    int i;
+
+    if (vm->faulted)
+        return;
+    vm->faulted = 1;
+    vm->events = 1;
+
+    fflush(stdout);
+    fprintf(stderr, "[FAULT]: ");
+    switch (kind) {
+    case RSVM_STACK_UNDERFLOW:
+        fprintf(stderr, "Could not pop the stack as it's empty!");
+        break;
+    case RSVM_DIVISION_BY_ZERO:
+        fprintf(stderr, "Could not divide by zero!");
+        break;
+    case RSVM_INVALID_REGISTER:
+        fprintf(stderr, "Could not access register %llu as it doesn't exist!", (unsigned long long)arg);
+        break;
+    case RSVM_PROGRAM_COUNTER_OUT_OF_BOUNDS:
+        fprintf(stderr, "Could not fetch past the end of the program!");
+        break;
+    case RSVM_INVALID_THREAD:
+        fprintf(stderr, "Could not join thread %llu as it can't be waited on!", (unsigned long long)arg);
+        break;
+    case RSVM_DEADLOCK:
+        fprintf(stderr, "Could not schedule a thread as all of them are blocked!");
+        break;
+    case RSVM_SYSCALL_FAILED:
+        fprintf(stderr, "Could not proceed with syscall %llu!", (unsigned long long)arg);
+        break;
+    case RSVM_NOT_TRANSLATED:
+        fprintf(stderr, "Could not run code that was not translated!");
+        break;
+    }
+    fprintf(stderr, " (at 0x%llx)\n", (unsigned long long)at);
+
+    /* The frames linked through `base_ptr`, as the VM walks them. */
+    while (frame >= 2 && frame <= vm->stack.len) {
+        size_t caller = vm->stack.buf[frame - 1];
+
+        frames++;
+        frame = caller < frame ? caller : 0;
+    }
+    if (frames < 2)
+        return;
+
+    fprintf(stderr, "    #0 0x%04llx\n", (unsigned long long)at);
+    for (i = 1, frame = vm->base_ptr; frame >= 2 && frame <= vm->stack.len; i++) {
+        size_t caller = vm->stack.buf[frame - 1];
+
+        fprintf(stderr, "    #%d 0x%04llx\n", i, (unsigned long long)vm->stack.buf[frame - 2]);
+        frame = caller < frame ? caller : 0;
+    }
+}
+
+/* Pops the frame of the callee, leaving the return address in `pc`. */
+static inline int rsvm_ret(struct rsvm *vm)
+{
+    uint32_t base_ptr, addr;
+
+    if (vm->stack.len < vm->base_ptr)
+        return 0;
+    vm->stack.len = vm->base_ptr;
+
+    if (!rsvm_pop(vm, &base_ptr) || !rsvm_pop(vm, &addr))
+        return 0;
+
+    vm->base_ptr = base_ptr;
+    vm->pc = addr;
+    return 1;
+}
+
+static inline int rsvm_int_return(struct rsvm *vm)
+{
+    uint32_t bits, addr;
+    int have_bits = rsvm_pop(vm, &bits), have_addr = rsvm_pop(vm, &addr);
+    unsigned char stop = vm->flags[RSVM_STOP];
+    int i;
+
+    if (!have_bits || !have_addr)
+        return 0;
+
+    for (i = 0; i < RSVM_NO_OF_FLAGS; i++)
+        vm->flags[i] = bits >> i & 1;
+    vm->flags[RSVM_STOP] = stop;
+
+    vm->pc = addr;
+    return 1;
+}
+
+static inline void rsvm_swap_context(struct rsvm *vm, size_t tid)
+{
+    struct rsvm_thread *thread = &vm->threads[tid];
+    struct rsvm_thread saved = *thread;
+
+    memcpy(thread->regs, vm->regs, sizeof(vm->regs));
+    memcpy(thread->flags, vm->flags, sizeof(vm->flags));
+    thread->stack = vm->stack;
+    thread->pc = vm->pc;
+    thread->base_ptr = vm->base_ptr;
+
+    memcpy(vm->regs, saved.regs, sizeof(vm->regs));
+    memcpy(vm->flags, saved.flags, sizeof(vm->flags));
+    vm->stack = saved.stack;
+    vm->pc = saved.pc;
+    vm->base_ptr = saved.base_ptr;
+}
+
+static inline void rsvm_push_thread(struct rsvm *vm, const uint64_t *regs, uint64_t pc)
+{
+    struct rsvm_thread *threads = realloc(vm->threads, (vm->thread_count + 1) * sizeof(*threads));
+    struct rsvm_thread *thread;
+
+    if (threads == NULL) {
+        fprintf(stderr, "Failed to allocate a thread! Aborting!\n");
+        abort();
+    }
+    vm->threads = threads;
+
+    thread = &threads[vm->thread_count++];
+    memset(thread, 0, sizeof(*thread));
+    memcpy(thread->regs, regs, sizeof(thread->regs));
+    rsvm_stack_init(&thread->stack);
+    thread->pc = pc;
+}
+
+static inline uint64_t rsvm_spawn(struct rsvm *vm, uint64_t addr)
+{
+    static const uint64_t no_regs[4];
+
+    if (vm->thread_count == 0)
+        rsvm_push_thread(vm, no_regs, 0);
+    rsvm_push_thread(vm, vm->regs, addr);
+
+    return vm->thread_count - 1;
+}
+
+static inline int rsvm_is_runnable(struct rsvm *vm, size_t tid)
+{
+    switch (vm->threads[tid].state) {
+    case RSVM_READY:
+        return 1;
+    case RSVM_JOINING:
+        return vm->threads[vm->threads[tid].joining].state == RSVM_FINISHED;
+    default:
+        return 0;
+    }
+}
+
+static inline void rsvm_schedule(struct rsvm *vm, uint64_t at)
+{
+    size_t len = vm->thread_count, offset, next = 0;
+    int found = 0;
+
+    vm->yield_requested = 0;
+
+    /* Before anything is spawned, the main thread is the one to run. */
+    if (len == 0)
+        return;
+
+    for (offset = 1; offset <= len && !found; offset++) {
+        next = (vm->current_thread + offset) % len;
+        found = rsvm_is_runnable(vm, next);
+    }
+
+    if (!found) {
+        rsvm_fault(vm, RSVM_DEADLOCK, 0, at);
+        return;
+    }
+
+    vm->threads[next].state = RSVM_READY;
+
+    if (next != vm->current_thread) {
+        rsvm_swap_context(vm, vm->current_thread);
+        rsvm_swap_context(vm, next);
+        vm->current_thread = next;
+    }
+}
+
+static inline int rsvm_join(struct rsvm *vm, uint64_t tid, uint64_t at)
+{
+    size_t len = vm->thread_count > 1 ? vm->thread_count : 1;
+
+    if (tid >= len || tid == vm->current_thread) {
+        rsvm_fault(vm, RSVM_INVALID_THREAD, tid, at);
+        return 0;
+    }
+
+    vm->threads[vm->current_thread].state = RSVM_JOINING;
+    vm->threads[vm->current_thread].joining = tid;
+    vm->yield_requested = 1;
+    vm->events = 1;
+    return 1;
+}
+
+static inline void rsvm_exit_thread(struct rsvm *vm)
+{
+    vm->events = 1;
+
+    if (vm->current_thread == 0) {
+        vm->flags[RSVM_STOP] = 1;
+        return;
+    }
+
+    vm->threads[vm->current_thread].state = RSVM_FINISHED;
+    free(vm->stack.buf);
+    rsvm_stack_init(&vm->stack);
+    vm->yield_requested = 1;
+}
+
+static inline void rsvm_set_timer(struct rsvm *vm, uint32_t period)
+{
+    vm->timer_period = period;
+    vm->timer_count = 0;
+    vm->events = 1;
+}
+
+static inline void rsvm_service_interrupts(struct rsvm *vm)
+{
+    uint32_t n = 0, handler;
+
+    if (!vm->flags[RSVM_INTERRUPT] || vm->pending == 0)
+        return;
+
+    while (!(vm->pending >> n & 1))
+        n++;
+    vm->pending &= ~((uint32_t)1 << n);
+
+    handler = rsvm_read(vm, vm->ivt_base + n);
+    if (handler == 0)
+        return;
+
+    rsvm_push(vm, (uint32_t)vm->pc);
+    rsvm_push(vm, rsvm_flag_bits(vm));
+
+    vm->flags[RSVM_INTERRUPT] = 0;
+    vm->pc = handler;
+}
+
+/*
+ * Does what the VM does between two instructions, once the one at `at` is
+ * done and `pc` holds the next. Tells whether the program has to continue
+ * somewhere else than that.
+ */
+static inline int rsvm_poll(struct rsvm *vm, uint64_t at)
+{
+    uint64_t pc = vm->pc;
+
+    if (vm->flags[RSVM_STOP] || vm->faulted)
+        return 1;
+
+    if (vm->timer_period != 0 && ++vm->timer_count >= vm->timer_period) {
+        vm->timer_count = 0;
+        vm->pending |= 1;
+    }
+
+    if (vm->yield_requested) {
+        rsvm_schedule(vm, at);
+
+        if (vm->faulted)
+            return 1;
+    }
+
+    rsvm_service_interrupts(vm);
+
+    vm->events = vm->timer_period != 0 || vm->yield_requested || vm->pending != 0;
+    return vm->pc != pc;
+}
+
+static inline uint64_t rsvm_splitmix64(uint64_t *state)
+{
+    uint64_t z = *state += 0x9e3779b97f4a7c15u;
+
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9u;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebu;
+    return z ^ (z >> 31);
+}
+
+static inline uint64_t rsvm_rotl(uint64_t x, int k)
+{
+    return (x << k) | (x >> (64 - k));
+}
+
+/* The xoshiro256** generator of the VM, seeded with 0 like it is. */
+static inline uint32_t rsvm_random(struct rsvm *vm)
+{
+    uint64_t *s = vm->rng;
+    uint64_t result = rsvm_rotl(s[1] * 5, 7) * 9;
+    uint64_t t = s[1] << 17;
+
+    s[2] ^= s[0];
+    s[3] ^= s[1];
+    s[1] ^= s[2];
+    s[0] ^= s[3];
+    s[2] ^= t;
+    s[3] = rsvm_rotl(s[3], 45);
+
+    return (uint32_t)(result >> 32);
+}
+
+static inline uint64_t rsvm_seek_status(struct rsvm *vm)
+{
+    int32_t offset = (int32_t)(uint32_t)vm->regs[2];
+
+    switch (vm->regs[3]) {
+    case RSVM_SEEK_START:
+        return offset >= 0 ? RSVM_BAD_FD : RSVM_INVALID;
+    case RSVM_SEEK_CURRENT:
+    case RSVM_SEEK_END:
+        return RSVM_BAD_FD;
+    default:
+        return RSVM_INVALID;
+    }
+}
+
+static inline void rsvm_set_split(struct rsvm *vm, uint64_t value)
+{
+    vm->regs[1] = value >> 32;
+    vm->regs[2] = (uint32_t)value;
+}
+
+/* Runs the syscall numbered in A, telling whether it faulted. */
+static inline int rsvm_syscall(struct rsvm *vm, uint64_t at)
+{
+    uint64_t number = vm->regs[0];
+    struct timespec now;
+    uint64_t i;
+
+    switch (number) {
+    case RSVM_WRITE_STDOUT: {
+        size_t len = vm->regs[2] * 4;
+        unsigned char *bytes = rsvm_read_bytes(vm, vm->regs[1], len);
+        size_t written = fwrite(bytes, 1, len, stdout);
+
+        free(bytes);
+        if (written != len) {
+            rsvm_fault(vm, RSVM_SYSCALL_FAILED, number, at);
+            return 1;
+        }
+        break;
+    }
+    case RSVM_READ_STDIN: {
+        size_t len = vm->regs[2];
+        unsigned char *bytes = malloc(len + 1);
+        ssize_t got;
+
+        if (bytes == NULL) {
+            fprintf(stderr, "Failed to allocate a buffer! Aborting!\n");
+            abort();
+        }
+
+        fflush(stdout);
+        got = read(STDIN_FILENO, bytes, len);
+        if (got < 0) {
+            free(bytes);
+            rsvm_fault(vm, RSVM_SYSCALL_FAILED, number, at);
+            return 1;
+        }
+
+        rsvm_write_bytes(vm, vm->regs[1], bytes, (size_t)got);
+        vm->regs[3] = (uint64_t)got;
+        free(bytes);
+        break;
+    }
+    case RSVM_CLEAR_SCREEN:
+        /* The VM runs `clear` with its output captured, so nothing ever
+         * reaches the terminal. */
+        break;
+    case RSVM_PRINT_NUMBER:
+        if (printf("%llu\n", (unsigned long long)vm->regs[1]) < 0) {
+            rsvm_fault(vm, RSVM_SYSCALL_FAILED, number, at);
+            return 1;
+        }
+        break;
+    case RSVM_EXIT:
+        vm->exit_code = (uint32_t)vm->regs[1];
+        vm->flags[RSVM_STOP] = 1;
+        vm->events = 1;
+        break;
+    case 0x10: case 0x11: case 0x12: case 0x13:
+    case 0x14: case 0x15: case 0x16: case 0x17:
+        vm->regs[0] = RSVM_BAD_CHANNEL;
+        break;
+    case RSVM_FILE_OPEN:
+    case RSVM_FILE_STAT:
+        /* Without a filesystem, paths are refused before being read. */
+        vm->regs[0] = RSVM_PERMISSION_DENIED;
+        break;
+    case RSVM_FILE_READ:
+    case RSVM_FILE_WRITE:
+    case RSVM_FILE_CLOSE:
+        vm->regs[0] = RSVM_BAD_FD;
+        break;
+    case RSVM_FILE_SEEK:
+        vm->regs[0] = rsvm_seek_status(vm);
+        break;
+    case RSVM_CLOCK_MONOTONIC:
+        clock_gettime(CLOCK_MONOTONIC, &now);
+        rsvm_set_split(vm, (uint64_t)((now.tv_sec - vm->start.tv_sec) * 1000000000LL + (now.tv_nsec - vm->start.tv_nsec)));
+        vm->regs[0] = RSVM_OK;
+        break;
+    case RSVM_CLOCK_WALL:
+        clock_gettime(CLOCK_REALTIME, &now);
+        rsvm_set_split(vm, (uint64_t)now.tv_sec);
+        vm->regs[3] = (uint64_t)now.tv_nsec;
+        vm->regs[0] = RSVM_OK;
+        break;
+    case RSVM_SLEEP: {
+        uint32_t millis = (uint32_t)vm->regs[1];
+        struct timespec duration;
+
+        duration.tv_sec = millis / 1000;
+        duration.tv_nsec = (long)(millis % 1000) * 1000000;
+
+        fflush(stdout);
+        while (nanosleep(&duration, &duration) != 0)
+            ;
+        vm->regs[0] = RSVM_OK;
+        break;
+    }
+    case RSVM_RANDOM:
+        vm->regs[1] = rsvm_random(vm);
+        vm->regs[2] = rsvm_random(vm);
+        vm->regs[3] = rsvm_random(vm);
+        vm->regs[0] = RSVM_OK;
+        break;
+    case RSVM_RANDOM_FILL:
+        if (vm->regs[2] > RSVM_MAX_IO_LEN || vm->regs[1] + vm->regs[2] < vm->regs[1]) {
+            vm->regs[0] = RSVM_TOO_LONG;
+            break;
+        }
+        for (i = 0; i < vm->regs[2]; i++) {
+            uint32_t value = rsvm_random(vm);
+
+            rsvm_write(vm, vm->regs[1] + i, value);
+        }
+        vm->regs[0] = RSVM_OK;
+        break;
+    }
+
+    return 0;
+}
+
+/*
+ * Lays out the arguments right after the data of the header, each as its
+ * length in bytes followed by the bytes themselves, and passes argc and argv
+ * in A and B. The environment is left empty.
+ */
+static inline void rsvm_set_args(struct rsvm *vm, size_t data_len, int argc, char **argv)
+{
+    uint64_t argv_addr = data_len, addr = data_len + argc;
+    int i;
+
+    for (i = 0; i < argc; i++) {
+        size_t len = strlen(argv[i]);
+
+        rsvm_write(vm, argv_addr + i, (uint32_t)addr);
+        rsvm_write(vm, addr, (uint32_t)len);
+        rsvm_write_bytes(vm, addr + 1, (const unsigned char *)argv[i], len);
+
+        addr += 1 + (len + 3) / 4;
+    }
+
+    vm->regs[0] = argc;
+    vm->regs[1] = argv_addr;
+    vm->regs[2] = 0;
+    vm->regs[3] = argv_addr + argc;
+}
+
+int main(int argc, char **argv)
+{
+    static struct rsvm vm;
+    uint64_t seed = 0;
+    int i;
+
+    vm.heap = calloc(RSVM_HEAP_INITIAL_CAPACITY, sizeof(uint32_t));
+    vm.heap_cap = RSVM_HEAP_INITIAL_CAPACITY;
+    if (vm.heap == NULL) {
+        fprintf(stderr, "Failed to allocate VM heap! Aborting!\n");
+        abort();
+    }
+    rsvm_stack_init(&vm.stack);
+
+    for (i = 0; i < 4; i++)
+        vm.rng[i] = rsvm_splitmix64(&seed);
+    clock_gettime(CLOCK_MONOTONIC, &vm.start);
+
+    rsvm_set_args(&vm, rsvm_load(&vm), argc, argv);
+    rsvm_run(&vm);
+
+    if (fflush(stdout) != 0 && !vm.faulted)
+        return 1;
+    if (vm.faulted)
+        return RSVM_FAULT_EXIT_CODE;
+
+    /* Clamped the way `rsvm` does it, rather than wrapping around. */
+    if (vm.exit_code == RSVM_FAULT_EXIT_CODE)
+        return RSVM_FAULT_EXIT_CODE + 1;
+    return vm.exit_code > 255 ? 255 : (int)vm.exit_code;
+}
+
+/*
+ * Continues at `label`, the translation of the instruction at `to`, once
+ * the one at `at` is done.
+ */
+#define RSVM_NEXT(at, to, label)            \
+    do {                                    \
+        if (vm->events) {                   \
+            vm->pc = (to);                  \
+            if (rsvm_poll(vm, (at)))        \
+                goto dispatch;              \
+        }                                   \
+        goto label;                         \
+    } while (0)
+
+/* Continues at whatever is at `to`, found through the dispatcher. */
+#define RSVM_JUMP(at, to)                   \
+    do {                                    \
+        vm->pc = (to);                      \
+        if (vm->events)                     \
+            rsvm_poll(vm, (at));            \
+        goto dispatch;                      \
+    } while (0)
+
+#define RSVM_FAULT(kind, arg, at)           \
+    do {                                    \
+        rsvm_fault(vm, (kind), (arg), (at)); \
+        return;                             \
+    } while (0)
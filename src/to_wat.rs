@@ -0,0 +1,485 @@
+use std::io::{self, Write};
+
+use crate::decode::{Instr, Reachable};
+use crate::{FaultKind, VM};
+
+/// The imports, globals and helpers of the translated module, which come
+/// before its code in the output.
+const RUNTIME: &str = include_str!("to_wat/runtime.wat");
+
+/// The faults as the `fault` import gets them.
+const STACK_UNDERFLOW: u32 = 0;
+const DIVISION_BY_ZERO: u32 = 1;
+const INVALID_REGISTER: u32 = 2;
+const PROGRAM_COUNTER_OUT_OF_BOUNDS: u32 = 3;
+const INVALID_THREAD: u32 = 4;
+// 5 is a deadlock, which the main thread alone never runs into.
+const SYSCALL_FAILED: u32 = 6;
+const NOT_TRANSLATED: u32 = 7;
+/// Spawning a thread, which the module has no way to do.
+const NOT_SUPPORTED: u32 = 8;
+
+const HEAP_INITIAL_CAPACITY: usize = 256;
+const STACK_INITIAL_CAPACITY: usize = 128;
+const PAGE_SIZE: usize = 0x10000;
+
+fn label(offset: usize) -> String {
+    format!("$L_{:04x}", offset)
+}
+
+fn reg(reg: u8) -> String {
+    format!("global.get ${}", (b'a' + reg) as char)
+}
+
+fn set_reg(reg: u8) -> String {
+    format!("global.set ${}", (b'a' + reg) as char)
+}
+
+struct Translator<'a, W> {
+    w: W,
+    instrs: &'a Reachable,
+    /// Whether the program sets the timer, without which there is nothing
+    /// to poll for between instructions.
+    timer: bool,
+}
+
+impl<'a, W: Write> Translator<'a, W> {
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.w, "    {}", line)
+    }
+
+    fn lines(&mut self, lines: &[&str]) -> io::Result<()> {
+        lines.iter().try_for_each(|line| self.line(line))
+    }
+
+    /// Reports a fault with the argument `arg` leaves on the stack.
+    fn fault(&mut self, at: usize, kind: u32, arg: &str) -> io::Result<()> {
+        self.line(&format!("i32.const {}", kind))?;
+        self.line(arg)?;
+        self.line(&format!("i32.const {:#x}", at))?;
+        self.lines(&["call $fault", "return"])
+    }
+
+    /// Faults with `kind` if the condition on the stack holds.
+    fn fault_if(&mut self, at: usize, kind: u32) -> io::Result<()> {
+        self.line("if")?;
+        self.fault(at, kind, "i64.const 0")?;
+        self.line("end")
+    }
+
+    /// Pops a word into `$x`, and another into `$y` for `two`.
+    fn pop(&mut self, at: usize, two: bool) -> io::Result<()> {
+        self.lines(&["call $pop", "local.set $x"])?;
+        if two {
+            self.lines(&["call $pop", "local.set $y"])?;
+        }
+
+        self.line("global.get $underflow")?;
+        self.fault_if(at, STACK_UNDERFLOW)
+    }
+
+    /// Pops two words, `$x` from the top, and pushes what `ops` make of them.
+    fn stack_op(&mut self, at: usize, ops: &[&str]) -> io::Result<()> {
+        self.pop(at, true)?;
+        self.lines(&["local.get $x", "local.get $y"])?;
+        self.lines(ops)?;
+        self.line("call $push")
+    }
+
+    /// Pops a word into `$x` and pushes what `ops` make of `num` and it.
+    fn stack_op_num(&mut self, at: usize, num: u8, ops: &[&str]) -> io::Result<()> {
+        self.pop(at, false)?;
+        self.lines(&[&format!("i32.const {}", num), "local.get $x"])?;
+        self.lines(ops)?;
+        self.line("call $push")
+    }
+
+    /// Sets `reg` to what `ops` make of its low word and `lit`.
+    fn reg_op_num(&mut self, r: u8, lit: u32, ops: &[&str]) -> io::Result<()> {
+        self.lines(&[&reg(r), "i32.wrap_i64", &format!("i32.const {}", lit)])?;
+        self.lines(ops)?;
+        self.lines(&["i64.extend_i32_u", &set_reg(r)])
+    }
+
+    /// Sets `a` to what `op` makes of it and `b`.
+    fn reg_op(&mut self, a: u8, b: u8, op: &str) -> io::Result<()> {
+        self.lines(&[&reg(a), &reg(b), op, &set_reg(a)])
+    }
+
+    /// Carries on at the `$pc` the instruction at hand left behind.
+    fn dispatch(&mut self) -> io::Result<()> {
+        if self.timer {
+            self.lines(&[
+                "global.get $events",
+                "if",
+                "local.get $pc",
+                "call $poll",
+                "local.set $pc",
+                "end",
+            ])?;
+        }
+
+        self.line("br $dispatch")
+    }
+
+    /// Carries on at `to` once the instruction at `at` is done: by falling
+    /// through to the next case, with a branch forward, or through the
+    /// dispatcher.
+    fn next(&mut self, at: usize, to: usize) -> io::Result<()> {
+        if self.timer {
+            self.lines(&["global.get $events", "if"])?;
+            self.line(&format!("i32.const {:#x}", to))?;
+            self.lines(&["call $poll", "local.set $pc", "br $dispatch", "end"])?;
+        }
+
+        let following = self
+            .instrs
+            .range(at + 1..)
+            .next()
+            .map(|(&offset, _)| offset);
+        if following == Some(to) {
+            Ok(())
+        } else if to > at && self.instrs.contains_key(&to) {
+            self.line(&format!("br {}", label(to)))
+        } else {
+            self.line(&format!("i32.const {:#x}", to))?;
+            self.lines(&["local.set $pc", "br $dispatch"])
+        }
+    }
+
+    fn branch(&mut self, at: usize, flag: &str, to: usize, next: usize) -> io::Result<()> {
+        self.lines(&[&format!("global.get ${}", flag), "if"])?;
+        self.next(at, to)?;
+        self.line("end")?;
+        self.next(at, next)
+    }
+
+    fn instr(&mut self, at: usize, instr: Instr, len: usize) -> io::Result<()> {
+        let next = at + len;
+
+        match instr {
+            Instr::Nop => {}
+            Instr::Exit => return self.lines(&["i32.const 0", "return"]),
+            Instr::ExitReg(r) => return self.lines(&[&reg(r), "i32.wrap_i64", "return"]),
+            Instr::PushLit(lit) => self.lines(&[&format!("i32.const {}", lit), "call $push"])?,
+            Instr::PushReg(r) => self.lines(&[&reg(r), "i32.wrap_i64", "call $push"])?,
+            Instr::PopReg(r) => {
+                self.pop(at, false)?;
+                self.lines(&["local.get $x", "i64.extend_i32_u", &set_reg(r)])?;
+            }
+            Instr::PopHeap(addr) => {
+                self.pop(at, false)?;
+                self.line(&format!("i64.const {}", addr))?;
+                self.lines(&["local.get $x", "call $write"])?;
+            }
+            Instr::StackDupe => self.lines(&["call $peek", "call $push"])?,
+            Instr::MovLitReg(r, lit) => {
+                self.lines(&[&format!("i64.const {}", lit), &set_reg(r)])?
+            }
+            Instr::MovLitHeap(addr, lit) => self.lines(&[
+                &format!("i64.const {}", addr),
+                &format!("i32.const {}", lit),
+                "call $write",
+            ])?,
+            Instr::MovHeapReg(r, addr) => self.lines(&[
+                &format!("i64.const {}", addr),
+                "call $read",
+                "i64.extend_i32_u",
+                &set_reg(r),
+            ])?,
+            Instr::MovRegHeap(addr, r) => self.lines(&[
+                &format!("i64.const {}", addr),
+                &reg(r),
+                "i32.wrap_i64",
+                "call $write",
+            ])?,
+            Instr::MovRegReg(dst, src) => self.lines(&[&reg(src), &set_reg(dst)])?,
+            Instr::MovHeapHeap(src, dst) => self.lines(&[
+                &format!("i64.const {}", dst),
+                &format!("i64.const {}", src),
+                "call $read",
+                "call $write",
+            ])?,
+            Instr::PushHeap(addr) => {
+                self.lines(&[&format!("i64.const {}", addr), "call $read", "call $push"])?
+            }
+            Instr::MovPtrReg(dst, ptr) => {
+                self.lines(&[&reg(ptr), "call $read", "i64.extend_i32_u", &set_reg(dst)])?
+            }
+            Instr::MovRegPtr(ptr, src) => {
+                self.lines(&[&reg(ptr), &reg(src), "i32.wrap_i64", "call $write"])?
+            }
+            Instr::AddReg(a, b) => self.reg_op(a, b, "call $add")?,
+            Instr::SubReg(a, b) => self.reg_op(a, b, "call $sub")?,
+            Instr::MulReg(a, b) => self.reg_op(a, b, "call $mul")?,
+            Instr::DivReg(a, b) => {
+                self.lines(&[&reg(b), "i64.eqz"])?;
+                self.fault_if(at, DIVISION_BY_ZERO)?;
+                self.reg_op(a, b, "i64.div_u")?;
+            }
+            Instr::AddStack => self.stack_op(at, &["call $add32"])?,
+            Instr::SubStack => self.stack_op(at, &["call $sub32"])?,
+            Instr::MulStack => self.stack_op(at, &["call $mul32"])?,
+            Instr::DivStack => {
+                self.pop(at, true)?;
+                self.lines(&["local.get $y", "i32.eqz"])?;
+                self.fault_if(at, DIVISION_BY_ZERO)?;
+                self.lines(&["local.get $x", "local.get $y", "i32.div_u", "call $push"])?;
+            }
+            Instr::NotReg(r) => self.lines(&[&reg(r), "i64.const -1", "i64.xor", &set_reg(r)])?,
+            Instr::NotStack => {
+                self.pop(at, false)?;
+                self.lines(&["local.get $x", "i32.const -1", "i32.xor", "call $push"])?;
+            }
+            Instr::AndReg(a, b) => self.reg_op(a, b, "i64.and")?,
+            Instr::AndStack => self.stack_op(at, &["i32.and"])?,
+            Instr::OrReg(a, b) => self.reg_op(a, b, "i64.or")?,
+            Instr::OrStack => self.stack_op(at, &["i32.or"])?,
+            Instr::XorReg(a, b) => self.reg_op(a, b, "i64.xor")?,
+            Instr::XorStack => self.stack_op(at, &["i32.xor"])?,
+            Instr::Jump(addr) => return self.next(at, addr as usize),
+            Instr::Call(addr) => {
+                self.lines(&[&format!("i32.const {:#x}", next as u32), "call $push"])?;
+                self.lines(&["global.get $base_ptr", "call $push"])?;
+                self.lines(&["global.get $stack_len", "global.set $base_ptr"])?;
+                return self.next(at, addr as usize);
+            }
+            Instr::Ret | Instr::IntReturn => {
+                let function = match instr {
+                    Instr::Ret => "call $ret",
+                    _ => "call $int_return",
+                };
+
+                self.lines(&[function, "local.set $pc", "global.get $underflow"])?;
+                self.fault_if(at, STACK_UNDERFLOW)?;
+                return self.dispatch();
+            }
+            Instr::CompareRegReg(a, b) => self.lines(&[
+                &reg(a),
+                "i32.wrap_i64",
+                &reg(b),
+                "i32.wrap_i64",
+                "call $compare",
+            ])?,
+            Instr::CompareRegLit(r, lit) => self.lines(&[
+                &reg(r),
+                "i32.wrap_i64",
+                &format!("i32.const {}", lit),
+                "call $compare",
+            ])?,
+            Instr::CompareStackLit(lit) => {
+                self.lines(&["call $peek", &format!("i32.const {}", lit), "call $compare"])?
+            }
+            Instr::JumpEqual(addr) => return self.branch(at, "equal", addr as usize, next),
+            Instr::JumpNotEqual(addr) => return self.branch(at, "not_equal", addr as usize, next),
+            Instr::JumpGreater(addr) => return self.branch(at, "greater", addr as usize, next),
+            Instr::JumpSmaller(addr) => return self.branch(at, "smaller", addr as usize, next),
+            Instr::JumpOverflow(addr) => return self.branch(at, "overflow", addr as usize, next),
+            Instr::FlagReset => {
+                for flag in ["equal", "not_equal", "greater", "smaller", "overflow"] {
+                    self.lines(&["i32.const 0", &format!("global.set ${}", flag)])?;
+                }
+            }
+            Instr::IncReg(r) => self.lines(&[&reg(r), "i64.const 1", "call $add", &set_reg(r)])?,
+            Instr::DecReg(r) => self.lines(&[&reg(r), "i64.const 1", "call $sub", &set_reg(r)])?,
+            Instr::IncStack => {
+                self.pop(at, false)?;
+                self.lines(&["local.get $x", "i32.const 1", "call $add32", "call $push"])?;
+            }
+            Instr::DecStack => {
+                self.pop(at, false)?;
+                self.lines(&["local.get $x", "i32.const 1", "call $sub32", "call $push"])?;
+            }
+            Instr::AddRegNum(r, lit) => self.reg_op_num(r, lit, &["call $add32"])?,
+            Instr::SubRegNum(r, lit) => self.reg_op_num(r, lit, &["call $sub32"])?,
+            Instr::MulRegNum(r, lit) => self.reg_op_num(r, lit, &["call $mul32"])?,
+            Instr::DivRegNum(_, 0) => return self.fault(at, DIVISION_BY_ZERO, "i64.const 0"),
+            Instr::DivRegNum(r, lit) => self.lines(&[
+                &reg(r),
+                &format!("i64.const {}", lit),
+                "i64.div_u",
+                &set_reg(r),
+            ])?,
+            Instr::AndRegNum(r, lit) => self.lines(&[
+                &reg(r),
+                &format!("i64.const {}", lit),
+                "i64.and",
+                &set_reg(r),
+            ])?,
+            Instr::OrRegNum(r, lit) => self.lines(&[
+                &reg(r),
+                &format!("i64.const {}", lit),
+                "i64.or",
+                &set_reg(r),
+            ])?,
+            Instr::XorRegNum(r, lit) => self.lines(&[
+                &reg(r),
+                &format!("i64.const {}", lit),
+                "i64.xor",
+                &set_reg(r),
+            ])?,
+            Instr::AddStackNum(num) => self.stack_op_num(at, num, &["call $add32"])?,
+            Instr::SubStackNum(num) => self.stack_op_num(at, num, &["call $sub32"])?,
+            Instr::MulStackNum(num) => self.stack_op_num(at, num, &["call $mul32"])?,
+            Instr::DivStackNum(num) => {
+                self.pop(at, false)?;
+                self.lines(&["local.get $x", "i32.eqz"])?;
+                self.fault_if(at, DIVISION_BY_ZERO)?;
+                self.lines(&[&format!("i32.const {}", num), "local.get $x", "i32.div_u"])?;
+                self.line("call $push")?;
+            }
+            Instr::AndStackNum(num) => self.stack_op_num(at, num, &["i32.and"])?,
+            Instr::OrStackNum(num) => self.stack_op_num(at, num, &["i32.or"])?,
+            Instr::XorStackNum(num) => self.stack_op_num(at, num, &["i32.xor"])?,
+            Instr::IntEnable => self.lines(&["i32.const 1", "global.set $interrupt"])?,
+            Instr::IntDisable => self.lines(&["i32.const 0", "global.set $interrupt"])?,
+            Instr::IntTable(addr) => {
+                self.lines(&[&format!("i64.const {}", addr), "global.set $ivt_base"])?
+            }
+            Instr::TimerSet(period) => {
+                self.lines(&[&format!("i32.const {}", period), "call $set_timer"])?
+            }
+            Instr::ThreadSpawn(_, _) => return self.fault(at, NOT_SUPPORTED, "i64.const 0"),
+            // With the main thread alone there is nothing to switch to or to
+            // wait for.
+            Instr::ThreadYield => {}
+            Instr::ThreadJoin(r) => return self.fault(at, INVALID_THREAD, &reg(r)),
+            Instr::ThreadSelf(r) => self.lines(&["i64.const 0", &set_reg(r)])?,
+            Instr::Syscall => {
+                self.lines(&["global.get $a", "local.tee $n", "i64.const 4", "i64.eq"])?;
+                self.lines(&["if", "global.get $b", "i32.wrap_i64", "return", "end"])?;
+                self.lines(&["local.get $n", "call $syscall", "if"])?;
+                self.fault(at, SYSCALL_FAILED, "local.get $n")?;
+                self.line("end")?;
+            }
+        }
+
+        self.next(at, next)
+    }
+
+    fn run(&mut self, code_len: usize) -> io::Result<()> {
+        writeln!(self.w, "  (func $run (export \"run\") (result i32)")?;
+        self.line("(local $pc i32)")?;
+        self.line("(local $x i32)")?;
+        self.line("(local $y i32)")?;
+        self.line("(local $n i64)")?;
+        self.line("loop $dispatch")?;
+        self.line("block $default")?;
+        for &offset in self.instrs.keys().rev() {
+            self.line(&format!("block {}", label(offset)))?;
+        }
+
+        self.line("local.get $pc")?;
+        self.line("br_table")?;
+        let targets = (0..code_len)
+            .map(|offset| match self.instrs.contains_key(&offset) {
+                true => label(offset),
+                false => "$default".to_string(),
+            })
+            .collect::<Vec<_>>();
+        for chunk in targets.chunks(8) {
+            self.line(&format!("  {}", chunk.join(" ")))?;
+        }
+        self.line("  $default")?;
+
+        for (&offset, decoded) in self.instrs {
+            self.line("end")?;
+
+            match decoded {
+                Ok((instr, len)) => {
+                    self.line(&format!(";; {:#06x} {:?}", offset, instr))?;
+                    self.instr(offset, *instr, *len)?;
+                }
+                Err(kind) => {
+                    self.line(&format!(";; {:#06x}", offset))?;
+
+                    let (kind, arg) = match kind {
+                        FaultKind::InvalidRegister(reg) => (INVALID_REGISTER, *reg),
+                        _ => (PROGRAM_COUNTER_OUT_OF_BOUNDS, 0),
+                    };
+                    self.fault(offset, kind, &format!("i64.const {}", arg))?;
+                }
+            }
+        }
+
+        self.line("end")?;
+        self.lines(&[
+            "local.get $pc",
+            &format!("i32.const {:#x}", code_len),
+            "i32.ge_u",
+        ])?;
+        self.line("if")?;
+        self.lines(&[
+            &format!("i32.const {}", PROGRAM_COUNTER_OUT_OF_BOUNDS),
+            "i64.const 0",
+            "local.get $pc",
+            "call $fault",
+            "return",
+        ])?;
+        self.line("end")?;
+        self.lines(&[
+            &format!("i32.const {}", NOT_TRANSLATED),
+            "i64.const 0",
+            "local.get $pc",
+            "call $fault",
+            "return",
+        ])?;
+        self.line("end")?;
+        self.line("unreachable")?;
+        writeln!(self.w, "  )")
+    }
+}
+
+/// Translates a program, header and all, to a WebAssembly text module that
+/// runs it the way the `VM` would, short of threads. The module leaves the
+/// syscalls to its host; `to_wat/runtime.wat` has the interface between the
+/// two. Bytecode without a header is refused with
+/// `io::ErrorKind::InvalidData` before anything is written.
+pub fn translate<W: Write>(bytecode: Vec<u8>, mut w: W) -> io::Result<()> {
+    let vm = VM::load_translatable(bytecode)?;
+
+    let data = &vm.bytecode[..vm.hdr_size - 4];
+    let code = vm.bytecode.get(vm.hdr_size..).unwrap_or_default();
+    let instrs = vm.decoded.reachable(code);
+    let timer = instrs
+        .values()
+        .any(|decoded| matches!(decoded, Ok((Instr::TimerSet(_), _))));
+
+    let heap_cap = data.len().max(HEAP_INITIAL_CAPACITY);
+    let pages = ((heap_cap + STACK_INITIAL_CAPACITY) * 4).div_ceil(PAGE_SIZE);
+
+    writeln!(w, ";; Translated from rsvm bytecode by rsvm-to-wat.")?;
+    writeln!(w, "(module")?;
+    w.write_all(RUNTIME.as_bytes())?;
+    writeln!(w)?;
+    writeln!(w, "  (memory (export \"memory\") {})", pages)?;
+    writeln!(w, "  (global $heap_cap (mut i32) (i32.const {}))", heap_cap)?;
+    writeln!(
+        w,
+        "  (global $data_len (export \"data_len\") i32 (i32.const {}))",
+        data.len()
+    )?;
+
+    if !data.is_empty() {
+        writeln!(w, "  (data (i32.const 0)")?;
+        for chunk in data.chunks(16) {
+            let words = chunk
+                .iter()
+                .map(|byte| format!("\\{:02x}\\00\\00\\00", byte))
+                .collect::<String>();
+            writeln!(w, "    \"{}\"", words)?;
+        }
+        writeln!(w, "  )")?;
+    }
+    writeln!(w)?;
+
+    Translator {
+        w: &mut w,
+        instrs: &instrs,
+        timer,
+    }
+    .run(code.len())?;
+
+    writeln!(w, ")")?;
+    w.flush()
+}
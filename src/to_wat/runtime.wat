@@ -0,0 +1,272 @@
+  ;; The runtime of modules translated by rsvm-to-wat. It mirrors the parts of
+  ;; the VM the translated code cannot do inline: the stack and the heap and
+  ;; the timer interrupt. The heap starts at address 0 of the memory, one word
+  ;; every 4 bytes, and the stack comes right after it. There is only the main
+  ;; thread.
+  ;;
+  ;; The host runs the program through `run`, which returns its exit code,
+  ;; and provides the two imports:
+  ;;
+  ;;   syscall(number: i64) -> i32
+  ;;       Carries out every syscall but EXIT, with the registers in the `a`
+  ;;       to `d` globals and the heap behind `read` and `write`. Anything
+  ;;       but 0 fails the syscall.
+  ;;   fault(kind: i32, arg: i64, at: i32) -> i32
+  ;;       Reports a fault, which stops the program. Whatever it returns is
+  ;;       what `run` does. The kinds are, in order from 0: stack underflow,
+  ;;       division by zero, invalid register, program counter out of
+  ;;       bounds, invalid thread, deadlock, syscall failed, not translated
+  ;;       and spawning a thread, which the module cannot do.
+  ;;
+  ;; The arguments go on the heap starting at `data_len`, the way rsvm lays
+  ;; them out, before calling `run`.
+
+  (import "rsvm" "syscall" (func $syscall (param i64) (result i32)))
+  (import "rsvm" "fault" (func $fault (param i32 i64 i32) (result i32)))
+
+  (global $a (export "a") (mut i64) (i64.const 0))
+  (global $b (export "b") (mut i64) (i64.const 0))
+  (global $c (export "c") (mut i64) (i64.const 0))
+  (global $d (export "d") (mut i64) (i64.const 0))
+
+  (global $equal (mut i32) (i32.const 0))
+  (global $not_equal (mut i32) (i32.const 0))
+  (global $greater (mut i32) (i32.const 0))
+  (global $smaller (mut i32) (i32.const 0))
+  (global $overflow (mut i32) (i32.const 0))
+  (global $interrupt (mut i32) (i32.const 0))
+
+  (global $stack_len (mut i32) (i32.const 0))
+  (global $stack_cap (mut i32) (i32.const 128))
+  (global $base_ptr (mut i32) (i32.const 0))
+  ;; Set by the pops that found the stack empty.
+  (global $underflow (mut i32) (i32.const 0))
+
+  (global $ivt_base (mut i64) (i64.const 0))
+  (global $timer_period (mut i32) (i32.const 0))
+  (global $timer_count (mut i32) (i32.const 0))
+  (global $pending (mut i32) (i32.const 0))
+  ;; Whether the timer or a pending interrupt needs `poll` after every
+  ;; instruction.
+  (global $events (mut i32) (i32.const 0))
+
+  ;; Grows the memory to hold `words` words, trapping past 4GiB.
+  (func $reserve (param $words i64)
+    (local $pages i64)
+    (if (i64.gt_u (local.get $words) (i64.const 0x40000000))
+      (then (unreachable)))
+    (local.set $pages
+      (i64.shr_u
+        (i64.add (i64.shl (local.get $words) (i64.const 2)) (i64.const 0xffff))
+        (i64.const 16)))
+    (if (i64.gt_u (local.get $pages) (i64.extend_i32_u (memory.size)))
+      (then
+        (if (i32.eq
+              (memory.grow
+                (i32.sub (i32.wrap_i64 (local.get $pages)) (memory.size)))
+              (i32.const -1))
+          (then (unreachable))))))
+
+  (func $read (export "read") (param $addr i64) (result i32)
+    (if (result i32)
+      (i64.lt_u (local.get $addr) (i64.extend_i32_u (global.get $heap_cap)))
+      (then (i32.load (i32.shl (i32.wrap_i64 (local.get $addr)) (i32.const 2))))
+      (else (i32.const 0))))
+
+  ;; Writes a word to the heap, growing it like the VM does and moving the
+  ;; stack out of the way.
+  (func $write (export "write") (param $addr i64) (param $value i32)
+    (local $cap i64)
+    (if (i64.ge_u (local.get $addr) (i64.extend_i32_u (global.get $heap_cap)))
+      (then
+        (if (i32.eqz (local.get $value))
+          (then (return)))
+        (local.set $cap
+          (i64.shl (i64.extend_i32_u (global.get $heap_cap)) (i64.const 1)))
+        (if (i64.gt_u (i64.add (local.get $addr) (i64.const 1)) (local.get $cap))
+          (then (local.set $cap (i64.add (local.get $addr) (i64.const 1)))))
+        (call $reserve
+          (i64.add (local.get $cap) (i64.extend_i32_u (global.get $stack_cap))))
+        (memory.copy
+          (i32.shl (i32.wrap_i64 (local.get $cap)) (i32.const 2))
+          (i32.shl (global.get $heap_cap) (i32.const 2))
+          (i32.shl (global.get $stack_cap) (i32.const 2)))
+        (memory.fill
+          (i32.shl (global.get $heap_cap) (i32.const 2))
+          (i32.const 0)
+          (i32.shl
+            (i32.sub (i32.wrap_i64 (local.get $cap)) (global.get $heap_cap))
+            (i32.const 2)))
+        (global.set $heap_cap (i32.wrap_i64 (local.get $cap)))))
+    (i32.store
+      (i32.shl (i32.wrap_i64 (local.get $addr)) (i32.const 2))
+      (local.get $value)))
+
+  ;; The address of the stack slot `index`.
+  (func $slot (param $index i32) (result i32)
+    (i32.shl (i32.add (global.get $heap_cap) (local.get $index)) (i32.const 2)))
+
+  (func $push (param $value i32)
+    (if (i32.eq (global.get $stack_len) (global.get $stack_cap))
+      (then
+        (call $reserve
+          (i64.add
+            (i64.extend_i32_u (global.get $heap_cap))
+            (i64.shl (i64.extend_i32_u (global.get $stack_cap)) (i64.const 1))))
+        (memory.fill
+          (call $slot (global.get $stack_cap))
+          (i32.const 0)
+          (i32.shl (global.get $stack_cap) (i32.const 2)))
+        (global.set $stack_cap (i32.shl (global.get $stack_cap) (i32.const 1)))))
+    (i32.store (call $slot (global.get $stack_len)) (local.get $value))
+    (global.set $stack_len (i32.add (global.get $stack_len) (i32.const 1))))
+
+  (func $pop (result i32)
+    (if (i32.eqz (global.get $stack_len))
+      (then
+        (global.set $underflow (i32.const 1))
+        (return (i32.const 0))))
+    (global.set $stack_len (i32.sub (global.get $stack_len) (i32.const 1)))
+    (i32.load (call $slot (global.get $stack_len))))
+
+  ;; The word on top of the stack, or 0 if it's empty.
+  (func $peek (result i32)
+    (if (result i32) (i32.eqz (global.get $stack_len))
+      (then (i32.const 0))
+      (else (i32.load (call $slot (i32.sub (global.get $stack_len) (i32.const 1)))))))
+
+  (func $add (param $a i64) (param $b i64) (result i64)
+    (local $value i64)
+    (local.set $value (i64.add (local.get $a) (local.get $b)))
+    (if (i64.lt_u (local.get $value) (local.get $a))
+      (then (global.set $overflow (i32.const 1))))
+    (local.get $value))
+
+  (func $sub (param $a i64) (param $b i64) (result i64)
+    (if (i64.lt_u (local.get $a) (local.get $b))
+      (then (global.set $overflow (i32.const 1))))
+    (i64.sub (local.get $a) (local.get $b)))
+
+  (func $mul (param $a i64) (param $b i64) (result i64)
+    (local $value i64)
+    (local.set $value (i64.mul (local.get $a) (local.get $b)))
+    (if (i64.ne (local.get $a) (i64.const 0))
+      (then
+        (if (i64.ne (i64.div_u (local.get $value) (local.get $a)) (local.get $b))
+          (then (global.set $overflow (i32.const 1))))))
+    (local.get $value))
+
+  (func $add32 (param $a i32) (param $b i32) (result i32)
+    (local $value i32)
+    (local.set $value (i32.add (local.get $a) (local.get $b)))
+    (if (i32.lt_u (local.get $value) (local.get $a))
+      (then (global.set $overflow (i32.const 1))))
+    (local.get $value))
+
+  (func $sub32 (param $a i32) (param $b i32) (result i32)
+    (if (i32.lt_u (local.get $a) (local.get $b))
+      (then (global.set $overflow (i32.const 1))))
+    (i32.sub (local.get $a) (local.get $b)))
+
+  (func $mul32 (param $a i32) (param $b i32) (result i32)
+    (local $value i64)
+    (local.set $value
+      (i64.mul (i64.extend_i32_u (local.get $a)) (i64.extend_i32_u (local.get $b))))
+    (if (i64.gt_u (local.get $value) (i64.const 0xffffffff))
+      (then (global.set $overflow (i32.const 1))))
+    (i32.wrap_i64 (local.get $value)))
+
+  (func $compare (param $a i32) (param $b i32)
+    (global.set $equal (i32.eq (local.get $a) (local.get $b)))
+    (global.set $not_equal (i32.ne (local.get $a) (local.get $b)))
+    (global.set $greater (i32.gt_u (local.get $a) (local.get $b)))
+    (global.set $smaller (i32.lt_u (local.get $a) (local.get $b))))
+
+  (func $flag_bits (result i32)
+    (i32.or
+      (i32.or
+        (i32.or (global.get $equal) (i32.shl (global.get $not_equal) (i32.const 1)))
+        (i32.or
+          (i32.shl (global.get $greater) (i32.const 2))
+          (i32.shl (global.get $smaller) (i32.const 3))))
+      (i32.or
+        (i32.shl (global.get $overflow) (i32.const 4))
+        (i32.shl (global.get $interrupt) (i32.const 6)))))
+
+  (func $set_flag_bits (param $bits i32)
+    (global.set $equal (i32.and (local.get $bits) (i32.const 1)))
+    (global.set $not_equal
+      (i32.and (i32.shr_u (local.get $bits) (i32.const 1)) (i32.const 1)))
+    (global.set $greater
+      (i32.and (i32.shr_u (local.get $bits) (i32.const 2)) (i32.const 1)))
+    (global.set $smaller
+      (i32.and (i32.shr_u (local.get $bits) (i32.const 3)) (i32.const 1)))
+    (global.set $overflow
+      (i32.and (i32.shr_u (local.get $bits) (i32.const 4)) (i32.const 1)))
+    (global.set $interrupt
+      (i32.and (i32.shr_u (local.get $bits) (i32.const 6)) (i32.const 1))))
+
+  ;; Pops the frame of the callee and returns the return address, or sets
+  ;; `underflow`.
+  (func $ret (result i32)
+    (local $base_ptr i32)
+    (local $addr i32)
+    (if (i32.lt_u (global.get $stack_len) (global.get $base_ptr))
+      (then
+        (global.set $underflow (i32.const 1))
+        (return (i32.const 0))))
+    (global.set $stack_len (global.get $base_ptr))
+    (local.set $base_ptr (call $pop))
+    (local.set $addr (call $pop))
+    (if (i32.eqz (global.get $underflow))
+      (then (global.set $base_ptr (local.get $base_ptr))))
+    (local.get $addr))
+
+  (func $int_return (result i32)
+    (local $bits i32)
+    (local $addr i32)
+    (local.set $bits (call $pop))
+    (local.set $addr (call $pop))
+    (if (i32.eqz (global.get $underflow))
+      (then (call $set_flag_bits (local.get $bits))))
+    (local.get $addr))
+
+  (func $set_timer (param $period i32)
+    (global.set $timer_period (local.get $period))
+    (global.set $timer_count (i32.const 0))
+    (global.set $events
+      (i32.or (local.get $period) (global.get $pending))))
+
+  ;; Ticks the timer and services a pending interrupt once the instruction
+  ;; before `pc` is done, returning where the program carries on.
+  (func $poll (param $pc i32) (result i32)
+    (local $n i32)
+    (local $handler i32)
+    (if (global.get $timer_period)
+      (then
+        (global.set $timer_count (i32.add (global.get $timer_count) (i32.const 1)))
+        (if (i32.ge_u (global.get $timer_count) (global.get $timer_period))
+          (then
+            (global.set $timer_count (i32.const 0))
+            (global.set $pending (i32.or (global.get $pending) (i32.const 1)))))))
+
+    (if (i32.and (global.get $interrupt) (i32.ne (global.get $pending) (i32.const 0)))
+      (then
+        (local.set $n (i32.ctz (global.get $pending)))
+        (global.set $pending
+          (i32.and
+            (global.get $pending)
+            (i32.xor (i32.shl (i32.const 1) (local.get $n)) (i32.const -1))))
+        (local.set $handler
+          (call $read
+            (i64.add (global.get $ivt_base) (i64.extend_i32_u (local.get $n)))))
+        (if (local.get $handler)
+          (then
+            (call $push (local.get $pc))
+            (call $push (call $flag_bits))
+            (global.set $interrupt (i32.const 0))
+            (local.set $pc (local.get $handler))))))
+
+    (global.set $events
+      (i32.or (global.get $timer_period) (global.get $pending)))
+    (local.get $pc))
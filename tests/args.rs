@@ -0,0 +1,65 @@
+mod common;
+
+use common::{assemble_with_data, load};
+
+#[test]
+fn args_and_env_follow_the_header_data() {
+    let mut src = "
+        push_reg D
+        push_reg C
+        push_reg B
+        mov_reg_reg B A
+        mov_lit_reg A 3
+        syscall
+        pop_reg B
+        syscall
+        pop_reg B
+        syscall
+        pop_reg B
+        syscall
+    "
+    .to_string();
+    for addr in 3..12 {
+        src += &format!("mov_heap_reg B {}\nsyscall\n", addr);
+    }
+    src += "exit";
+
+    let (mut vm, output) = load(assemble_with_data(b"abc", &src));
+    vm.set_args(&["prog", "ab"], &["K=V"]);
+    vm.run_program().unwrap();
+
+    let word = |bytes: &[u8; 4]| u32::from_be_bytes(*bytes);
+    let expected = [
+        // argc, argv, envc and envp.
+        2,
+        3,
+        1,
+        5,
+        // The pointer arrays.
+        6,
+        8,
+        10,
+        // Each string's length followed by its bytes.
+        4,
+        word(b"prog"),
+        2,
+        word(b"ab\0\0"),
+        3,
+        word(b"K=V\0"),
+    ];
+    let printed = output
+        .text()
+        .lines()
+        .map(|line| line.parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(printed, expected);
+}
+
+#[test]
+fn args_start_at_zero_without_header_data() {
+    let (mut vm, _) = load(assemble_with_data(b"", "exit_reg B"));
+    vm.set_args(&["prog"], &[]);
+
+    assert_eq!(vm.run_program().unwrap().code(), 0);
+}
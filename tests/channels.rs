@@ -0,0 +1,225 @@
+mod common;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::VM;
+
+use common::{assemble, assemble_with_data, Captured};
+
+/// Prints the status in A and the length in D after a syscall.
+const REPORT: &str = "
+    mov_reg_reg C D
+    mov_reg_reg B A
+    mov_lit_reg A 3
+    syscall
+    mov_reg_reg B C
+    mov_lit_reg A 3
+    syscall
+";
+
+fn load(bytecode: Vec<u8>, channel: &Channel) -> (VM, Captured) {
+    let (mut vm, output) = common::load(bytecode);
+    vm.attach_channel(channel.clone());
+
+    (vm, output)
+}
+
+fn run(bytecode: Vec<u8>, channel: &Channel) -> String {
+    let (mut vm, output) = load(bytecode, channel);
+    vm.run_program().unwrap();
+
+    output.text()
+}
+
+#[test]
+fn words_go_both_ways() {
+    let channel = Channel::unbounded();
+    channel.send(Message::Word(5)).unwrap();
+    let src = "
+        mov_lit_reg A 0x12
+        mov_lit_reg B 0
+        syscall
+        mov_reg_reg C B
+        math_mul_reg_num C 2
+        mov_lit_reg A 0x10
+        mov_lit_reg B 0
+        syscall
+        exit_reg A
+    ";
+
+    assert_eq!(run(assemble(src), &channel), "");
+    assert_eq!(channel.recv(), Some(Message::Word(10)));
+}
+
+#[test]
+fn buffers_are_sent_from_the_heap() {
+    let channel = Channel::unbounded();
+    let src = "
+        mov_lit_reg A 0x11
+        mov_lit_reg B 0
+        mov_lit_reg C 1
+        mov_lit_reg D 3
+        syscall
+    "
+    .to_string()
+        + REPORT
+        + "exit";
+
+    assert_eq!(
+        run(assemble_with_data(&[1, 2, 3, 4], &src), &channel),
+        "0\n3\n"
+    );
+    assert_eq!(channel.recv(), Some(Message::Buffer(vec![2, 3, 4])));
+}
+
+#[test]
+fn buffers_are_received_into_the_heap() {
+    let channel = Channel::unbounded();
+    channel.send(Message::Buffer(vec![5, 6])).unwrap();
+    let src = "
+        mov_lit_reg A 0x13
+        mov_lit_reg B 0
+        mov_lit_reg C 10
+        mov_lit_reg D 4
+        syscall
+    "
+    .to_string()
+        + REPORT
+        + "
+        mov_heap_reg B 10
+        syscall
+        mov_heap_reg B 11
+        syscall
+        exit
+    ";
+
+    assert_eq!(run(assemble(&src), &channel), "0\n2\n5\n6\n");
+}
+
+#[test]
+fn buffers_too_long_to_receive_stay_queued() {
+    let channel = Channel::unbounded();
+    channel.send(Message::Buffer(vec![5, 6])).unwrap();
+    let src = "
+        mov_lit_reg A 0x13
+        mov_lit_reg B 0
+        mov_lit_reg C 10
+        mov_lit_reg D 1
+        syscall
+    "
+    .to_string()
+        + REPORT
+        + "exit";
+
+    assert_eq!(run(assemble(&src), &channel), "4\n2\n");
+    assert_eq!(channel.recv(), Some(Message::Buffer(vec![5, 6])));
+}
+
+#[test]
+fn buffers_are_not_received_as_words() {
+    let channel = Channel::unbounded();
+    channel.send(Message::Buffer(vec![5])).unwrap();
+    let src = "
+        mov_lit_reg A 0x12
+        mov_lit_reg B 0
+        syscall
+        exit_reg A
+    ";
+    let (mut vm, _) = load(assemble(src), &channel);
+
+    assert_eq!(vm.run_program().unwrap().code(), 5);
+    assert_eq!(channel.len(), 1);
+}
+
+#[test]
+fn buffers_past_the_io_limit_are_too_long() {
+    let channel = Channel::unbounded();
+    let src = "
+        mov_lit_reg A 0x11
+        mov_lit_reg B 0
+        mov_lit_reg C 0
+        mov_lit_reg D 0x100001
+        syscall
+        exit_reg A
+    ";
+    let (mut vm, _) = load(assemble(src), &channel);
+
+    assert_eq!(vm.run_program().unwrap().code(), 4);
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn buffers_past_the_end_of_the_address_space_are_too_long() {
+    let channel = Channel::unbounded();
+    channel.send(Message::Buffer(vec![5, 6])).unwrap();
+    for number in &["0x11", "0x13"] {
+        let src = format!(
+            "
+            mov_lit_reg A {}
+            mov_lit_reg B 0
+            mov_lit_reg C 0
+            math_not_reg C
+            mov_lit_reg D 2
+            syscall
+            exit_reg A
+        ",
+            number
+        );
+        let (mut vm, _) = load(assemble(&src), &channel);
+
+        assert_eq!(vm.run_program().unwrap().code(), 4);
+    }
+    assert_eq!(channel.recv(), Some(Message::Buffer(vec![5, 6])));
+}
+
+#[test]
+fn statuses_tell_why_nothing_was_transferred() {
+    let try_recv = "
+        mov_lit_reg A 0x16
+        syscall
+        exit_reg A
+    ";
+    let try_send = "
+        mov_lit_reg A 0x14
+        syscall
+        exit_reg A
+    ";
+    let code = |src: &str, channel: &Channel| {
+        let (mut vm, _) = load(assemble(src), channel);
+        vm.run_program().unwrap().code()
+    };
+
+    let channel = Channel::new(1);
+    assert_eq!(code(try_recv, &channel), 1);
+
+    channel.send(Message::Word(1)).unwrap();
+    assert_eq!(code(try_send, &channel), 1);
+
+    channel.close();
+    channel.recv();
+    assert_eq!(code(try_recv, &channel), 3);
+    assert_eq!(code(try_send, &channel), 3);
+
+    let bad_channel = format!("mov_lit_reg B 1\n{}", try_recv);
+    assert_eq!(code(&bad_channel, &Channel::unbounded()), 2);
+}
+
+#[test]
+fn receiving_blocks_until_a_message_arrives() {
+    let channel = Channel::unbounded();
+    let src = "
+        mov_lit_reg A 0x12
+        mov_lit_reg B 0
+        syscall
+        exit_reg B
+    ";
+    let (mut vm, _) = load(assemble(src), &channel);
+
+    for _ in 0..10 {
+        vm.step();
+    }
+    assert!(vm.is_blocked());
+    assert!(!vm.is_stopped());
+
+    channel.send(Message::Word(9)).unwrap();
+    assert_eq!(vm.run_program().unwrap().code(), 9);
+}
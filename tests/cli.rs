@@ -0,0 +1,124 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use common::{assemble, write_program};
+
+fn run(name: &str, src: &str, stdin: &[u8]) -> Output {
+    let path = write_program(name, &assemble(src));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rsvm"))
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    std::fs::remove_file(path).unwrap();
+    output
+}
+
+#[test]
+fn stdin_is_read_four_bytes_to_a_word() {
+    let src = "
+        mov_lit_reg A 1
+        mov_lit_reg B 100
+        mov_lit_reg C 16
+        syscall
+        push_reg D
+        mov_lit_reg A 0
+        mov_lit_reg B 100
+        mov_lit_reg C 2
+        syscall
+        mov_lit_reg A 3
+        mov_heap_reg B 100
+        syscall
+        pop_reg B
+        syscall
+        exit
+    ";
+
+    let output = run("stdin", src, b"rsvm!");
+
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        format!("rsvm!\0\0\0{}\n5\n", u32::from_be_bytes(*b"rsvm")).as_bytes()
+    );
+}
+
+fn exit_code(name: &str, src: &str) -> i32 {
+    run(name, src, b"").status.code().unwrap()
+}
+
+#[test]
+fn guest_exit_codes_reach_the_shell() {
+    assert_eq!(exit_code("exit-0", "exit"), 0);
+    assert_eq!(exit_code("exit-1", "mov_lit_reg A 1\nexit_reg A"), 1);
+    assert_eq!(exit_code("exit-255", "mov_lit_reg A 255\nexit_reg A"), 255);
+}
+
+#[test]
+fn guest_exit_codes_past_a_byte_do_not_wrap_around() {
+    assert_eq!(exit_code("exit-256", "mov_lit_reg A 256\nexit_reg A"), 255);
+    assert_eq!(exit_code("exit-326", "mov_lit_reg A 326\nexit_reg A"), 255);
+}
+
+#[test]
+fn faults_have_an_exit_code_of_their_own() {
+    let fault = "
+        mov_lit_reg A 1
+        mov_lit_reg B 0
+        math_div_reg A B
+        exit
+    ";
+
+    assert_eq!(exit_code("fault", fault), 70);
+    assert_eq!(exit_code("exit-70", "mov_lit_reg A 70\nexit_reg A"), 71);
+}
+
+#[test]
+fn huge_writes_to_stdout_do_not_crash_the_host() {
+    let src = "
+        mov_lit_reg C 0xFFFFFFFF
+        math_mul_reg C C
+        mov_lit_reg A 0
+        syscall
+        exit_reg A
+    ";
+
+    let output = run("huge-write", src, b"");
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn help_tells_how_exit_codes_are_mapped() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rsvm"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    let help = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(help.contains("clamped to 255"));
+    assert!(help.contains("exits with 71"));
+}
+
+#[test]
+fn recording_and_replaying_at_once_is_refused() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rsvm"))
+        .args(["--record", "run.log", "--replay", "run.log", "program"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("can't be used together"));
+    assert!(!std::path::Path::new("run.log").exists());
+}
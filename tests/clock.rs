@@ -0,0 +1,120 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::clock::VirtualClock;
+
+use common::{assemble, dispatches, load};
+
+/// Prints the low word of the monotonic clock in nanoseconds.
+const PRINT_MONOTONIC: &str = "
+    mov_lit_reg A 0x30
+    syscall
+    mov_reg_reg B C
+    mov_lit_reg A 3
+    syscall
+";
+
+fn run(src: &str, clock: impl Fn() -> VirtualClock) -> Vec<String> {
+    let mut outputs = Vec::new();
+
+    for dispatch in dispatches() {
+        let (mut vm, output) = load(assemble(src));
+        vm.set_dispatch(dispatch);
+        vm.set_clock(clock());
+
+        vm.run_program().unwrap();
+        outputs.push(output.text());
+    }
+
+    outputs
+}
+
+#[test]
+fn virtual_time_counts_executed_instructions() {
+    let src = "
+        mov_lit_reg D 0
+    loop:
+        math_inc_reg D
+        compare_reg_lit D 1000
+        jump_not_equal loop
+    "
+    .to_string()
+        + PRINT_MONOTONIC
+        + PRINT_MONOTONIC
+        + "exit";
+
+    for output in run(&src, || VirtualClock::new(Duration::from_nanos(3))) {
+        assert_eq!(output, "9006\n9021\n");
+    }
+}
+
+#[test]
+fn virtual_time_jumps_ahead_while_sleeping() {
+    let src = "
+        mov_lit_reg A 0x32
+        mov_lit_reg B 2
+        syscall
+    "
+    .to_string()
+        + PRINT_MONOTONIC
+        + "
+        mov_lit_reg A 0x31
+        syscall
+        mov_reg_reg B C
+        mov_lit_reg A 3
+        syscall
+        mov_reg_reg B D
+        syscall
+        exit
+    ";
+    let clock = || {
+        let mut clock = VirtualClock::new(Duration::from_nanos(1));
+        clock.set_epoch(Duration::new(1000, 500));
+        clock
+    };
+
+    for output in run(&src, clock) {
+        assert_eq!(output, "2000004\n1000\n2000509\n");
+    }
+}
+
+#[test]
+fn virtual_time_stands_still_while_blocked() {
+    let src = "
+        mov_lit_reg D 0
+    loop:
+        mov_lit_reg A 0x12
+        mov_lit_reg B 0
+        syscall
+        math_inc_reg D
+        compare_reg_lit D 300
+        jump_not_equal loop
+    "
+    .to_string()
+        + PRINT_MONOTONIC
+        + "exit";
+
+    for dispatch in dispatches() {
+        let channel = Channel::unbounded();
+        let (mut vm, output) = load(assemble(&src));
+        vm.set_dispatch(dispatch);
+        vm.set_clock(VirtualClock::default());
+        vm.attach_channel(channel.clone());
+
+        let sender = thread::spawn(move || {
+            for batch in 0..10 {
+                thread::sleep(Duration::from_millis(2));
+                for word in 0..30 {
+                    channel.send(Message::Word(batch * 30 + word)).unwrap();
+                }
+            }
+        });
+        vm.run_program().unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(output.text(), "1802\n", "{:?}", dispatch);
+    }
+}
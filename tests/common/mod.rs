@@ -0,0 +1,257 @@
+// Helpers shared by the integration tests. Each test crate uses some of
+// them only.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use rsvm::VM;
+
+/// The opcodes by the names `OP_CODES` gives their handlers, along with
+/// their operands: a register, a literal or a byte.
+const OPCODES: &[(&str, u8, &str)] = &[
+    ("exit", 0x00, ""),
+    ("push_lit", 0x01, "l"),
+    ("push_reg", 0x02, "r"),
+    ("pop_reg", 0x03, "r"),
+    ("pop_heap", 0x04, "l"),
+    ("stack_dupe", 0x05, ""),
+    ("mov_lit_reg", 0x06, "rl"),
+    ("mov_lit_heap", 0x07, "ll"),
+    ("mov_heap_reg", 0x08, "rl"),
+    ("mov_reg_heap", 0x09, "lr"),
+    ("mov_reg_reg", 0x0A, "rr"),
+    ("mov_heap_heap", 0x0B, "ll"),
+    ("push_heap", 0x0C, "l"),
+    ("mov_ptr_reg", 0x0D, "rr"),
+    ("mov_reg_ptr", 0x0E, "rr"),
+    ("exit_reg", 0x0F, "r"),
+    ("math_add_reg", 0x10, "rr"),
+    ("math_add_stack", 0x11, ""),
+    ("math_sub_reg", 0x12, "rr"),
+    ("math_sub_stack", 0x13, ""),
+    ("math_mul_reg", 0x14, "rr"),
+    ("math_mul_stack", 0x15, ""),
+    ("math_div_reg", 0x16, "rr"),
+    ("math_div_stack", 0x17, ""),
+    ("math_not_reg", 0x18, "r"),
+    ("math_not_stack", 0x19, ""),
+    ("math_and_reg", 0x1A, "rr"),
+    ("math_and_stack", 0x1B, ""),
+    ("math_or_reg", 0x1C, "rr"),
+    ("math_or_stack", 0x1D, ""),
+    ("math_xor_reg", 0x1E, "rr"),
+    ("math_xor_stack", 0x1F, ""),
+    ("jump_absolute", 0x20, "l"),
+    ("call", 0x21, "l"),
+    ("ret", 0x22, ""),
+    ("nop", 0x2F, ""),
+    ("compare_reg_reg", 0x30, "rr"),
+    ("compare_reg_lit", 0x31, "rl"),
+    ("compare_stack_lit", 0x32, "l"),
+    ("jump_equal", 0x33, "l"),
+    ("jump_not_equal", 0x34, "l"),
+    ("jump_greater", 0x35, "l"),
+    ("jump_smaller", 0x36, "l"),
+    ("jump_overflow", 0x37, "l"),
+    ("flag_reset", 0x40, ""),
+    ("math_inc_reg", 0x50, "r"),
+    ("math_dec_reg", 0x51, "r"),
+    ("math_inc_stack", 0x52, ""),
+    ("math_dec_stack", 0x53, ""),
+    ("math_add_reg_num", 0x70, "rl"),
+    ("math_add_stack_num", 0x71, "b"),
+    ("math_sub_reg_num", 0x72, "rl"),
+    ("math_sub_stack_num", 0x73, "b"),
+    ("math_mul_reg_num", 0x74, "rl"),
+    ("math_mul_stack_num", 0x75, "b"),
+    ("math_div_reg_num", 0x76, "rl"),
+    ("math_div_stack_num", 0x77, "b"),
+    ("math_and_reg_num", 0x78, "rl"),
+    ("math_and_stack_num", 0x79, "b"),
+    ("math_or_reg_num", 0x7A, "rl"),
+    ("math_or_stack_num", 0x7B, "b"),
+    ("math_xor_reg_num", 0x7C, "rl"),
+    ("math_xor_stack_num", 0x7D, "b"),
+    ("int_enable", 0x80, ""),
+    ("int_disable", 0x81, ""),
+    ("int_return", 0x82, ""),
+    ("int_table_lit", 0x83, "l"),
+    ("timer_set_lit", 0x84, "l"),
+    ("thread_spawn", 0x90, "rl"),
+    ("thread_yield", 0x91, ""),
+    ("thread_join", 0x92, "r"),
+    ("thread_self", 0x93, "r"),
+    ("syscall", 0xFF, ""),
+];
+
+/// Every mnemonic along with its operands.
+pub fn mnemonics() -> impl Iterator<Item = (&'static str, &'static str)> {
+    OPCODES.iter().map(|&(name, _, operands)| (name, operands))
+}
+
+/// Assembles one instruction per line, with `label:` lines naming the
+/// offset of the next one and `;` starting a comment. Literals are numbers
+/// or labels, and registers are `A` to `D`.
+pub fn assemble(src: &str) -> Vec<u8> {
+    assemble_with_data(&[], src)
+}
+
+/// Assembles `src` after a header carrying `data`.
+pub fn assemble_with_data(data: &[u8], src: &str) -> Vec<u8> {
+    let lines = src
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    // The first pass only finds out where the labels are.
+    let mut labels = HashMap::new();
+    let mut code = Vec::new();
+    for pass in 0..2 {
+        code.clear();
+
+        for line in &lines {
+            if let Some(label) = line.strip_suffix(':') {
+                labels.insert(label.to_string(), code.len() as u32);
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap();
+            let &(_, opcode, operands) = OPCODES
+                .iter()
+                .find(|&&(op, _, _)| op == name)
+                .unwrap_or_else(|| panic!("unknown instruction {}", name));
+
+            code.push(opcode);
+            for kind in operands.chars() {
+                let word = words
+                    .next()
+                    .unwrap_or_else(|| panic!("missing operand in {}", line));
+
+                match kind {
+                    'r' => code.push(register(word)),
+                    'b' => code.push(number(word).unwrap() as u8),
+                    _ => {
+                        let lit = match number(word) {
+                            Some(lit) => lit,
+                            None if pass == 0 => 0,
+                            None => *labels
+                                .get(word)
+                                .unwrap_or_else(|| panic!("unknown label {}", word)),
+                        };
+                        code.extend_from_slice(&lit.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut bytecode = data.to_vec();
+    bytecode.extend_from_slice(&[0x1d; 4]);
+    bytecode.extend_from_slice(&code);
+    bytecode
+}
+
+fn register(word: &str) -> u8 {
+    match word {
+        "A" => 0,
+        "B" => 1,
+        "C" => 2,
+        "D" => 3,
+        _ => number(word).unwrap() as u8,
+    }
+}
+
+fn number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+/// Collects what a guest writes, to look at after it ran.
+#[derive(Clone, Default)]
+pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.contents()).unwrap()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A `VM` with `bytecode` loaded, writing to the returned buffer.
+pub fn load(bytecode: Vec<u8>) -> (VM, Captured) {
+    let output = Captured::default();
+
+    let mut vm = VM::new();
+    vm.load_program(bytecode);
+    vm.set_output(output.clone());
+
+    (vm, output)
+}
+
+/// The program counter, registers, flags, stack and heap of a `VM`.
+pub type State = (usize, [usize; 4], u32, Vec<u32>, Vec<u32>);
+
+pub fn state(vm: &VM) -> State {
+    (
+        vm.prgrm_cntr(),
+        *vm.registers(),
+        vm.flags().bits(),
+        vm.stack().as_slice().to_vec(),
+        vm.heap().as_slice().to_vec(),
+    )
+}
+
+/// Instructions storing `bytes` on the heap from `addr` on, four to a word
+/// the way the syscalls expect strings.
+pub fn store_bytes(addr: u32, bytes: &[u8]) -> String {
+    bytes
+        .chunks(4)
+        .zip(addr..)
+        .map(|(chunk, addr)| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+
+            format!("mov_lit_heap {} {}\n", addr, u32::from_be_bytes(word))
+        })
+        .collect()
+}
+
+/// Writes `bytecode` to a file of its own for a binary to run.
+pub fn write_program(name: &str, bytecode: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("rsvm-{}-{}.bin", name, std::process::id()));
+    std::fs::write(&path, bytecode).unwrap();
+
+    path
+}
+
+/// Every way the `VM` has of running instructions.
+pub fn dispatches() -> Vec<rsvm::decode::Dispatch> {
+    use rsvm::decode::Dispatch;
+
+    vec![
+        Dispatch::Bytecode,
+        Dispatch::Decoded,
+        #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+        Dispatch::Compiled,
+    ]
+}
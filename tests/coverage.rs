@@ -0,0 +1,129 @@
+mod common;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::debuginfo::DebugInfoBuilder;
+
+use common::{assemble, load};
+
+#[test]
+fn lcov_records_lines_and_branches_per_file() {
+    let mut bytecode = assemble(
+        "
+            call work
+            exit
+        work:
+            mov_lit_reg D 3
+        loop:
+            math_dec_reg D
+            compare_reg_lit D 0
+            jump_not_equal loop
+            ret
+            jump_equal loop
+            exit
+        ",
+    );
+    DebugInfoBuilder::new()
+        .line(0, "main.s", 1, 1)
+        .line(5, "main.s", 2, 1)
+        .line(6, "lib.s", 4, 1)
+        .line(12, "lib.s", 5, 1)
+        .line(14, "lib.s", 6, 1)
+        .line(20, "lib.s", 7, 1)
+        .line(25, "lib.s", 8, 1)
+        .line(26, "lib.s", 10, 1)
+        .line(31, "lib.s", 11, 1)
+        .build()
+        .append_to(&mut bytecode);
+
+    let (mut vm, _) = load(bytecode);
+    vm.enable_coverage();
+    vm.run_program().unwrap();
+
+    let mut lcov = Vec::new();
+    vm.coverage()
+        .unwrap()
+        .write_lcov(&mut lcov, vm.debug_info())
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(lcov).unwrap(),
+        "TN:\n\
+         SF:lib.s\n\
+         BRDA:7,20,0,2\n\
+         BRDA:7,20,1,1\n\
+         BRDA:10,26,0,-\n\
+         BRDA:10,26,1,-\n\
+         BRF:4\n\
+         BRH:2\n\
+         DA:4,1\n\
+         DA:5,3\n\
+         DA:6,3\n\
+         DA:7,3\n\
+         DA:8,1\n\
+         DA:10,0\n\
+         DA:11,0\n\
+         LF:7\n\
+         LH:5\n\
+         end_of_record\n\
+         TN:\n\
+         SF:main.s\n\
+         BRF:0\n\
+         BRH:0\n\
+         DA:1,1\n\
+         DA:2,1\n\
+         LF:2\n\
+         LH:2\n\
+         end_of_record\n"
+    );
+
+    let mut summary = Vec::new();
+    vm.coverage()
+        .unwrap()
+        .write_summary(&mut summary, vm.debug_info())
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "7 instructions hit\n7 of 9 lines hit\n2 of 4 branches hit\n"
+    );
+}
+
+#[test]
+fn lcov_needs_debug_info() {
+    let (mut vm, _) = load(assemble("exit"));
+    vm.enable_coverage();
+    vm.run_program().unwrap();
+
+    let mut lcov = Vec::new();
+    vm.coverage().unwrap().write_lcov(&mut lcov, None).unwrap();
+
+    assert!(lcov.is_empty());
+    assert_eq!(vm.coverage().unwrap().instruction_count(0), 1);
+}
+
+#[test]
+fn blocked_instructions_are_hit_once() {
+    let (mut vm, _) = load(assemble(
+        "
+            mov_lit_reg A 0x12
+            mov_lit_reg B 0
+            syscall
+            exit
+        ",
+    ));
+    let channel = Channel::unbounded();
+    vm.attach_channel(channel.clone());
+    vm.enable_coverage();
+
+    for _ in 0..10 {
+        vm.step();
+    }
+    assert!(vm.is_blocked());
+
+    channel.send(Message::Word(1)).unwrap();
+    vm.run_program().unwrap();
+
+    let coverage = vm.coverage().unwrap();
+    assert_eq!(coverage.instruction_count(12), 1);
+    assert_eq!(coverage.instruction_count(13), 1);
+}
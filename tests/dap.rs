@@ -0,0 +1,191 @@
+mod common;
+
+use std::io::{BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rsvm::dap::DapServer;
+
+use common::{assemble, write_program, Captured};
+
+/// An editor talking to a server on a thread of its own.
+struct Client {
+    stream: UnixStream,
+    output: Captured,
+    server: JoinHandle<std::io::Result<()>>,
+    seq: u64,
+}
+
+impl Client {
+    fn start() -> Client {
+        let (stream, server_end) = UnixStream::pair().unwrap();
+        let output = Captured::default();
+
+        let out = output.clone();
+        let server = thread::spawn(move || DapServer::new(BufReader::new(server_end), out).serve());
+
+        Client {
+            stream,
+            output,
+            server,
+            seq: 0,
+        }
+    }
+
+    fn request(&mut self, command: &str, arguments: &str) {
+        self.seq += 1;
+        let body = format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            self.seq, command, arguments
+        );
+
+        write!(
+            self.stream,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    }
+
+    /// Waits for the server to have sent `needle` `count` times all told.
+    fn wait_for(&self, needle: &str, count: usize) -> String {
+        let start = Instant::now();
+
+        loop {
+            let text = self.output.text();
+            if text.matches(needle).count() >= count {
+                return text;
+            }
+
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "no {} in {}",
+                needle,
+                text
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn disconnect(mut self) {
+        self.request("disconnect", "{}");
+        self.server.join().unwrap().unwrap();
+    }
+}
+
+fn launch(client: &mut Client, name: &str, src: &str, stop_on_entry: bool) {
+    let program = write_program(name, &assemble(src));
+    let arguments = format!(
+        r#"{{"program":"{}","stopOnEntry":{}}}"#,
+        program.display(),
+        stop_on_entry
+    );
+
+    client.request("initialize", "{}");
+    client.request("launch", &arguments);
+    client.request("configurationDone", "{}");
+}
+
+#[test]
+fn guests_read_stdin_as_empty() {
+    let mut client = Client::start();
+    launch(
+        &mut client,
+        "dap-stdin",
+        "
+            mov_lit_reg A 1
+            mov_lit_reg B 100
+            mov_lit_reg C 16
+            mov_lit_reg D 99
+            syscall
+            mov_reg_reg B D
+            mov_lit_reg A 3
+            syscall
+            exit
+        ",
+        false,
+    );
+
+    let text = client.wait_for(r#""event":"exited""#, 1);
+    assert!(text.contains(r#""output":"0\n""#), "{}", text);
+    assert!(text.contains(r#""exitCode":0"#), "{}", text);
+
+    client.disconnect();
+}
+
+#[test]
+fn step_out_runs_until_the_function_returns() {
+    let mut client = Client::start();
+    launch(
+        &mut client,
+        "dap-step-out",
+        "
+            call function
+            exit
+        function:
+            nop
+            nop
+            ret
+        ",
+        true,
+    );
+    client.wait_for(r#""reason":"entry""#, 1);
+
+    client.request("stepIn", r#"{"threadId":1}"#);
+    client.wait_for(r#""reason":"step""#, 1);
+    client.request("stepOut", r#"{"threadId":1}"#);
+    client.wait_for(r#""reason":"step""#, 2);
+
+    client.request("stackTrace", r#"{"threadId":1}"#);
+    let text = client.wait_for(r#""totalFrames""#, 1);
+    assert!(text.contains(r#""totalFrames":1"#), "{}", text);
+    assert!(
+        text.contains(r#""instructionPointerReference":"0x5""#),
+        "{}",
+        text
+    );
+
+    // Outside of any function there's nothing to run to the end of.
+    client.request("stepOut", r#"{"threadId":1}"#);
+    client.wait_for(r#""event":"exited""#, 1);
+
+    client.disconnect();
+}
+
+#[test]
+fn next_steps_over_calls() {
+    let mut client = Client::start();
+    launch(
+        &mut client,
+        "dap-next",
+        "
+            call function
+            exit
+        function:
+            nop
+            nop
+            ret
+        ",
+        true,
+    );
+    client.wait_for(r#""reason":"entry""#, 1);
+
+    client.request("next", r#"{"threadId":1}"#);
+    client.wait_for(r#""reason":"step""#, 1);
+
+    client.request("stackTrace", r#"{"threadId":1}"#);
+    let text = client.wait_for(r#""totalFrames""#, 1);
+    assert!(text.contains(r#""totalFrames":1"#), "{}", text);
+    assert!(
+        text.contains(r#""instructionPointerReference":"0x5""#),
+        "{}",
+        text
+    );
+
+    client.request("next", r#"{"threadId":1}"#);
+    client.wait_for(r#""event":"exited""#, 1);
+
+    client.disconnect();
+}
@@ -0,0 +1,347 @@
+mod common;
+
+use std::time::Duration;
+
+use rsvm::clock::VirtualClock;
+use rsvm::debugger::{Debugger, StopReason};
+
+use common::{assemble, load, state};
+
+fn debugger(src: &str) -> Debugger {
+    let (vm, _) = load(assemble(src));
+
+    Debugger::new(vm)
+}
+
+const COUNTDOWN: &str = "
+        mov_lit_reg D 3
+    loop:
+        push_reg D
+        mov_reg_heap 200 D
+        math_dec_reg D
+        compare_reg_lit D 0
+        jump_not_equal loop
+        pop_reg A
+        exit_reg D
+";
+
+#[test]
+fn stepping_back_goes_through_the_same_states() {
+    let mut debugger = debugger(COUNTDOWN);
+    let mut states = vec![state(debugger.vm())];
+
+    while debugger.step() == StopReason::Step {
+        states.push(state(debugger.vm()));
+    }
+
+    while let Some(expected) = states.pop() {
+        assert_eq!(debugger.step_back(), StopReason::Step);
+        assert_eq!(state(debugger.vm()), expected);
+    }
+
+    assert_eq!(debugger.step_back(), StopReason::StartOfHistory);
+    assert_eq!(debugger.history_len(), 0);
+}
+
+#[test]
+fn stepping_back_over_exit_resumes_the_program() {
+    let mut debugger = debugger(COUNTDOWN);
+
+    assert!(matches!(debugger.resume(), StopReason::Exited(_)));
+    assert_eq!(debugger.step_back(), StopReason::Step);
+    assert!(!debugger.vm().is_stopped());
+    assert!(matches!(debugger.resume(), StopReason::Exited(_)));
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.set_history_limit(4);
+
+    debugger.resume();
+    assert_eq!(debugger.history_len(), 4);
+
+    for _ in 0..4 {
+        assert_eq!(debugger.step_back(), StopReason::Step);
+    }
+    assert_eq!(debugger.step_back(), StopReason::StartOfHistory);
+}
+
+#[test]
+fn reverse_continue_stops_at_breakpoints() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.step();
+
+    let start = debugger.vm().prgrm_cntr();
+    debugger.resume();
+    debugger.set_breakpoint(start);
+
+    // The last time around the loop, then the two before it.
+    for d in 1..=3 {
+        assert_eq!(debugger.reverse_continue(), StopReason::Breakpoint(start));
+        assert_eq!(debugger.vm().registers()[3], d);
+    }
+    assert_eq!(debugger.reverse_continue(), StopReason::StartOfHistory);
+}
+
+#[test]
+fn reverse_continue_stops_where_watched_words_change() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.resume();
+    debugger.set_watchpoint(200);
+
+    let reason = debugger.reverse_continue();
+    assert_eq!(
+        reason,
+        StopReason::Watchpoint {
+            addr: 200,
+            old: 2,
+            new: 1
+        }
+    );
+    assert_eq!(debugger.vm().heap().read(200), 2);
+    assert_eq!(
+        debugger.reverse_continue(),
+        StopReason::Watchpoint {
+            addr: 200,
+            old: 3,
+            new: 2
+        }
+    );
+    assert_eq!(
+        debugger.reverse_continue(),
+        StopReason::Watchpoint {
+            addr: 200,
+            old: 0,
+            new: 3
+        }
+    );
+    assert_eq!(debugger.reverse_continue(), StopReason::StartOfHistory);
+}
+
+#[test]
+fn stepping_back_takes_back_timer_interrupts() {
+    // Interrupts stay disabled, so the timer's is left pending.
+    let mut debugger = debugger(
+        "
+            timer_set_lit 2
+        loop:
+            jump_absolute loop
+        ",
+    );
+    let interrupts = debugger.vm().interrupt_handle();
+
+    while interrupts.pending() == 0 {
+        debugger.step();
+    }
+
+    assert_eq!(debugger.step_back(), StopReason::Step);
+    assert_eq!(interrupts.pending(), 0);
+    debugger.step();
+    assert_eq!(interrupts.pending(), 1);
+}
+
+#[test]
+fn stepping_back_raises_serviced_interrupts_again() {
+    let mut debugger = debugger(
+        "
+            int_table_lit 100
+            mov_lit_heap 105 handler
+            int_enable
+        loop:
+            jump_absolute loop
+        handler:
+            exit
+        ",
+    );
+    let interrupts = debugger.vm().interrupt_handle();
+
+    for _ in 0..3 {
+        debugger.step();
+    }
+    interrupts.raise(5).unwrap();
+    debugger.step();
+    assert_eq!(interrupts.pending(), 0);
+
+    assert_eq!(debugger.step_back(), StopReason::Step);
+    assert_eq!(interrupts.pending(), 1 << 5);
+    assert!(matches!(debugger.resume(), StopReason::Exited(_)));
+}
+
+#[test]
+fn stepping_back_rewinds_a_virtual_clock() {
+    let (mut vm, _) = load(assemble(
+        "
+            nop
+            nop
+            mov_lit_reg A 0x30
+            syscall
+            exit_reg C
+        ",
+    ));
+    vm.set_clock(VirtualClock::new(Duration::from_nanos(10)));
+    let mut debugger = Debugger::new(vm);
+
+    for _ in 0..4 {
+        debugger.step();
+    }
+    let now = debugger.vm().registers()[2];
+
+    for _ in 0..4 {
+        debugger.step_back();
+    }
+    for _ in 0..4 {
+        debugger.step();
+    }
+
+    assert_eq!(now, 30);
+    assert_eq!(debugger.vm().registers()[2], now);
+}
+
+#[test]
+fn stepping_out_returns_to_the_caller() {
+    let mut debugger = debugger(
+        "
+            call outer
+            exit
+        outer:
+            call inner
+            ret
+        inner:
+            nop
+            ret
+        ",
+    );
+
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.vm().prgrm_cntr(), 12);
+
+    assert_eq!(debugger.step_out(), StopReason::Step);
+    assert_eq!(debugger.vm().prgrm_cntr(), 11);
+    assert_eq!(debugger.step_out(), StopReason::Step);
+    assert_eq!(debugger.vm().prgrm_cntr(), 5);
+    assert!(debugger.caller().is_none());
+    assert!(matches!(debugger.step_out(), StopReason::Exited(_)));
+}
+
+#[test]
+fn stepping_over_a_call_runs_the_whole_function() {
+    let mut debugger = debugger(
+        "
+            call outer
+            exit
+        outer:
+            call inner
+            ret
+        inner:
+            nop
+            ret
+        ",
+    );
+
+    assert!(debugger.call_site().is_some());
+    assert_eq!(debugger.step_over(), StopReason::Step);
+    assert_eq!(debugger.vm().prgrm_cntr(), 5);
+    assert!(debugger.caller().is_none());
+
+    // Anything else is stepped over the same as it is stepped into.
+    assert!(debugger.call_site().is_none());
+    assert!(matches!(debugger.step_over(), StopReason::Exited(_)));
+}
+
+#[test]
+fn stepping_over_a_call_stops_at_breakpoints() {
+    let mut debugger = debugger(
+        "
+            call outer
+            exit
+        outer:
+            call inner
+            ret
+        inner:
+            nop
+            ret
+        ",
+    );
+    debugger.set_breakpoint(12);
+
+    assert_eq!(debugger.step_over(), StopReason::Breakpoint(12));
+    assert_eq!(debugger.step_over(), StopReason::Step);
+    assert_eq!(debugger.vm().prgrm_cntr(), 13);
+}
+
+#[test]
+fn stepping_out_stops_at_breakpoints() {
+    let mut debugger = debugger(
+        "
+            call outer
+            exit
+        outer:
+            call inner
+            ret
+        inner:
+            nop
+            ret
+        ",
+    );
+    debugger.step();
+    debugger.set_breakpoint(12);
+
+    assert_eq!(debugger.step_out(), StopReason::Breakpoint(12));
+    assert_eq!(debugger.step_out(), StopReason::Step);
+    assert_eq!(debugger.vm().prgrm_cntr(), 11);
+}
+
+#[test]
+fn stepping_back_shrinks_the_heap_back() {
+    let mut debugger = debugger("mov_lit_heap 0x10000 1\nexit");
+    let capacity = debugger.vm().heap.capacity();
+
+    debugger.step();
+    assert!(debugger.vm().heap.capacity() > capacity);
+
+    debugger.step_back();
+    assert_eq!(debugger.vm().heap.capacity(), capacity);
+    assert_eq!(debugger.vm().heap.read(0x10000), 0);
+}
+
+#[test]
+fn inputs_stepped_back_over_are_taken_again() {
+    let src = "
+        mov_lit_reg A 1
+        mov_lit_reg B 100
+        mov_lit_reg C 1
+        syscall
+        mov_lit_reg A 0x40
+        syscall
+        mov_lit_reg A 1
+        mov_lit_reg B 101
+        mov_lit_reg C 1
+        syscall
+        mov_lit_reg A 0x40
+        syscall
+        exit
+    ";
+    let run = |rewind: bool| {
+        let (mut vm, _) = load(assemble(src));
+        vm.set_input(&b"ab"[..]);
+        vm.seed_rng(7);
+        let mut debugger = Debugger::new(vm);
+
+        let mut states = Vec::new();
+        while debugger.step() == StopReason::Step {
+            if rewind {
+                debugger.step_back();
+                debugger.step();
+            }
+            states.push(state(debugger.vm()));
+        }
+        states
+    };
+
+    let states = run(true);
+
+    assert_eq!(states, run(false));
+    assert_eq!(states.last().unwrap().4[100..102], [0x61000000, 0x62000000]);
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use rsvm::debuginfo::{DebugInfo, DebugInfoBuilder, Location, SourceLocation};
+
+use common::{assemble, load};
+
+fn source(file: &str, line: u32, column: u32) -> Option<SourceLocation> {
+    Some(SourceLocation {
+        file: file.to_string(),
+        line,
+        column,
+    })
+}
+
+fn sample() -> DebugInfo {
+    // Added out of order on purpose.
+    DebugInfoBuilder::new()
+        .line(12, "lib.s", 1, 1)
+        .line(0, "main.s", 1, 1)
+        .line(6, "main.s", 4, 3)
+        .line(5, "main.s", 2, 1)
+        .line(11, "main.s", 5, 3)
+        .line(13, "lib.s", 2, 1)
+        .label(12, "inner")
+        .label(0, "main")
+        .label(6, "outer")
+        .label(11, "tail")
+        .build()
+}
+
+#[test]
+fn debug_info_survives_being_appended_and_split_off() {
+    let info = sample();
+    let mut bytecode = vec![1, 2, 3];
+    info.append_to(&mut bytecode);
+
+    assert_eq!(DebugInfo::split_off(&mut bytecode), Some(info));
+    assert_eq!(bytecode, [1, 2, 3]);
+    assert_eq!(DebugInfo::split_off(&mut bytecode), None);
+}
+
+#[test]
+fn corrupt_debug_info_is_left_alone() {
+    let mut bytecode = vec![1, 2, 3];
+    sample().append_to(&mut bytecode);
+    bytecode[3] ^= 0xFF;
+    let corrupt = bytecode.clone();
+
+    assert_eq!(DebugInfo::split_off(&mut bytecode), None);
+    assert_eq!(bytecode, corrupt);
+
+    let mut short = b"RSVMDBG1".to_vec();
+    assert_eq!(DebugInfo::split_off(&mut short), None);
+}
+
+#[test]
+fn offsets_are_located_by_the_closest_entries_before_them() {
+    let info = sample();
+
+    assert_eq!(
+        info.locate(0),
+        Some(Location {
+            symbol: Some(("main".to_string(), 0)),
+            source: source("main.s", 1, 1),
+        })
+    );
+    assert_eq!(
+        info.locate(9),
+        Some(Location {
+            symbol: Some(("outer".to_string(), 3)),
+            source: source("main.s", 4, 3),
+        })
+    );
+    assert_eq!(
+        info.locate(100).unwrap().to_string(),
+        "inner+0x58 at lib.s:2:1"
+    );
+    assert_eq!(info.locate(12).unwrap().to_string(), "inner at lib.s:1:1");
+
+    let lines_only = DebugInfoBuilder::new().line(4, "a.s", 7, 2).build();
+    assert_eq!(lines_only.locate(3), None);
+    assert_eq!(lines_only.locate(4).unwrap().to_string(), "a.s:7:2");
+}
+
+#[test]
+fn labels_and_lines_are_looked_up_by_name() {
+    let info = sample();
+
+    assert_eq!(info.label_offset("tail"), Some(11));
+    assert_eq!(info.label_offset("missing"), None);
+    assert_eq!(info.line_offset("main.s", 4), Some(6));
+    assert_eq!(info.line_offset("lib.s", 4), None);
+    assert_eq!(info.files(), ["lib.s", "main.s"]);
+    assert_eq!(info.lines().next(), Some((0, "main.s", 1)));
+}
+
+#[test]
+fn backtraces_locate_callers_by_their_calls() {
+    let mut bytecode = assemble(
+        "
+            call outer
+            exit
+        outer:
+            call inner
+            ret
+        inner:
+            nop
+            ret
+        ",
+    );
+    sample().append_to(&mut bytecode);
+
+    let (mut vm, _) = load(bytecode);
+    while vm.prgrm_cntr() != 12 {
+        vm.step();
+    }
+
+    // Both return addresses land on the first instruction after a label
+    // of their own, but the frames belong to the functions making the calls.
+    let frames = vm
+        .backtrace(vm.prgrm_cntr())
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [
+            "0x000c in inner at lib.s:1:1",
+            "0x000b in outer+0x5 at main.s:4:3",
+            "0x0005 in main+0x5 at main.s:1:1",
+        ]
+    );
+}
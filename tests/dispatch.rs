@@ -0,0 +1,146 @@
+mod common;
+
+use rsvm::decode::Dispatch;
+use rsvm::VM;
+
+use common::{assemble, load, mnemonics, state, State};
+
+/// Leaves every register, the flags, the stack and the heap holding
+/// something for the instruction under test to work on. D is zero so that
+/// dividing by it faults.
+const PRELUDE: &str = "
+        mov_lit_reg A 5
+        mov_lit_reg B 0xFFFFFFFF
+        mov_lit_reg C 200
+        mov_lit_reg D 0
+        mov_lit_heap 200 0x80000000
+        mov_lit_heap 201 201
+        push_lit 0xFFFFFFFE
+        push_lit 9
+        push_lit 200
+        compare_reg_reg A B
+";
+
+/// Where the instruction under test goes on to.
+const EPILOGUE: &str = "
+        math_inc_reg A
+        exit_reg A
+    target:
+        mov_lit_reg A 42
+        exit_reg A
+";
+
+/// Operands for `name` covering the edge cases: registers against
+/// themselves and others, literals that overflow, addresses past the end of
+/// the program and heap words that are there or not.
+fn variants(name: &str, operands: &str) -> &'static [&'static str] {
+    let jumps = name.starts_with("jump") || name == "call";
+
+    match operands {
+        "" => &[""],
+        "r" => &["A", "B", "D"],
+        // Heap addresses that far out would grow the heap to gigabytes.
+        "rr" if name.contains("ptr") => &["C A", "A C", "C C"],
+        "rr" => &["A B", "B B", "C A", "A D"],
+        "rl" if name == "thread_spawn" => &["A target", "B 0x10000"],
+        "rl" => &["A 0", "B 0xFFFFFFFF", "C 201", "D 3"],
+        "lr" => &["200 A", "0x10000 B"],
+        "l" if jumps => &["target", "0x10000"],
+        "l" => &["200", "0", "0x10000"],
+        "ll" if name == "mov_heap_heap" => &["200 201", "201 0x10000", "0x10000 5"],
+        "ll" => &["200 201", "201 0xFFFFFFFF", "0x10000 5"],
+        "b" => &["0", "255", "3"],
+        operands => panic!("unknown operands {}", operands),
+    }
+}
+
+fn instances(name: &str, operands: &str) -> Vec<String> {
+    variants(name, operands)
+        .iter()
+        .map(|operands| format!("{}\n{} {}\n{}", PRELUDE, name, operands, EPILOGUE))
+        .collect()
+}
+
+fn run(src: &str, dispatch: Dispatch) -> (Vec<State>, Option<String>, String) {
+    let (mut vm, output) = load(assemble(src));
+    vm.set_dispatch(dispatch);
+
+    let mut states = vec![state(&vm)];
+    for _ in 0..64 {
+        if vm.is_stopped() {
+            break;
+        }
+
+        vm.step();
+        states.push(state(&vm));
+    }
+
+    let status = vm.exit_status().map(|status| format!("{:?}", status));
+    (states, status, output.text())
+}
+
+fn assert_same(src: &str) {
+    let bytecode = run(src, Dispatch::Bytecode);
+    let decoded = run(src, Dispatch::Decoded);
+
+    assert_eq!(bytecode.0.len(), decoded.0.len(), "{}", src);
+    for (step, (bytecode, decoded)) in bytecode.0.iter().zip(&decoded.0).enumerate() {
+        assert_eq!(bytecode, decoded, "at step {} of {}", step, src);
+    }
+    assert_eq!(bytecode.1, decoded.1, "{}", src);
+    assert_eq!(bytecode.2, decoded.2, "{}", src);
+}
+
+#[test]
+fn every_opcode_decodes_to_what_it_does_in_bytecode() {
+    for (name, operands) in mnemonics() {
+        for src in instances(name, operands) {
+            assert_same(&src);
+        }
+    }
+}
+
+#[test]
+fn instructions_cut_off_by_the_end_fault_the_same() {
+    for (name, operands) in mnemonics().filter(|(_, operands)| !operands.is_empty()) {
+        let mut bytecode = assemble(&format!(
+            "target:\n{} {}",
+            name,
+            variants(name, operands)[0]
+        ));
+        bytecode.truncate(bytecode.len() - 1);
+
+        let statuses = [Dispatch::Bytecode, Dispatch::Decoded].map(|dispatch| {
+            let mut vm = VM::new();
+            vm.load_program(bytecode.clone());
+            vm.set_dispatch(dispatch);
+
+            format!("{:?}", vm.run_program())
+        });
+
+        assert_eq!(statuses[0], statuses[1], "{}", name);
+    }
+}
+
+#[test]
+fn interrupts_and_syscalls_agree() {
+    assert_same(
+        "
+            int_table_lit 300
+            mov_lit_heap 300 handler
+            timer_set_lit 2
+            int_enable
+            mov_lit_reg D 0
+        loop:
+            compare_reg_lit D 3
+            jump_not_equal loop
+            mov_lit_reg A 3
+            mov_reg_reg B D
+            syscall
+            exit
+        handler:
+            math_inc_reg D
+            int_return
+        ",
+    );
+}
@@ -0,0 +1,342 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rsvm::fs::MemFs;
+
+use common::{assemble, load, store_bytes};
+
+/// A directory of its own for a test to use as the filesystem root.
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Root {
+        let path = env::temp_dir().join(format!("rsvm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Root(path)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Opens the path at heap address 100 and exits with the status.
+fn open(path_len: &str) -> String {
+    format!(
+        "
+        mov_lit_reg A 0x20
+        mov_lit_reg B 100
+        {}
+        mov_lit_reg D 1
+        syscall
+        exit_reg A
+    ",
+        path_len
+    )
+}
+
+fn status(src: &str, root: Option<&Root>) -> u32 {
+    let (mut vm, _) = load(assemble(src));
+    if let Some(root) = root {
+        vm.set_fs_root(&root.0).unwrap();
+    }
+
+    vm.run_program().unwrap().code()
+}
+
+#[test]
+fn paths_are_refused_without_a_filesystem() {
+    let huge = "mov_lit_reg C 0\nmath_not_reg C";
+
+    assert_eq!(status(&open(huge), None), 7);
+    assert_eq!(status(&open(huge).replace("0x20", "0x25"), None), 7);
+    assert_eq!(status(&open("mov_lit_reg C 4"), None), 7);
+}
+
+#[test]
+fn overlong_paths_are_too_long() {
+    let root = Root::new("overlong");
+
+    assert_eq!(status(&open("mov_lit_reg C 4097"), Some(&root)), 4);
+    assert_eq!(
+        status(&open("mov_lit_reg C 0\nmath_not_reg C"), Some(&root)),
+        4
+    );
+    assert_eq!(status(&open("mov_lit_reg C 4096"), Some(&root)), 10);
+}
+
+#[test]
+fn stdout_writes_past_the_io_limit_are_too_long() {
+    let write = |len: &str| {
+        format!(
+            "
+            {}
+            mov_lit_reg A 0
+            syscall
+            exit_reg A
+        ",
+            len
+        )
+    };
+
+    let (mut vm, output) = load(assemble(&write(
+        "mov_lit_reg C 0xFFFFFFFF\nmath_mul_reg C C",
+    )));
+    assert_eq!(vm.run_program().unwrap().code(), 4);
+    assert!(output.contents().is_empty());
+
+    assert_eq!(status(&write("mov_lit_reg C 0x100001"), None), 4);
+    assert_eq!(status(&write("mov_lit_reg C 0x100000"), None), 0);
+}
+
+#[test]
+fn paths_may_not_leave_the_root() {
+    let root = Root::new("escape");
+    let src = store_bytes(100, b"../outside") + &open("mov_lit_reg C 10");
+
+    assert_eq!(status(&src, Some(&root)), 7);
+}
+
+#[test]
+fn files_round_trip_through_the_root() {
+    let root = Root::new("round-trip");
+    let src = store_bytes(100, b"note.txt")
+        + &store_bytes(200, b"hello")
+        + "
+        mov_lit_reg A 0x20
+        mov_lit_reg B 100
+        mov_lit_reg C 8
+        mov_lit_reg D 7
+        syscall
+        push_reg B
+        mov_lit_reg A 0x22
+        mov_lit_reg C 200
+        mov_lit_reg D 5
+        syscall
+        mov_lit_reg A 0x23
+        mov_lit_reg C 1
+        mov_lit_reg D 0
+        syscall
+        pop_reg B
+        mov_lit_reg A 0x21
+        mov_lit_reg C 300
+        mov_lit_reg D 16
+        syscall
+        mov_reg_reg C D
+        mov_lit_reg A 0x24
+        syscall
+        mov_heap_reg B 300
+        mov_lit_reg A 3
+        syscall
+        exit_reg C
+    ";
+    let (mut vm, output) = load(assemble(&src));
+    vm.set_fs_root(&root.0).unwrap();
+
+    assert_eq!(vm.run_program().unwrap().code(), 4);
+    assert_eq!(output.text(), format!("{}\n", u32::from_be_bytes(*b"ello")));
+    assert_eq!(fs::read(root.0.join("note.txt")).unwrap(), b"hello");
+}
+
+/// Runs `src` against `memfs` with `path` stored at heap address 100 and
+/// `contents` at 200, returning the exit code and what got printed.
+fn run_memfs(memfs: &MemFs, path: &str, contents: &[u8], src: &str) -> (u32, String) {
+    let src = store_bytes(100, path.as_bytes()) + &store_bytes(200, contents) + src;
+    let (mut vm, output) = load(assemble(&src));
+    vm.set_filesystem(memfs.clone());
+
+    let code = vm.run_program().unwrap().code();
+    (code, output.text())
+}
+
+/// Opens the path at 100 with `flags`, leaving the file on the stack.
+fn open_with(path: &str, flags: u32) -> String {
+    format!(
+        "
+        mov_lit_reg A 0x20
+        mov_lit_reg B 100
+        mov_lit_reg C {}
+        mov_lit_reg D {}
+        syscall
+        compare_reg_lit A 0
+        jump_not_equal failed
+        push_reg B
+    ",
+        path.len(),
+        flags
+    )
+}
+
+/// Exits with the status in A.
+const FAILED: &str = "
+    failed:
+        exit_reg A
+";
+
+#[test]
+fn memfs_files_are_read_by_guests() {
+    let memfs = MemFs::new();
+    memfs.insert("data/in.txt", "hello world").unwrap();
+    let src = open_with("data/in.txt", 1)
+        + "
+        pop_reg B
+        mov_lit_reg A 0x21
+        mov_lit_reg C 300
+        mov_lit_reg D 64
+        syscall
+        mov_reg_reg C D
+        mov_lit_reg A 3
+        mov_heap_reg B 300
+        syscall
+        mov_heap_reg B 302
+        syscall
+        exit_reg C
+    " + FAILED;
+
+    let (code, output) = run_memfs(&memfs, "data/in.txt", b"", &src);
+
+    assert_eq!(code, 11);
+    assert_eq!(
+        output,
+        format!(
+            "{}\n{}\n",
+            u32::from_be_bytes(*b"hell"),
+            u32::from_be_bytes(*b"rld\0")
+        )
+    );
+}
+
+#[test]
+fn memfs_keeps_what_guests_write() {
+    let memfs = MemFs::new();
+    memfs.insert("log.txt", "old contents").unwrap();
+    let write = "
+        pop_reg B
+        mov_lit_reg A 0x22
+        mov_lit_reg C 200
+        mov_lit_reg D 5
+        syscall
+        mov_lit_reg A 0x24
+        syscall
+        exit_reg A
+    ";
+
+    let src = open_with("new.txt", 2 | 4) + write + FAILED;
+    assert_eq!(run_memfs(&memfs, "new.txt", b"hello", &src).0, 0);
+    assert_eq!(memfs.get("new.txt").unwrap(), b"hello");
+
+    let src = open_with("log.txt", 2 | 8) + write + FAILED;
+    assert_eq!(run_memfs(&memfs, "log.txt", b"fresh", &src).0, 0);
+    assert_eq!(memfs.get("log.txt").unwrap(), b"fresh");
+
+    let src = open_with("log.txt", 0x10) + write + FAILED;
+    assert_eq!(run_memfs(&memfs, "log.txt", b"!!!!!", &src).0, 0);
+    assert_eq!(memfs.get("log.txt").unwrap(), b"fresh!!!!!");
+
+    assert_eq!(
+        memfs.paths(),
+        [PathBuf::from("log.txt"), PathBuf::from("new.txt")]
+    );
+}
+
+#[test]
+fn memfs_refuses_what_it_cannot_open() {
+    let memfs = MemFs::new();
+
+    let src = open_with("missing.txt", 1) + "exit" + FAILED;
+    assert_eq!(run_memfs(&memfs, "missing.txt", b"", &src).0, 6);
+
+    let src = open_with("missing.txt", 4) + "exit" + FAILED;
+    assert_eq!(run_memfs(&memfs, "missing.txt", b"", &src).0, 10);
+
+    let src = open_with("../missing.txt", 1) + "exit" + FAILED;
+    assert_eq!(run_memfs(&memfs, "../missing.txt", b"", &src).0, 7);
+
+    assert!(memfs.paths().is_empty());
+}
+
+#[test]
+fn memfs_seeks_fill_gaps_with_zeroes() {
+    let memfs = MemFs::new();
+    memfs.insert("gap.bin", "ab").unwrap();
+    let src = open_with("gap.bin", 2)
+        + "
+        pop_reg B
+        push_reg B
+        mov_lit_reg A 0x23
+        mov_lit_reg C 2
+        mov_lit_reg D 2
+        syscall
+        pop_reg B
+        mov_lit_reg A 0x22
+        mov_lit_reg C 200
+        mov_lit_reg D 1
+        syscall
+        exit_reg A
+    " + FAILED;
+
+    assert_eq!(run_memfs(&memfs, "gap.bin", b"z", &src).0, 0);
+    assert_eq!(memfs.get("gap.bin").unwrap(), b"ab\0\0z");
+}
+
+#[test]
+fn memfs_files_do_not_grow_without_bound() {
+    let memfs = MemFs::new();
+    let src = open_with("big.bin", 2 | 4)
+        + "
+        pop_reg B
+        push_reg B
+        mov_lit_reg A 0x23
+        mov_lit_reg C 0x7fffffff
+        mov_lit_reg D 0
+        syscall
+        pop_reg B
+        mov_lit_reg A 0x22
+        mov_lit_reg C 200
+        mov_lit_reg D 1
+        syscall
+        exit_reg A
+    " + FAILED;
+
+    assert_eq!(run_memfs(&memfs, "big.bin", b"z", &src).0, 10);
+    assert_eq!(memfs.get("big.bin").unwrap(), b"");
+}
+
+fn stat(memfs: &MemFs, path: &str) -> (u32, String) {
+    let src = format!(
+        "
+        mov_lit_reg A 0x25
+        mov_lit_reg B 100
+        mov_lit_reg C {}
+        syscall
+        mov_reg_reg D A
+        mov_lit_reg A 3
+        syscall
+        mov_reg_reg B C
+        syscall
+        exit_reg D
+    ",
+        path.len()
+    );
+
+    run_memfs(memfs, path, b"", &src)
+}
+
+#[test]
+fn memfs_stats_files_and_directories() {
+    let memfs = MemFs::new();
+    assert_eq!(stat(&memfs, "/"), (0, "0\n2\n".to_string()));
+    assert_eq!(stat(&memfs, "missing").0, 6);
+
+    memfs.insert("dir/file.txt", "four").unwrap();
+    assert_eq!(stat(&memfs, "/dir/file.txt"), (0, "4\n1\n".to_string()));
+    assert_eq!(stat(&memfs, "dir"), (0, "0\n2\n".to_string()));
+    assert_eq!(stat(&memfs, ""), (0, "0\n2\n".to_string()));
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use rsvm::debugger::Debugger;
+use rsvm::gdb::{Ending, GdbServer};
+
+use common::{assemble, assemble_with_data, load};
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn connect(bytecode: Vec<u8>) -> (Client, JoinHandle<Ending>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (vm, _) = load(bytecode);
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut debugger = Debugger::new(vm);
+
+            GdbServer::new(&mut debugger, stream).serve().unwrap()
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        (Client { stream }, server)
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send_raw(&mut self, packet: &str) -> u8 {
+        self.stream.write_all(packet.as_bytes()).unwrap();
+        self.read_byte()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let data = String::from_utf8(data).unwrap();
+
+        let sum = [self.read_byte(), self.read_byte()];
+        let sum = u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap();
+        assert_eq!(sum, checksum(&data));
+
+        self.stream.write_all(b"+").unwrap();
+        data
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+        assert_eq!(self.send_raw(&packet), b'+');
+
+        self.reply()
+    }
+
+    fn detach(mut self, server: JoinHandle<Ending>) {
+        assert_eq!(self.request("D"), "OK");
+        assert_eq!(server.join().unwrap(), Ending::Detached);
+    }
+}
+
+fn program() -> Vec<u8> {
+    assemble_with_data(&[0x12, 0x34], "exit")
+}
+
+#[test]
+fn packets_with_a_bad_checksum_are_asked_for_again() {
+    let (mut client, server) = Client::connect(program());
+
+    assert_eq!(client.send_raw("$?#00"), b'-');
+    assert_eq!(client.send_raw("$?#3f"), b'+');
+    assert_eq!(client.reply(), "S05");
+
+    client.detach(server);
+}
+
+#[test]
+fn replies_are_sent_again_when_asked_for() {
+    let (mut client, server) = Client::connect(program());
+
+    client.stream.write_all(b"$?#3f").unwrap();
+    assert_eq!(client.read_byte(), b'+');
+    let first = {
+        let mut packet = [0; 7];
+        client.stream.read_exact(&mut packet).unwrap();
+        packet
+    };
+    client.stream.write_all(b"-").unwrap();
+
+    assert_eq!(&first, b"$S05#b8");
+    assert_eq!(client.reply(), "S05");
+
+    client.detach(server);
+}
+
+#[test]
+fn memory_is_the_heap_four_bytes_to_a_word() {
+    let (mut client, server) = Client::connect(program());
+
+    assert_eq!(client.request("m0,8"), "0000001200000034");
+    assert_eq!(client.request("M5,3:abcdef"), "OK");
+    assert_eq!(client.request("m4,4"), "00abcdef");
+
+    client.detach(server);
+}
+
+#[test]
+fn memory_reads_are_cut_to_fit_a_packet() {
+    let (mut client, server) = Client::connect(program());
+
+    assert_eq!(client.request("m0,100000").len(), 0x4000);
+
+    client.detach(server);
+}
+
+#[test]
+fn memory_past_the_end_of_the_address_space_is_an_error() {
+    let (mut client, server) = Client::connect(program());
+
+    assert_eq!(client.request("mffffffffffffffff,10"), "E01");
+    assert_eq!(client.request("Mffffffffffffffff,2:abcd"), "E01");
+    assert_eq!(
+        client.request("qXfer:features:read:target.xml:10,ffffffffffffffff"),
+        "E00"
+    );
+
+    client.detach(server);
+}
+
+#[test]
+fn running_to_the_end_finishes_the_session() {
+    let (mut client, server) = Client::connect(program());
+
+    assert_eq!(client.request("c"), "W00");
+    assert_eq!(server.join().unwrap(), Ending::Finished);
+}
+
+#[test]
+fn exit_codes_are_reported_the_way_the_cli_exits_with_them() {
+    for (code, reply) in [(3, "W03"), (70, "W47"), (256, "Wff")] {
+        let src = format!("mov_lit_reg A {}\nexit_reg A", code);
+        let (mut client, server) = Client::connect(assemble(&src));
+
+        assert_eq!(client.request("c"), reply);
+        assert_eq!(server.join().unwrap(), Ending::Finished);
+    }
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use rsvm::{FaultKind, InterruptOutOfRange};
+
+use common::{assemble, load};
+
+fn run(src: &str, raised: &[u8]) -> u32 {
+    let (mut vm, _) = load(assemble(src));
+
+    for &n in raised {
+        vm.raise_interrupt(n).unwrap();
+    }
+
+    vm.run_program().unwrap().code()
+}
+
+#[test]
+fn timer_runs_its_handler_every_period() {
+    let src = "
+        int_table_lit 100
+        mov_lit_heap 100 handler
+        mov_lit_reg D 0
+        timer_set_lit 10
+        int_enable
+    loop:
+        compare_reg_lit D 3
+        jump_not_equal loop
+        exit_reg D
+    handler:
+        math_inc_reg D
+        int_return
+    ";
+
+    assert_eq!(run(src, &[]), 3);
+}
+
+#[test]
+fn masked_interrupts_wait_for_int_enable() {
+    let src = "
+        int_table_lit 100
+        mov_lit_heap 105 handler
+        mov_lit_reg D 0
+        nop
+        nop
+        compare_reg_lit D 0
+        jump_not_equal fail
+        int_enable
+        nop
+        exit_reg D
+    fail:
+        mov_lit_reg D 99
+        exit_reg D
+    handler:
+        mov_lit_reg D 7
+        int_return
+    ";
+
+    assert_eq!(run(src, &[5]), 7);
+}
+
+#[test]
+fn int_return_restores_the_flags() {
+    let src = "
+        int_table_lit 100
+        mov_lit_heap 100 handler
+        mov_lit_reg A 1
+        compare_reg_lit A 1
+        timer_set_lit 1
+        int_enable
+        nop
+        jump_equal done
+        exit
+    done:
+        mov_lit_reg D 7
+        exit_reg D
+    handler:
+        timer_set_lit 0
+        compare_reg_lit A 2
+        int_return
+    ";
+
+    assert_eq!(run(src, &[]), 7);
+}
+
+#[test]
+fn int_return_without_a_frame_faults() {
+    let (mut vm, _) = load(assemble("int_return"));
+
+    assert_eq!(
+        vm.run_program().unwrap_err().kind,
+        FaultKind::StackUnderflow
+    );
+}
+
+#[test]
+fn interrupts_without_a_handler_stay_pending() {
+    let src = "
+        int_table_lit 100
+        mov_lit_reg D 0
+        int_enable
+        nop
+        nop
+        compare_reg_lit D 0
+        jump_not_equal fail
+        mov_lit_heap 102 handler
+        nop
+        exit_reg D
+    fail:
+        mov_lit_reg D 99
+        exit_reg D
+    handler:
+        mov_lit_reg D 7
+        int_return
+    ";
+
+    assert_eq!(run(src, &[2]), 7);
+}
+
+#[test]
+fn interrupts_without_a_handler_let_later_ones_through() {
+    let src = "
+        int_table_lit 100
+        mov_lit_heap 102 handler
+        mov_lit_reg D 0
+        int_enable
+        nop
+        exit_reg D
+    handler:
+        mov_lit_reg D 7
+        int_return
+    ";
+
+    assert_eq!(run(src, &[1, 2]), 7);
+}
+
+#[test]
+fn raising_past_the_last_interrupt_is_an_error() {
+    let (vm, _) = load(assemble("exit"));
+
+    assert_eq!(vm.raise_interrupt(31), Ok(()));
+    assert_eq!(vm.raise_interrupt(32), Err(InterruptOutOfRange(32)));
+    assert_eq!(
+        vm.interrupt_handle().raise(u8::MAX),
+        Err(InterruptOutOfRange(u8::MAX))
+    );
+}
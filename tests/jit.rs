@@ -0,0 +1,194 @@
+#![cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+
+mod common;
+
+use rsvm::decode::Dispatch;
+
+use common::{assemble, load, state, State};
+
+fn run(src: &str, dispatch: Dispatch) -> (State, String, String) {
+    let (mut vm, output) = load(assemble(src));
+    vm.set_dispatch(dispatch);
+
+    let status = format!("{:?}", vm.run_program());
+    (state(&vm), status, output.text())
+}
+
+/// Runs `src` interpreted and compiled, which has to come out the same.
+fn assert_same(src: &str) -> String {
+    let decoded = run(src, Dispatch::Decoded);
+    let compiled = run(src, Dispatch::Compiled);
+
+    assert_eq!(compiled, decoded, "{}", src);
+    decoded.2
+}
+
+#[test]
+fn arithmetic_loops_agree() {
+    // Every round mixes up A with B, counting the overflows in C.
+    let src = "
+            mov_lit_reg A 1
+            mov_lit_reg B 0x9E3779B9
+            mov_lit_reg C 0
+            mov_lit_reg D 5000
+        loop:
+            math_add_reg A B
+            jump_overflow overflowed
+            jump_absolute mix
+        overflowed:
+            math_inc_reg C
+        mix:
+            math_mul_reg_num A 31
+            math_xor_reg A B
+            math_sub_reg_num B 7
+            math_or_reg_num A 1
+            math_and_reg_num B 0xFFFFFFF
+            math_not_reg B
+            math_sub_reg B A
+            math_div_reg_num A 3
+            math_mul_reg A D
+            math_dec_reg D
+            compare_reg_lit D 0
+            jump_greater loop
+            mov_lit_reg A 3
+            mov_reg_reg B C
+            syscall
+            exit
+    ";
+
+    assert_ne!(assert_same(src), "0\n");
+}
+
+#[test]
+fn heap_loops_agree() {
+    // Writes squares far enough out for the heap to grow under the compiled
+    // code, then sums them back up through pointers.
+    let src = "
+            mov_lit_reg A 0
+            mov_lit_reg C 1000
+        fill:
+            mov_reg_reg B A
+            math_mul_reg B A
+            mov_reg_ptr C B
+            math_inc_reg A
+            math_inc_reg C
+            compare_reg_lit A 3000
+            jump_smaller fill
+            mov_lit_reg B 0
+            mov_lit_reg C 1000
+        sum:
+            mov_ptr_reg D C
+            math_add_reg B D
+            mov_heap_heap 1001 500
+            mov_reg_heap 501 B
+            math_inc_reg C
+            compare_reg_lit C 4000
+            jump_not_equal sum
+            mov_lit_reg A 3
+            syscall
+            exit
+    ";
+
+    assert_same(src);
+}
+
+#[test]
+fn loops_making_syscalls_agree() {
+    let src = "
+            mov_lit_reg D 0
+        loop:
+            mov_lit_reg A 3
+            mov_reg_reg B D
+            syscall
+            math_inc_reg D
+            compare_reg_lit D 200
+            jump_not_equal loop
+            exit
+    ";
+
+    let output = assert_same(src);
+    assert_eq!(output.lines().count(), 200);
+}
+
+#[test]
+fn loops_calling_functions_agree() {
+    let src = "
+            mov_lit_reg D 300
+        loop:
+            call bump
+            math_dec_reg D
+            compare_reg_lit D 0
+            jump_not_equal loop
+            exit_reg A
+        bump:
+            push_reg D
+            math_add_reg_num A 3
+            pop_reg D
+            ret
+    ";
+
+    assert_same(src);
+}
+
+#[test]
+fn faults_in_hot_loops_agree() {
+    // D runs down to zero and is divided by.
+    let src = "
+            mov_lit_reg A 1000000
+            mov_lit_reg D 500
+        loop:
+            mov_lit_reg B 1000000
+            math_div_reg B D
+            math_add_reg A B
+            math_dec_reg D
+            jump_absolute loop
+    ";
+
+    let (_, status, _) = run(src, Dispatch::Compiled);
+    assert!(status.starts_with("Err"), "{}", status);
+    assert_same(src);
+}
+
+#[test]
+fn heap_writes_past_the_limit_in_hot_loops_agree() {
+    // Once hot, the loop is sent to write far past the heap limit.
+    let src = "
+            mov_lit_reg A 0
+            mov_lit_reg C 10
+        loop:
+            mov_reg_ptr C A
+            math_inc_reg A
+            compare_reg_lit A 5000
+            jump_not_equal loop
+            mov_lit_reg C 0x8000000
+            jump_absolute loop
+    ";
+
+    let (_, status, _) = run(src, Dispatch::Compiled);
+    assert!(status.contains("OutOfMemory"), "{}", status);
+    assert_same(src);
+}
+
+#[test]
+fn loops_that_run_for_long_agree() {
+    // Far more rounds than a compiled block runs for in one go.
+    let src = "
+            mov_lit_reg A 0
+            mov_lit_reg D 0
+        loop:
+            math_add_reg_num A 0x12345
+            compare_reg_reg A D
+            jump_smaller wrapped
+            jump_absolute next
+        wrapped:
+            math_inc_reg C
+        next:
+            mov_reg_reg D A
+            math_inc_reg B
+            compare_reg_lit B 300000
+            jump_not_equal loop
+            exit_reg C
+    ";
+
+    assert_same(src);
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use rsvm::decode::Dispatch;
+use rsvm::{FaultKind, Heap, Stack, VM};
+
+use common::{assemble, load};
+
+#[test]
+fn heap_reads_zero_outside_of_it() {
+    let heap = Heap::new();
+
+    assert_eq!(heap.read(0), 0);
+    assert_eq!(heap.read(heap.capacity() - 1), 0);
+    assert_eq!(heap.read(heap.capacity()), 0);
+    assert_eq!(heap.read(usize::MAX), 0);
+}
+
+#[test]
+fn heap_grows_to_fit_writes() {
+    let mut heap = Heap::new();
+    let addr = heap.capacity() + 100;
+
+    heap.write(3, 7).unwrap();
+    heap.write(addr, 42).unwrap();
+
+    assert_eq!(heap.capacity(), addr + 1);
+    assert_eq!(heap.read(3), 7);
+    assert_eq!(heap.read(addr), 42);
+    assert!((0..addr)
+        .filter(|&addr| addr != 3)
+        .all(|addr| heap.read(addr) == 0));
+}
+
+#[test]
+fn heap_does_not_grow_for_zero() {
+    let mut heap = Heap::new();
+    let capacity = heap.capacity();
+
+    heap.write(capacity * 4, 0).unwrap();
+
+    assert_eq!(heap.capacity(), capacity);
+}
+
+#[test]
+fn heap_keeps_bytes_four_to_a_word() {
+    let mut heap = Heap::new();
+
+    heap.write_bytes(10, b"hello").unwrap();
+
+    assert_eq!(heap.read(10), u32::from_be_bytes(*b"hell"));
+    assert_eq!(heap.read(11), u32::from_be_bytes(*b"o\0\0\0"));
+    assert_eq!(heap.read_bytes(10, 5), b"hello");
+}
+
+#[test]
+fn heap_does_not_grow_past_its_limit() {
+    let mut heap = Heap::new();
+    heap.set_limit(1024);
+
+    assert_eq!(heap.write(1023, 1), Ok(()));
+    assert_eq!(heap.write(1024, 1), Err(FaultKind::OutOfMemory));
+    assert_eq!(heap.write(usize::MAX, 1), Err(FaultKind::OutOfMemory));
+    assert_eq!(heap.capacity(), 1024);
+}
+
+#[test]
+fn heap_byte_writes_do_not_wrap_around() {
+    let mut heap = Heap::new();
+
+    // Zeroes leave the heap be, so only the addresses can go wrong.
+    assert_eq!(
+        heap.write_bytes(usize::MAX, &[0; 8]),
+        Err(FaultKind::OutOfMemory)
+    );
+}
+
+#[test]
+fn writes_past_the_heap_limit_fault() {
+    let (mut vm, _) = load(assemble("mov_lit_heap 2048 1\nexit"));
+    vm.heap.set_limit(1024);
+
+    assert_eq!(vm.run_program().unwrap_err().kind, FaultKind::OutOfMemory);
+}
+
+#[test]
+fn stack_pops_what_was_pushed_last() {
+    let mut stack = Stack::new();
+
+    stack.push(1);
+    stack.push(2);
+
+    assert_eq!(stack.len(), 2);
+    assert_eq!(stack.pop(), Some(2));
+    assert_eq!(stack.pop(), Some(1));
+    assert_eq!(stack.pop(), None);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn stack_peeks_at_the_top() {
+    let mut stack = Stack::new();
+    assert_eq!(stack.peek(), 0);
+
+    stack.push(1);
+    stack.push(2);
+    assert_eq!(stack.peek(), 2);
+
+    stack.pop();
+    assert_eq!(stack.peek(), 1);
+    assert_eq!(stack.len(), 1);
+}
+
+#[test]
+fn stack_keeps_its_words_as_it_grows() {
+    let mut stack = Stack::new();
+
+    for word in 0..1000 {
+        stack.push(word);
+    }
+
+    assert_eq!(stack.as_slice(), (0..1000).collect::<Vec<_>>().as_slice());
+
+    stack.clear();
+    assert!(stack.is_empty());
+    assert_eq!(stack.as_slice(), &[]);
+}
+
+#[test]
+fn dupe_copies_the_top_of_the_stack() {
+    // push_lit 1, push_lit 2, pop_reg A, dup, pop_reg B, exit_reg B
+    let bytecode = vec![
+        0x1d, 0x1d, 0x1d, 0x1d, 0x01, 0, 0, 0, 1, 0x01, 0, 0, 0, 2, 0x03, 0, 0x05, 0x03, 1, 0x0F, 1,
+    ];
+
+    for &dispatch in &[Dispatch::Bytecode, Dispatch::Decoded] {
+        let mut vm = VM::new();
+        vm.load_program(bytecode.clone());
+        vm.set_dispatch(dispatch);
+
+        assert_eq!(vm.run_program().unwrap().code(), 1);
+    }
+}
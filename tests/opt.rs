@@ -0,0 +1,228 @@
+mod common;
+
+use rsvm::debuginfo::{DebugInfo, DebugInfoBuilder};
+use rsvm::decode::{Instr, Program};
+use rsvm::opt::{optimize, verify, Unsupported};
+
+use common::assemble;
+
+/// Optimizes `original`, checking the result still behaves the same.
+fn optimized(original: &[u8]) -> Vec<u8> {
+    let optimized = optimize(original.to_vec()).unwrap();
+
+    assert_eq!(verify(original, &optimized, &[] as &[&str]), Ok(()));
+    optimized
+}
+
+/// The code of `bytecode`, after its header and before its debug info.
+fn code(mut bytecode: Vec<u8>) -> Vec<u8> {
+    DebugInfo::split_off(&mut bytecode);
+    let end = bytecode
+        .windows(4)
+        .position(|word| word == [0x1d; 4])
+        .unwrap();
+
+    bytecode.split_off(end + 4)
+}
+
+fn instrs(bytecode: Vec<u8>) -> Vec<Instr> {
+    Program::decode(&code(bytecode)).instrs().collect()
+}
+
+#[test]
+fn pushes_are_fused_with_the_pops_after_them() {
+    let program = assemble(
+        "
+        push_lit 500
+        pop_reg A
+        push_reg A
+        pop_heap 0x10
+        mov_heap_reg B 0x10
+        math_add_reg A B
+        exit_reg A
+        ",
+    );
+
+    assert_eq!(
+        instrs(optimized(&program)),
+        [
+            Instr::MovLitReg(0, 500),
+            Instr::MovRegHeap(0x10, 0),
+            Instr::MovHeapReg(1, 0x10),
+            Instr::AddReg(0, 1),
+            Instr::ExitReg(0),
+        ]
+    );
+}
+
+#[test]
+fn arithmetic_on_literals_is_folded() {
+    let program = assemble(
+        "
+        push_lit 3
+        push_lit 4
+        math_add_stack
+        math_mul_stack_num 6
+        pop_reg A
+        math_add_reg_num A 2
+        exit_reg A
+        ",
+    );
+
+    assert_eq!(
+        instrs(optimized(&program)),
+        [
+            Instr::MovLitReg(0, 42),
+            Instr::MovLitReg(0, 44),
+            Instr::ExitReg(0),
+        ]
+    );
+}
+
+#[test]
+fn jumps_to_jumps_are_threaded() {
+    let program = assemble(
+        "
+        compare_reg_reg A B
+        jump_equal hop
+        exit
+        hop:
+        jump_absolute end
+        nop
+        end:
+        mov_lit_reg A 7
+        exit_reg A
+        ",
+    );
+
+    let optimized = optimized(&program);
+    let program = Program::decode(&code(optimized));
+    let instrs = program.iter().collect::<Vec<_>>();
+
+    assert!(!instrs
+        .iter()
+        .any(|&(_, instr, _)| matches!(instr, Instr::Jump(_))));
+
+    let target = instrs
+        .iter()
+        .find_map(|&(_, instr, _)| match instr {
+            Instr::JumpEqual(target) => Some(target as usize),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(program.get(target).unwrap().0, Instr::MovLitReg(0, 7));
+}
+
+#[test]
+fn code_nothing_reaches_is_swept() {
+    let program = assemble(
+        "
+        mov_lit_reg A 1
+        jump_absolute end
+        unused:
+        push_lit 2
+        pop_reg A
+        ret
+        end:
+        exit_reg A
+        never:
+        call unused
+        exit
+        ",
+    );
+
+    assert_eq!(
+        instrs(optimized(&program)),
+        [Instr::MovLitReg(0, 1), Instr::ExitReg(0)]
+    );
+}
+
+#[test]
+fn offsets_held_as_literals_stay_in_place() {
+    // Returning through a frame put together by hand jumps to `target`
+    // without any jump naming it.
+    let program = assemble(
+        "
+        nop
+        nop
+        push_lit target
+        push_lit 0
+        ret
+        nop
+        target:
+        mov_lit_reg A 9
+        exit_reg A
+        ",
+    );
+    let target = code(program.clone()).len() - 8;
+
+    let optimized = code(optimized(&program));
+
+    assert!(matches!(
+        Program::decode(&optimized).instrs().next(),
+        Some(Instr::PushLit(_))
+    ));
+    assert_eq!(
+        Instr::decode(&optimized[target..]),
+        Some((Instr::MovLitReg(0, 9), 6))
+    );
+}
+
+#[test]
+fn debug_info_follows_the_code() {
+    let mut program = assemble(
+        "
+        nop
+        push_lit 100
+        pop_reg A
+        nop
+        jump_absolute end
+        end:
+        exit_reg A
+        ",
+    );
+    let end = code(program.clone()).len() - 2;
+    DebugInfoBuilder::new()
+        .line(0, "main.s", 1, 1)
+        .line(end, "main.s", 6, 1)
+        .label(0, "main")
+        .label(end, "end")
+        .build()
+        .append_to(&mut program);
+
+    let mut optimized = optimized(&program);
+    let info = DebugInfo::split_off(&mut optimized).unwrap();
+    let code = code(optimized);
+
+    let moved = info.label_offset("end").unwrap();
+    assert!(moved < end);
+    assert_eq!(info.label_offset("main"), Some(0));
+    assert_eq!(info.line_offset("main.s", 6), Some(moved));
+    assert_eq!(Instr::decode(&code[moved..]), Some((Instr::ExitReg(0), 2)));
+}
+
+#[test]
+fn programs_taking_interrupts_are_left_alone() {
+    let program = assemble(
+        "
+        int_enable
+        exit
+        ",
+    );
+
+    assert_eq!(optimize(program), Err(Unsupported::Interrupts));
+}
+
+#[test]
+fn undecodable_code_is_left_alone() {
+    // The jump lands on the register of the move.
+    let program = assemble(
+        "
+        jump_absolute 6
+        mov_lit_reg A 0
+        exit
+        ",
+    );
+
+    assert_eq!(optimize(program), Err(Unsupported::Undecodable));
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::debuginfo::{DebugInfo, DebugInfoBuilder};
+
+use common::{assemble, load};
+
+const PROGRAM: &str = "
+        call work
+        exit
+    work:
+        mov_lit_reg D 3
+    loop:
+        math_dec_reg D
+        compare_reg_lit D 0
+        jump_not_equal loop
+        ret
+";
+
+fn debug_info() -> DebugInfo {
+    DebugInfoBuilder::new()
+        .label(0, "main")
+        .label(6, "work")
+        .label(12, "loop")
+        .build()
+}
+
+fn folded(debug_info: Option<&DebugInfo>) -> String {
+    let (mut vm, _) = load(assemble(PROGRAM));
+    vm.enable_profiling();
+    vm.run_program().unwrap();
+
+    let mut folded = Vec::new();
+    vm.profile()
+        .unwrap()
+        .write_folded(&mut folded, debug_info)
+        .unwrap();
+
+    String::from_utf8(folded).unwrap()
+}
+
+#[test]
+fn folded_stacks_are_named_after_labels() {
+    assert_eq!(
+        folded(Some(&debug_info())),
+        "main 2\n\
+         main;loop 10\n\
+         main;work 1\n"
+    );
+}
+
+#[test]
+fn folded_stacks_fall_back_to_offsets() {
+    assert_eq!(
+        folded(None),
+        "0x0000 1\n\
+         0x0004;0x0006 1\n\
+         0x0004;0x000c 3\n\
+         0x0004;0x000e 3\n\
+         0x0004;0x0014 3\n\
+         0x0004;0x0019 1\n\
+         0x0005 1\n"
+    );
+}
+
+#[test]
+fn blocked_instructions_are_sampled_once() {
+    let (mut vm, _) = load(assemble(
+        "
+            mov_lit_reg A 0x12
+            mov_lit_reg B 0
+            syscall
+            exit
+        ",
+    ));
+    let channel = Channel::unbounded();
+    vm.attach_channel(channel.clone());
+    vm.enable_profiling();
+
+    for _ in 0..10 {
+        vm.step();
+    }
+    assert!(vm.is_blocked());
+
+    channel.send(Message::Word(1)).unwrap();
+    vm.run_program().unwrap();
+
+    let profile = vm.profile().unwrap();
+    assert_eq!(profile.samples(), 4);
+    assert_eq!(profile.instruction_count(12), 1);
+    assert_eq!(profile.opcode_count(0xFF), 1);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{assemble, load};
+
+fn fill(len: &str, seed: u64) -> (u32, Vec<String>) {
+    let src = format!(
+        "
+        mov_lit_reg A 0x41
+        mov_lit_reg B 100
+        mov_lit_reg C {}
+        syscall
+        mov_reg_reg D A
+        mov_lit_reg A 3
+        mov_heap_reg B 100
+        syscall
+        mov_heap_reg B 101
+        syscall
+        mov_heap_reg B 102
+        syscall
+        exit_reg D
+    ",
+        len
+    );
+    let (mut vm, output) = load(assemble(&src));
+    vm.seed_rng(seed);
+
+    let status = vm.run_program().unwrap().code();
+    let words = output.text().lines().map(str::to_string).collect();
+    (status, words)
+}
+
+#[test]
+fn fill_is_deterministic_for_a_seed() {
+    let (status, words) = fill("2", 1);
+
+    assert_eq!(status, 0);
+    assert_ne!(words[0], "0");
+    assert_eq!(words[2], "0");
+    assert_eq!(fill("2", 1), (status, words.clone()));
+    assert_ne!(fill("2", 2).1, words);
+}
+
+#[test]
+fn fill_past_the_io_limit_is_too_long() {
+    assert_eq!(fill("0x100001", 1), (4, vec!["0".to_string(); 3]));
+}
+
+#[test]
+fn fill_past_the_end_of_the_address_space_is_too_long() {
+    let src = "
+        mov_lit_reg A 0x41
+        mov_lit_reg B 0
+        math_not_reg B
+        mov_lit_reg C 2
+        syscall
+        exit_reg A
+    ";
+    let (mut vm, _) = load(assemble(src));
+
+    assert_eq!(vm.run_program().unwrap().code(), 4);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::fs::MemFs;
+use rsvm::{FaultKind, VM};
+
+use common::{assemble, load, state, store_bytes, Captured, State};
+
+fn program() -> Vec<u8> {
+    let src = store_bytes(100, b"in.txt")
+        + "
+        mov_lit_reg A 0x40
+        syscall
+        push_reg B
+        mov_lit_reg A 0x30
+        syscall
+        push_reg C
+        mov_lit_reg A 0x20
+        mov_lit_reg B 100
+        mov_lit_reg C 6
+        mov_lit_reg D 1
+        syscall
+        mov_lit_reg A 0x21
+        mov_lit_reg C 300
+        mov_lit_reg D 8
+        syscall
+        push_reg D
+        mov_lit_reg D 0
+    loop:
+        push_reg D
+        mov_lit_reg A 0x12
+        mov_lit_reg B 0
+        syscall
+        mov_heap_reg C 400
+        math_add_reg C B
+        mov_reg_heap 400 C
+        pop_reg D
+        math_inc_reg D
+        compare_reg_lit D 5
+        jump_not_equal loop
+        mov_lit_reg A 0x16
+        mov_lit_reg B 0
+        syscall
+        push_reg A
+        mov_lit_reg A 3
+        mov_heap_reg B 400
+        syscall
+        exit
+    ";
+
+    assemble(&src)
+}
+
+fn setup(contents: &str, seed: u64) -> (VM, Captured, Channel) {
+    let (mut vm, output) = load(program());
+    let (memfs, channel) = (MemFs::new(), Channel::unbounded());
+
+    memfs.insert("in.txt", contents).unwrap();
+    vm.set_filesystem(memfs);
+    vm.attach_channel(channel.clone());
+    vm.seed_rng(seed);
+
+    (vm, output, channel)
+}
+
+fn run(vm: &mut VM) -> Vec<State> {
+    let mut states = vec![state(vm)];
+
+    while !vm.is_stopped() {
+        vm.step();
+        states.push(state(vm));
+    }
+
+    states
+}
+
+fn record() -> (Vec<State>, String, Vec<u8>) {
+    let (mut vm, output, channel) = setup("recorded", 1);
+    let log = Captured::default();
+    vm.record_to(log.clone()).unwrap();
+
+    let sender = thread::spawn(move || {
+        for word in 1..=5 {
+            thread::sleep(Duration::from_millis(1));
+            channel.send(Message::Word(word)).unwrap();
+        }
+    });
+    let states = run(&mut vm);
+    sender.join().unwrap();
+
+    assert!(vm.run_program().is_ok());
+    (states, output.text(), log.contents())
+}
+
+#[test]
+fn replays_go_through_the_same_states() {
+    let (recorded, output, log) = record();
+
+    // Neither the file nor the channel hold what they did, and the seed is
+    // another one, so all of it has to come from the recording.
+    let (mut vm, replayed_output, _) = setup("replayed", 2);
+    vm.replay_from(log.as_slice()).unwrap();
+    let replayed = run(&mut vm);
+
+    assert!(vm.run_program().is_ok());
+    assert_eq!(replayed.len(), recorded.len());
+    for (step, (recorded, replayed)) in recorded.iter().zip(&replayed).enumerate() {
+        assert_eq!(recorded, replayed, "at step {}", step);
+    }
+    assert_eq!(replayed_output.text(), output);
+    assert_eq!(output, "15\n");
+}
+
+#[test]
+fn replays_that_run_out_of_events_diverge() {
+    let (_, _, log) = record();
+
+    let (mut vm, _, _) = setup("recorded", 1);
+    vm.replay_from(&log[..log.len() - 1]).unwrap_err();
+    vm.replay_from(&log[..8]).unwrap();
+
+    assert_eq!(
+        vm.run_program().unwrap_err().kind,
+        FaultKind::ReplayDiverged
+    );
+}
+
+#[test]
+fn recordings_with_overlong_events_are_refused() {
+    let mut log = b"RSVMREC1".to_vec();
+    log.push(0);
+    log.extend_from_slice(&u32::MAX.to_be_bytes());
+    log.extend_from_slice(b"short");
+
+    let (mut vm, _, _) = setup("", 1);
+    assert!(vm.replay_from(log.as_slice()).is_err());
+}
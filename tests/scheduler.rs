@@ -0,0 +1,215 @@
+mod common;
+
+use rsvm::channel::{Channel, Message};
+use rsvm::scheduler::{Limits, Scheduler, Status};
+use rsvm::VM;
+
+use common::assemble;
+
+fn sender(channel: &Channel, id: u32) -> VM {
+    let src = format!(
+        "
+    loop:
+        mov_lit_reg A 0x10
+        mov_lit_reg C {}
+        syscall
+        jump_absolute loop
+    ",
+        id
+    );
+
+    let mut vm = VM::new();
+    vm.load_program(assemble(&src));
+    vm.attach_channel(channel.clone());
+    vm
+}
+
+fn sent_by_turns(quantum: u32) -> Vec<u32> {
+    let channel = Channel::unbounded();
+    let limits = Limits {
+        max_steps: Some(12),
+        ..Default::default()
+    };
+
+    let mut scheduler = Scheduler::new();
+    scheduler.set_quantum(quantum);
+    scheduler.add(sender(&channel, 7), limits);
+    scheduler.add(sender(&channel, 9), limits);
+
+    for outcome in scheduler.run() {
+        assert_eq!(outcome.status, Status::StepLimitExceeded);
+        assert_eq!(outcome.steps, 12);
+    }
+
+    let mut sent = Vec::new();
+    while let Some(Message::Word(id)) = channel.recv() {
+        sent.push(id);
+    }
+    sent
+}
+
+#[test]
+fn instances_take_turns_every_quantum() {
+    assert_eq!(sent_by_turns(4), [7, 9, 7, 9, 7, 9]);
+    assert_eq!(sent_by_turns(8), [7, 7, 9, 9, 7, 9]);
+    assert_eq!(sent_by_turns(1024), [7, 7, 7, 9, 9, 9]);
+}
+
+#[test]
+fn step_limit_stops_endless_programs() {
+    let mut scheduler = Scheduler::new();
+    scheduler.set_quantum(7);
+    scheduler.spawn(
+        assemble("loop:\njump_absolute loop"),
+        Limits {
+            max_steps: Some(100),
+            ..Default::default()
+        },
+    );
+    scheduler.spawn(assemble("mov_lit_reg A 3\nexit_reg A"), Limits::default());
+
+    let outcomes = scheduler.run();
+
+    assert_eq!(outcomes[0].status, Status::StepLimitExceeded);
+    assert_eq!(outcomes[0].steps, 100);
+    assert!(matches!(&outcomes[1].status, Status::Exited(status) if status.code() == 3));
+    assert_eq!(outcomes[1].steps, 2);
+}
+
+#[test]
+fn memory_limit_stops_growing_programs() {
+    let src = "
+    loop:
+        push_lit 1
+        jump_absolute loop
+    ";
+    let mut scheduler = Scheduler::new();
+    scheduler.spawn(
+        assemble(src),
+        Limits {
+            max_memory: Some(64 * 1024),
+            ..Default::default()
+        },
+    );
+
+    let outcomes = scheduler.run();
+
+    assert_eq!(outcomes[0].status, Status::MemoryLimitExceeded);
+}
+
+#[test]
+fn outputs_are_kept_apart() {
+    let mut scheduler = Scheduler::new();
+    scheduler.set_quantum(1);
+    for n in 0..3 {
+        let src = format!("mov_lit_reg A 3\nmov_lit_reg B {}\nsyscall\nexit", n);
+        scheduler.spawn(assemble(&src), Limits::default());
+    }
+
+    let outputs = scheduler
+        .run()
+        .into_iter()
+        .map(|outcome| outcome.output)
+        .collect::<Vec<_>>();
+
+    assert_eq!(outputs, [b"0\n", b"1\n", b"2\n"]);
+}
+
+fn receiver_and_sender(scheduler: &mut Scheduler) {
+    let channel = Channel::new(1);
+
+    let mut receiver = VM::new();
+    receiver.load_program(assemble(
+        "mov_lit_reg A 0x12\nmov_lit_reg B 0\nsyscall\nexit_reg B",
+    ));
+    receiver.attach_channel(channel.clone());
+    scheduler.add(receiver, Limits::default());
+
+    let mut sender = VM::new();
+    sender.load_program(assemble(
+        "mov_lit_reg A 0x10\nmov_lit_reg B 0\nmov_lit_reg C 42\nsyscall\nexit",
+    ));
+    sender.attach_channel(channel);
+    scheduler.add(sender, Limits::default());
+}
+
+#[test]
+fn parallel_runs_finish_like_sequential_ones() {
+    for &workers in &[1, 2, 8] {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_quantum(3);
+        receiver_and_sender(&mut scheduler);
+        for n in 0..16 {
+            let src = format!(
+                "
+                mov_lit_reg A 0
+            loop:
+                math_inc_reg A
+                compare_reg_lit A {}
+                jump_not_equal loop
+                exit_reg A
+            ",
+                n * 10 + 1
+            );
+            scheduler.spawn(assemble(&src), Limits::default());
+        }
+
+        let outcomes = scheduler.run_parallel(workers);
+
+        assert_eq!(outcomes.len(), 18);
+        for (id, outcome) in outcomes.iter().enumerate() {
+            let code = match &outcome.status {
+                Status::Exited(status) => status.code(),
+                status => panic!("instance {} ended with {:?}", id, status),
+            };
+
+            assert_eq!(outcome.id, id);
+            match id {
+                0 => assert_eq!(code, 42),
+                1 => assert_eq!(code, 0),
+                _ => assert_eq!(code, (id as u32 - 2) * 10 + 1),
+            }
+        }
+    }
+}
+
+#[test]
+fn memory_limit_stops_single_huge_writes() {
+    let mut scheduler = Scheduler::new();
+    scheduler.spawn(
+        assemble("mov_lit_heap 0x10000000 1\nexit"),
+        Limits {
+            max_memory: Some(64 * 1024),
+            ..Default::default()
+        },
+    );
+
+    let outcomes = scheduler.run();
+
+    assert_eq!(outcomes[0].status, Status::MemoryLimitExceeded);
+    assert_eq!(outcomes[0].steps, 1);
+}
+
+#[test]
+fn step_limit_stops_instances_blocked_for_good() {
+    let channel = Channel::new(1);
+    let mut receiver = VM::new();
+    receiver.load_program(assemble(
+        "mov_lit_reg A 0x12\nmov_lit_reg B 0\nsyscall\nexit_reg B",
+    ));
+    receiver.attach_channel(channel);
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add(
+        receiver,
+        Limits {
+            max_steps: Some(50),
+            ..Default::default()
+        },
+    );
+
+    let outcomes = scheduler.run();
+
+    assert_eq!(outcomes[0].status, Status::StepLimitExceeded);
+    assert_eq!(outcomes[0].steps, 50);
+}
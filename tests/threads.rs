@@ -0,0 +1,114 @@
+mod common;
+
+use rsvm::FaultKind;
+
+use common::{assemble, load};
+
+#[test]
+fn yield_without_other_threads_carries_on() {
+    let (mut vm, _) = load(vec![0x1d, 0x1d, 0x1d, 0x1d, 0x91, 0x00]);
+    assert_eq!(vm.run_program().unwrap().code(), 0);
+
+    let src = "
+        thread_yield
+        thread_yield
+        mov_lit_reg D 7
+        exit_reg D
+    ";
+    let (mut vm, _) = load(assemble(src));
+    assert_eq!(vm.run_program().unwrap().code(), 7);
+}
+
+#[test]
+fn yield_switches_to_the_next_thread() {
+    let src = "
+        thread_spawn C worker
+        mov_lit_reg A 3
+        mov_lit_reg B 1
+        syscall
+        thread_yield
+        mov_lit_reg A 3
+        mov_lit_reg B 3
+        syscall
+        thread_join C
+        exit
+    worker:
+        mov_lit_reg A 3
+        mov_lit_reg B 2
+        syscall
+        exit
+    ";
+    let (mut vm, output) = load(assemble(src));
+
+    assert!(vm.run_program().unwrap().success());
+    assert_eq!(output.text(), "1\n2\n3\n");
+}
+
+#[test]
+fn join_waits_for_the_thread_to_finish() {
+    let src = "
+        thread_spawn C worker
+        thread_join C
+        mov_heap_reg D 50
+        exit_reg D
+    worker:
+        thread_yield
+        thread_yield
+        mov_lit_heap 50 7
+        exit
+    ";
+    let (mut vm, _) = load(assemble(src));
+
+    assert_eq!(vm.run_program().unwrap().code(), 7);
+    assert_eq!(vm.thread_count(), 2);
+}
+
+#[test]
+fn threads_know_who_they_are() {
+    let src = "
+        thread_spawn C worker
+        thread_join C
+        thread_self A
+        mov_heap_reg B 50
+        math_add_reg A B
+        exit_reg A
+    worker:
+        thread_self D
+        mov_reg_heap 50 D
+        exit
+    ";
+    let (mut vm, _) = load(assemble(src));
+
+    assert_eq!(vm.run_program().unwrap().code(), 1);
+}
+
+#[test]
+fn joining_an_unknown_thread_faults() {
+    for src in &[
+        "mov_lit_reg A 1\nthread_join A",
+        "mov_lit_reg A 0\nthread_join A",
+    ] {
+        let (mut vm, _) = load(assemble(src));
+
+        assert!(matches!(
+            vm.run_program().unwrap_err().kind,
+            FaultKind::InvalidThread(_)
+        ));
+    }
+}
+
+#[test]
+fn threads_joining_each_other_deadlock() {
+    let src = "
+        thread_spawn B worker
+        thread_join B
+        exit
+    worker:
+        mov_lit_reg A 0
+        thread_join A
+        exit
+    ";
+    let (mut vm, _) = load(assemble(src));
+
+    assert_eq!(vm.run_program().unwrap_err().kind, FaultKind::Deadlock);
+}
@@ -0,0 +1,269 @@
+mod common;
+
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use rsvm::to_c::translate;
+
+use common::{assemble, assemble_with_data, load};
+
+/// Sample programs, each of which sticks to what the C runtime has: no
+/// channels and no filesystem.
+fn samples() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "countdown",
+            assemble(
+                "
+                    mov_lit_reg D 5
+                loop:
+                    mov_lit_reg A 3
+                    mov_reg_reg B D
+                    syscall
+                    math_dec_reg D
+                    compare_reg_lit D 0
+                    jump_not_equal loop
+                    mov_lit_reg B 3
+                    mov_lit_reg A 4
+                    syscall
+                ",
+            ),
+        ),
+        (
+            "factorial",
+            assemble(
+                "
+                    mov_lit_reg A 10
+                    call factorial
+                    mov_reg_reg B A
+                    mov_lit_reg A 3
+                    syscall
+                    exit
+                factorial:
+                    compare_reg_lit A 1
+                    jump_greater recurse
+                    mov_lit_reg A 1
+                    ret
+                recurse:
+                    push_reg A
+                    math_dec_reg A
+                    call factorial
+                    pop_reg B
+                    math_mul_reg A B
+                    ret
+                ",
+            ),
+        ),
+        (
+            "arithmetic",
+            assemble(
+                "
+                    mov_lit_reg A 0xFFFFFFFF
+                    math_add_reg_num A 2
+                    jump_overflow overflowed
+                    exit
+                overflowed:
+                    mov_reg_reg B A
+                    mov_lit_reg A 3
+                    syscall
+                    push_lit 7
+                    push_lit 0xFFFFFFFF
+                    math_mul_stack
+                    math_not_stack
+                    pop_reg B
+                    syscall
+                    mov_lit_reg C 0x1234
+                    math_xor_reg_num C 0xFF00
+                    math_and_reg_num C 0xF0F0
+                    math_or_reg_num C 1
+                    mov_reg_reg B C
+                    syscall
+                    exit_reg C
+                ",
+            ),
+        ),
+        (
+            "threads",
+            assemble(
+                "
+                    thread_spawn C worker
+                    mov_lit_reg D 0
+                main_loop:
+                    mov_lit_reg A 3
+                    mov_lit_reg B 100
+                    syscall
+                    thread_yield
+                    math_inc_reg D
+                    compare_reg_lit D 3
+                    jump_not_equal main_loop
+                    thread_join C
+                    exit
+                worker:
+                    mov_lit_reg D 0
+                worker_loop:
+                    mov_lit_reg A 3
+                    mov_lit_reg B 200
+                    syscall
+                    thread_yield
+                    math_inc_reg D
+                    compare_reg_lit D 5
+                    jump_not_equal worker_loop
+                    exit
+                ",
+            ),
+        ),
+        (
+            "timer",
+            assemble(
+                "
+                    int_table_lit 300
+                    mov_lit_heap 300 handler
+                    mov_lit_reg D 0
+                    timer_set_lit 4
+                    int_enable
+                loop:
+                    compare_reg_lit D 5
+                    jump_not_equal loop
+                    int_disable
+                    mov_reg_reg B D
+                    mov_lit_reg A 3
+                    syscall
+                    exit
+                handler:
+                    math_inc_reg D
+                    int_return
+                ",
+            ),
+        ),
+        (
+            "data",
+            assemble_with_data(
+                &[3, 1, 4, 1, 5],
+                "
+                    mov_lit_reg C 0
+                    mov_lit_reg B 0
+                loop:
+                    mov_ptr_reg D C
+                    math_add_reg B D
+                    math_inc_reg C
+                    compare_reg_lit C 5
+                    jump_not_equal loop
+                    mov_lit_reg A 3
+                    syscall
+                    exit_reg B
+                ",
+            ),
+        ),
+        (
+            "fault",
+            assemble(
+                "
+                    mov_lit_reg A 3
+                    mov_lit_reg B 1
+                    syscall
+                    mov_lit_reg D 0
+                    math_div_reg B D
+                    exit
+                ",
+            ),
+        ),
+        (
+            "big_exit",
+            assemble("mov_lit_reg B 300\nmov_lit_reg A 4\nsyscall"),
+        ),
+        ("stack_underflow", assemble("pop_reg A\nexit")),
+    ]
+}
+
+fn translated(bytecode: &[u8]) -> String {
+    let mut c = Vec::new();
+    translate(bytecode.to_vec(), &mut c).unwrap();
+
+    String::from_utf8(c).unwrap()
+}
+
+/// What `rsvm` prints and exits with for `bytecode`.
+fn expected(bytecode: &[u8]) -> (String, i32) {
+    let (mut vm, output) = load(bytecode.to_vec());
+
+    let code = match vm.run_program() {
+        Ok(status) => match status.code() {
+            70 => 71,
+            code @ 0..=255 => code as i32,
+            _ => 255,
+        },
+        Err(_) => 70,
+    };
+
+    (output.text(), code)
+}
+
+fn has_cc() -> bool {
+    Command::new("cc").arg("--version").output().is_ok()
+}
+
+fn compile(name: &str, c: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rsvm-to-c-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (source, binary) = (dir.join(format!("{}.c", name)), dir.join(name));
+    std::fs::write(&source, c).unwrap();
+
+    let compiled = Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-O1", "-o"])
+        .arg(&binary)
+        .arg(&source)
+        .output()
+        .unwrap();
+
+    let warnings = String::from_utf8_lossy(&compiled.stderr);
+    assert!(compiled.status.success(), "{}: {}", name, warnings);
+    assert!(warnings.is_empty(), "{}: {}", name, warnings);
+
+    binary
+}
+
+#[test]
+fn samples_translate() {
+    for (name, bytecode) in samples() {
+        let c = translated(&bytecode);
+
+        assert!(
+            c.contains("static void rsvm_run(struct rsvm *vm)"),
+            "{}",
+            name
+        );
+        assert!(c.contains("int main(int argc, char **argv)"), "{}", name);
+    }
+}
+
+#[test]
+fn translated_samples_run_like_the_vm() {
+    if !has_cc() {
+        eprintln!("no cc to compile the translations with");
+        return;
+    }
+
+    for (name, bytecode) in samples() {
+        let binary = compile(name, &translated(&bytecode));
+        let ran = Command::new(&binary).output().unwrap();
+
+        let (output, code) = expected(&bytecode);
+        assert_eq!(String::from_utf8_lossy(&ran.stdout), output, "{}", name);
+        assert_eq!(ran.status.code(), Some(code), "{}", name);
+    }
+}
+
+#[test]
+fn bytecode_without_a_header_is_refused() {
+    for bytecode in [&[][..], &[0x1d, 0x1d, 0x1d], &[1, 2, 3, 4, 5, 6]] {
+        let mut c = Vec::new();
+        let error = translate(bytecode.to_vec(), &mut c).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(c.is_empty());
+    }
+
+    assert!(translate(vec![0x1d; 4], io::sink()).is_ok());
+}
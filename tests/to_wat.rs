@@ -0,0 +1,192 @@
+mod common;
+
+use std::io;
+use std::process::Command;
+
+use rsvm::to_wat::translate;
+
+use common::{assemble, assemble_with_data};
+
+fn samples() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "countdown",
+            assemble(
+                "
+                    mov_lit_reg D 5
+                loop:
+                    mov_lit_reg A 3
+                    mov_reg_reg B D
+                    syscall
+                    math_dec_reg D
+                    compare_reg_lit D 0
+                    jump_not_equal loop
+                    exit_reg D
+                ",
+            ),
+        ),
+        (
+            "factorial",
+            assemble(
+                "
+                    mov_lit_reg A 10
+                    call factorial
+                    exit_reg A
+                factorial:
+                    compare_reg_lit A 1
+                    jump_greater recurse
+                    mov_lit_reg A 1
+                    ret
+                recurse:
+                    push_reg A
+                    math_dec_reg A
+                    call factorial
+                    pop_reg B
+                    math_mul_reg A B
+                    ret
+                ",
+            ),
+        ),
+        (
+            "timer",
+            assemble(
+                "
+                    int_table_lit 300
+                    mov_lit_heap 300 handler
+                    timer_set_lit 4
+                    int_enable
+                loop:
+                    compare_reg_lit D 5
+                    jump_not_equal loop
+                    exit_reg D
+                handler:
+                    math_inc_reg D
+                    int_return
+                ",
+            ),
+        ),
+        (
+            "data",
+            assemble_with_data(
+                &[3, 1, 4, 1, 5],
+                "
+                    mov_lit_reg C 4
+                    mov_ptr_reg B C
+                    exit_reg B
+                ",
+            ),
+        ),
+        ("threads", assemble("thread_spawn A 0\nthread_yield\nexit")),
+        ("empty", assemble("")),
+    ]
+}
+
+fn translated(bytecode: &[u8]) -> String {
+    let mut wat = Vec::new();
+    translate(bytecode.to_vec(), &mut wat).unwrap();
+
+    String::from_utf8(wat).unwrap()
+}
+
+/// Checks that the parentheses of `wat` pair up, leaving out comments and
+/// strings, and that it is a single module.
+fn assert_balanced(name: &str, wat: &str) {
+    let (mut depth, mut modules) = (0usize, 0);
+
+    for line in wat.lines() {
+        let mut chars = line.chars().peekable();
+        let mut in_string = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                ';' if !in_string && chars.peek() == Some(&';') => break,
+                '(' if !in_string => {
+                    depth += 1;
+                    modules += (depth == 1) as usize;
+                }
+                ')' if !in_string => {
+                    depth = depth
+                        .checked_sub(1)
+                        .unwrap_or_else(|| panic!("{}: unbalanced at {}", name, line));
+                }
+                _ => {}
+            }
+        }
+
+        assert!(!in_string, "{}: unterminated string at {}", name, line);
+    }
+
+    assert_eq!(depth, 0, "{}", name);
+    assert_eq!(modules, 1, "{}", name);
+}
+
+#[test]
+fn samples_translate_to_a_module() {
+    for (name, bytecode) in samples() {
+        let wat = translated(&bytecode);
+
+        assert!(wat.contains("(module"), "{}", name);
+        assert!(
+            wat.contains(r#"(func $run (export "run") (result i32)"#),
+            "{}",
+            name
+        );
+        assert!(wat.contains(r#"(memory (export "memory")"#), "{}", name);
+        assert_balanced(name, &wat);
+        assert_eq!(translated(&bytecode), wat, "{}", name);
+    }
+}
+
+#[test]
+fn data_goes_into_memory() {
+    let wat = translated(&samples()[3].1);
+
+    assert!(wat.contains(r#"(global $data_len (export "data_len") i32 (i32.const 5))"#));
+    assert!(wat.contains(r#""\03\00\00\00\01\00\00\00\04\00\00\00\01\00\00\00\05\00\00\00""#));
+}
+
+/// Where wabt is installed, the translations have to assemble as well.
+#[test]
+fn samples_assemble_with_wat2wasm() {
+    if Command::new("wat2wasm").arg("--version").output().is_err() {
+        eprintln!("no wat2wasm to assemble the translations with");
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("rsvm-to-wat-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (name, bytecode) in samples() {
+        let source = dir.join(format!("{}.wat", name));
+        std::fs::write(&source, translated(&bytecode)).unwrap();
+
+        let assembled = Command::new("wat2wasm")
+            .arg(&source)
+            .arg("-o")
+            .arg(dir.join(format!("{}.wasm", name)))
+            .output()
+            .unwrap();
+
+        assert!(
+            assembled.status.success(),
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(&assembled.stderr)
+        );
+    }
+}
+
+#[test]
+fn bytecode_without_a_header_is_refused() {
+    for bytecode in [&[][..], &[0x1d, 0x1d, 0x1d], &[1, 2, 3, 4, 5, 6]] {
+        let mut wat = Vec::new();
+        let error = translate(bytecode.to_vec(), &mut wat).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(wat.is_empty());
+    }
+}